@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+
+// Frames per analysis block for `Loudest` and `PhaseAware`, about 43 ms at 48 kHz
+const BLOCK_FRAMES: usize = 2048;
+
+/// How interleaved multichannel audio is reduced to the mono signal that gets encoded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Downmix {
+    /// Mean of all channels.
+    #[default]
+    Average,
+    /// A single channel by zero-based index; `Channel(1)` is the second channel.
+    Channel(usize),
+    /// The channel with the highest level in each block, crossfading on a switch.
+    Loudest,
+    /// Mean of all channels after flipping any channel whose polarity opposes the
+    /// loudest one, so out-of-phase microphones do not cancel each other out.
+    PhaseAware,
+}
+
+impl Downmix {
+    /// Checks that the mode can be applied to `channels` channels.
+    pub fn validate(&self, channels: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if channels == 0 {
+            return Err("channel count must be greater than zero".into());
+        }
+        if let Downmix::Channel(index) = *self {
+            if index >= channels {
+                return Err(format!("downmix channel {} does not exist in {}-channel audio", index, channels).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reduces interleaved `samples` with `channels` channels to mono.
+    ///
+    /// A trailing partial frame is dropped. Mono input is returned unchanged.
+    pub fn apply(&self, samples: &[f32], channels: usize) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        self.validate(channels)?;
+        if channels == 1 {
+            return Ok(samples.to_vec());
+        }
+
+        let frames = samples.len() / channels;
+        let samples = &samples[..frames * channels];
+
+        Ok(match *self {
+            Downmix::Average => samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect(),
+            Downmix::Channel(index) => samples.iter().skip(index).step_by(channels).copied().collect(),
+            Downmix::Loudest => loudest(samples, channels),
+            Downmix::PhaseAware => phase_aware(samples, channels),
+        })
+    }
+}
+
+fn loudest(samples: &[f32], channels: usize) -> Vec<f32> {
+    let mut output = Vec::with_capacity(samples.len() / channels);
+    let mut previous: Option<usize> = None;
+
+    for block in samples.chunks(BLOCK_FRAMES * channels) {
+        let energy = channel_energy(block, channels);
+        let current = (0..channels)
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap_or(0);
+        let from = previous.unwrap_or(current);
+
+        let frames = block.len() / channels;
+        for (i, frame) in block.chunks_exact(channels).enumerate() {
+            let t = if from == current { 1.0 } else { (i + 1) as f32 / frames as f32 };
+            output.push(frame[from] * (1.0 - t) + frame[current] * t);
+        }
+        previous = Some(current);
+    }
+
+    output
+}
+
+fn phase_aware(samples: &[f32], channels: usize) -> Vec<f32> {
+    let mut output = Vec::with_capacity(samples.len() / channels);
+    let mut previous_signs: Option<Vec<f32>> = None;
+
+    for block in samples.chunks(BLOCK_FRAMES * channels) {
+        let energy = channel_energy(block, channels);
+        let reference = (0..channels)
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap_or(0);
+
+        // Polarity of each channel relative to the loudest one
+        let signs: Vec<f32> = (0..channels)
+            .map(|channel| {
+                let correlation: f32 = block
+                    .chunks_exact(channels)
+                    .map(|frame| frame[channel] * frame[reference])
+                    .sum();
+                if correlation < 0.0 { -1.0 } else { 1.0 }
+            })
+            .collect();
+
+        // Ramp any polarity change across the block instead of switching abruptly
+        let from = previous_signs.take().unwrap_or_else(|| signs.clone());
+        let frames = block.len() / channels;
+        for (i, frame) in block.chunks_exact(channels).enumerate() {
+            let t = (i + 1) as f32 / frames as f32;
+            let sum: f32 = frame
+                .iter()
+                .enumerate()
+                .map(|(channel, &sample)| {
+                    sample * (from[channel] * (1.0 - t) + signs[channel] * t)
+                })
+                .sum();
+            output.push(sum / channels as f32);
+        }
+        previous_signs = Some(signs);
+    }
+
+    output
+}
+
+fn channel_energy(block: &[f32], channels: usize) -> Vec<f32> {
+    let mut energy = vec![0.0f32; channels];
+    for frame in block.chunks_exact(channels) {
+        for (total, &sample) in energy.iter_mut().zip(frame) {
+            *total += sample * sample;
+        }
+    }
+    energy
+}
@@ -2,8 +2,11 @@ use rustfft::num_complex::Complex;
 use rustfft::FftPlanner;
 use rustfft::num_traits::Zero;
 use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use crate::downmix::Downmix;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct AudioProcessor {
     pub sample_rate: f32,
     pub threshold_db: f32,
@@ -26,6 +29,8 @@ pub struct AudioProcessor {
     pub amplitude_gate_enabled: bool,
     pub gain_boost_enabled: bool,
     pub limiter_enabled: bool,
+    pub downmix: Downmix,
+    pub downmix_enabled: bool,
 }
 //AudioProcessor Default 
 impl AudioProcessor {
@@ -52,6 +57,8 @@ impl AudioProcessor {
             amplitude_gate_enabled: true,
             gain_boost_enabled: false,
             limiter_enabled: true,
+            downmix: Downmix::Average,
+            downmix_enabled: false,
         }
     }
 
@@ -67,6 +74,14 @@ impl AudioProcessor {
         } else {
             reader.samples::<i16>().map(|s| s.unwrap() as f32 / 32768.0).collect()
         };
+
+        // Optionally reduce to mono before processing
+        let channels = if self.downmix_enabled && spec.channels > 1 {
+            samples = self.downmix.apply(&samples, spec.channels as usize)?;
+            1
+        } else {
+            spec.channels
+        };
         
         // Apply RMS normalization if enabled (before other processing)
         if self.rms_enabled {
@@ -95,7 +110,7 @@ impl AudioProcessor {
         
         // Write output file - use the SAME spec as input
         let spec = hound::WavSpec {
-            channels: spec.channels,           // Keep original channel count
+            channels,                          // Original channel count unless downmixed
             sample_rate: spec.sample_rate,     // Keep original sample rate
             bits_per_sample: spec.bits_per_sample,  // Keep original bit depth
             sample_format: spec.sample_format, // Keep original format
@@ -1,8 +1,12 @@
 mod record;
 mod playback;
 mod dsp;
+mod downmix;
 mod opus_encoder;
+mod opus_packet;
 mod opus_playback;
+mod preset;
+mod resample;
 
 use eframe::egui;
 use record::record_audio;
@@ -12,8 +16,9 @@ use std::sync::Arc;
 use std::thread;
 use std::sync::Mutex;
 use crate::dsp::AudioProcessor;
-use opus_encoder::{OpusEncoder, OpusEncodingMode};
+use opus_encoder::{OpusApplication, OpusBandwidth, OpusEncoder, OpusEncodingMode, OpusFrameDuration, OpusSignal};
 use opus_playback::playback_opus;
+use preset::{Preset, BUILTIN_PRESETS};
 
 const RAW_BASELINE_FILE: &str = "raw_baseline.wav";
 
@@ -52,11 +57,12 @@ struct AudioApp {
     processor: AudioProcessor,
     opus_encoder: OpusEncoder,
     opus_encoding_mode: OpusEncodingMode,
-    opus_vbr_quality: i32,
+    opus_complexity: i32,
     processing_thread: Option<thread::JoinHandle<()>>,
     is_processing: Arc<AtomicBool>,
     should_cleanup_processing: bool,
     selected_sample_rate: u32,
+    selected_preset: String,
 }
 
 impl Default for AudioApp {
@@ -86,15 +92,32 @@ impl Default for AudioApp {
             processor: AudioProcessor::new(44100.0),
             opus_encoder: OpusEncoder::new(),
             opus_encoding_mode: OpusEncodingMode::Cbr,
-            opus_vbr_quality: 5,
+            opus_complexity: 5,
             processing_thread: None,
             is_processing: Arc::new(AtomicBool::new(false)),
             should_cleanup_processing: false,
             selected_sample_rate: 48000,
+            selected_preset: String::new(),
         }
     }
 }
 
+impl AudioApp {
+    fn apply_preset(&mut self, preset: &Preset) {
+        self.processor = preset.processor.clone();
+        self.opus_encoder = preset.opus.clone();
+        
+        // Keep the radio buttons and sliders in step with the encoder
+        self.opus_encoding_mode = self.opus_encoder.get_mode();
+        self.opus_complexity = self.opus_encoder.get_complexity();
+        self.selected_sample_rate = self.opus_encoder.get_sample_rate();
+        self.selected_preset = preset.name.clone();
+        
+        let mut info = self.audio_info.lock().unwrap();
+        info.last_message = format!("Loaded preset {}", preset.name);
+    }
+}
+
 impl eframe::App for AudioApp {
     fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
         let ctx = ui.ctx().clone();
@@ -111,6 +134,63 @@ impl eframe::App for AudioApp {
                         ui.heading("Audio Processor");
                         ui.add_space(20.0);
                         
+                        // Add presets section
+                        ui.group(|ui| {
+                            ui.set_width(panel_width);
+                            ui.heading("Presets");
+                            
+                            ui.horizontal(|ui| {
+                                let mut chosen = None;
+                                egui::ComboBox::from_id_salt("builtin_presets")
+                                    .selected_text(if self.selected_preset.is_empty() { "Built-in..." } else { self.selected_preset.as_str() })
+                                    .show_ui(ui, |ui| {
+                                        for name in BUILTIN_PRESETS {
+                                            if ui.selectable_label(self.selected_preset == *name, *name).clicked() {
+                                                chosen = Preset::builtin(name);
+                                            }
+                                        }
+                                    });
+                                if let Some(preset) = chosen {
+                                    self.apply_preset(&preset);
+                                }
+                                
+                                if ui.button("Load...").clicked() {
+                                    if let Some(path) = rfd::FileDialog::new()
+                                        .add_filter("Preset", &["toml", "json"])
+                                        .set_title("Load preset")
+                                        .pick_file()
+                                    {
+                                        match Preset::load(&path) {
+                                            Ok(preset) => self.apply_preset(&preset),
+                                            Err(e) => {
+                                                let mut info = self.audio_info.lock().unwrap();
+                                                info.last_message = format!("Error loading preset: {}", e);
+                                            }
+                                        }
+                                    }
+                                }
+                                
+                                if ui.button("Save...").clicked() {
+                                    if let Some(path) = rfd::FileDialog::new()
+                                        .add_filter("Preset", &["toml", "json"])
+                                        .set_title("Save preset")
+                                        .set_file_name("preset.toml")
+                                        .save_file()
+                                    {
+                                        let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+                                        let preset = Preset::from_settings(name, &self.processor, &self.opus_encoder);
+                                        let message = match preset.save(&path) {
+                                            Ok(()) => format!("Saved preset to {}", path.display()),
+                                            Err(e) => format!("Error saving preset: {}", e),
+                                        };
+                                        self.audio_info.lock().unwrap().last_message = message;
+                                    }
+                                }
+                            });
+                        });
+                        
+                        ui.add_space(10.0);
+                        
                         // Add effect toggles section
                         ui.group(|ui| {
                             ui.set_width(panel_width);
@@ -353,6 +433,41 @@ impl eframe::App for AudioApp {
                                 });
                             });
 
+                            // Bandwidth limit; lowers the encoding rate when below the sample rate
+                            ui.horizontal(|ui| {
+                                ui.label("Max Bandwidth:");
+                                let mut bandwidth = self.opus_encoder.get_max_bandwidth();
+                                egui::ComboBox::from_id_salt("max_bandwidth")
+                                    .selected_text(format!("{:?}", bandwidth))
+                                    .show_ui(ui, |ui| {
+                                        for option in [
+                                            OpusBandwidth::Narrowband,
+                                            OpusBandwidth::Mediumband,
+                                            OpusBandwidth::Wideband,
+                                            OpusBandwidth::Superwideband,
+                                            OpusBandwidth::Fullband,
+                                        ] {
+                                            let label = format!("{:?} ({} kHz)", option, option.cutoff_hz() / 1000);
+                                            ui.selectable_value(&mut bandwidth, option, label);
+                                        }
+                                    });
+                                self.opus_encoder.set_max_bandwidth(bandwidth);
+                            });
+
+                            // Longer packets save per-packet overhead at low bitrates
+                            ui.horizontal(|ui| {
+                                ui.label("Packet Duration:");
+                                let mut duration = self.opus_encoder.get_frame_duration();
+                                egui::ComboBox::from_id_salt("frame_duration")
+                                    .selected_text(format!("{} ms", duration.milliseconds()))
+                                    .show_ui(ui, |ui| {
+                                        for option in OpusFrameDuration::ALL {
+                                            ui.selectable_value(&mut duration, option, format!("{} ms", option.milliseconds()));
+                                        }
+                                    });
+                                self.opus_encoder.set_frame_duration(duration);
+                            });
+
                             // Add bitrate options with three choices
                             ui.horizontal(|ui| {
                                 ui.label("Mode:");
@@ -362,6 +477,49 @@ impl eframe::App for AudioApp {
                                 if ui.radio_value(&mut self.opus_encoding_mode, OpusEncodingMode::Vbr, "VBR (quality target)").clicked() {
                                     self.opus_encoder.set_mode(self.opus_encoding_mode);
                                 }
+                                if ui.radio_value(&mut self.opus_encoding_mode, OpusEncodingMode::ConstrainedVbr, "Constrained VBR").clicked() {
+                                    self.opus_encoder.set_mode(self.opus_encoding_mode);
+                                }
+                            });
+
+                            // Application and content hint steer the codec between SILK and CELT
+                            ui.horizontal(|ui| {
+                                ui.label("Application:");
+                                let mut application = self.opus_encoder.get_application();
+                                egui::ComboBox::from_id_salt("opus_application")
+                                    .selected_text(format!("{:?}", application))
+                                    .show_ui(ui, |ui| {
+                                        for option in [OpusApplication::Voip, OpusApplication::Audio, OpusApplication::RestrictedLowDelay] {
+                                            ui.selectable_value(&mut application, option, format!("{:?}", option));
+                                        }
+                                    });
+                                self.opus_encoder.set_application(application);
+
+                                ui.label("Signal:");
+                                let mut signal = self.opus_encoder.get_signal();
+                                egui::ComboBox::from_id_salt("opus_signal")
+                                    .selected_text(format!("{:?}", signal))
+                                    .show_ui(ui, |ui| {
+                                        for option in [OpusSignal::Auto, OpusSignal::Voice, OpusSignal::Music] {
+                                            ui.selectable_value(&mut signal, option, format!("{:?}", option));
+                                        }
+                                    });
+                                self.opus_encoder.set_signal(signal);
+                            });
+
+                            // Loss resilience and silence suppression
+                            ui.horizontal(|ui| {
+                                let mut fec = self.opus_encoder.get_inband_fec();
+                                ui.checkbox(&mut fec, "In-band FEC");
+                                self.opus_encoder.set_inband_fec(fec);
+
+                                let mut loss = self.opus_encoder.get_packet_loss_perc();
+                                ui.add(egui::Slider::new(&mut loss, 0..=100).suffix("%").text("expected loss"));
+                                self.opus_encoder.set_packet_loss_perc(loss);
+
+                                let mut dtx = self.opus_encoder.get_dtx();
+                                ui.checkbox(&mut dtx, "DTX");
+                                self.opus_encoder.set_dtx(dtx);
                             });
 
                             self.opus_encoder.set_mode(self.opus_encoding_mode);
@@ -390,19 +548,19 @@ impl eframe::App for AudioApp {
                                         self.opus_encoder.set_bitrate(6000);
                                     }
                                 }
-                                OpusEncodingMode::Vbr => {
+                                OpusEncodingMode::Vbr | OpusEncodingMode::ConstrainedVbr => {
                                     ui.horizontal(|ui| {
-                                        ui.label("Quality:");
-                                        let slider = egui::Slider::new(&mut self.opus_vbr_quality, 0..=10)
+                                        ui.label("Complexity:");
+                                        let slider = egui::Slider::new(&mut self.opus_complexity, 0..=10)
                                             .integer()
                                             .text("target");
                                         if ui.add(slider).changed() {
-                                            self.opus_encoder.set_vbr_quality(self.opus_vbr_quality);
+                                            self.opus_encoder.set_complexity(self.opus_complexity);
                                         }
                                     });
 
-                                    self.opus_encoder.set_vbr_quality(self.opus_vbr_quality);
-                                    ui.label(format!("VBR quality target: {} / 10", self.opus_encoder.get_vbr_quality()));
+                                    self.opus_encoder.set_complexity(self.opus_complexity);
+                                    ui.label(format!("Encoder complexity: {} / 10", self.opus_encoder.get_complexity()));
                                 }
                             }
 
@@ -412,8 +570,15 @@ impl eframe::App for AudioApp {
                                 }
                                 OpusEncodingMode::Vbr => {
                                     ui.label(format!(
-                                        "Current mode: VBR quality {} with {} kbps target",
-                                        self.opus_encoder.get_vbr_quality(),
+                                        "Current mode: VBR complexity {} with {} kbps target",
+                                        self.opus_encoder.get_complexity(),
+                                        self.opus_encoder.get_bitrate() / 1000
+                                    ));
+                                }
+                                OpusEncodingMode::ConstrainedVbr => {
+                                    ui.label(format!(
+                                        "Current mode: constrained VBR complexity {} capped at {} kbps",
+                                        self.opus_encoder.get_complexity(),
                                         self.opus_encoder.get_bitrate() / 1000
                                     ));
                                 }
@@ -2,28 +2,204 @@ use ogg::{PacketWriter, writing::PacketWriteEndInfo};
 use opus_rs::{Application, OpusEncoder as CodecEncoder};
 use std::fs::File;
 use std::io::BufWriter;
-
-#[derive(Clone, Copy, PartialEq, Eq)]
+use serde::{Deserialize, Serialize};
+use crate::downmix::Downmix;
+use crate::opus_packet::{frame_payloads, is_celt_only, join_frames, packet_samples};
+use crate::resample::{resample, ResamplerQuality};
+
+// Encoder lookahead in 48 kHz samples: 312 for SILK and hybrid frames (as libopus
+// reports), 120 when every frame is CELT-only. opus-rs has no lookahead query.
+const LOOKAHEAD: u64 = 312;
+const CELT_LOOKAHEAD: u64 = 120;
+
+// libopus moves music to CELT-only from this bitrate; VoIP raises it by 8 kbps
+const MUSIC_CELT_BITRATE: i32 = 10000;
+const VOIP_MODE_OFFSET: i32 = 8000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum OpusEncodingMode {
     Cbr,
     Vbr,
+    /// VBR, but no packet is larger than a CBR packet at the same bitrate.
+    ConstrainedVbr,
+}
+
+/// What the encoder is tuned for, as in libopus's `OPUS_APPLICATION_*`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpusApplication {
+    Voip,
+    #[default]
+    Audio,
+    RestrictedLowDelay,
+}
+
+/// Content hint, applied to the coding mode the way libopus's thresholds would.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpusSignal {
+    #[default]
+    Auto,
+    Voice,
+    Music,
+}
+
+/// Duration of the audio in each Opus packet.
+///
+/// Up to 20 ms, every packet holds a single frame; longer durations put two to
+/// six 20 ms frames in one packet. Durations under 10 ms are CELT-only and need
+/// a 48 kHz encoding rate.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
+pub enum OpusFrameDuration {
+    #[serde(rename = "2.5ms")]
+    Ms2_5,
+    #[serde(rename = "5ms")]
+    Ms5,
+    #[serde(rename = "10ms")]
+    Ms10,
+    #[default]
+    #[serde(rename = "20ms")]
+    Ms20,
+    #[serde(rename = "40ms")]
+    Ms40,
+    #[serde(rename = "60ms")]
+    Ms60,
+    #[serde(rename = "80ms")]
+    Ms80,
+    #[serde(rename = "100ms")]
+    Ms100,
+    #[serde(rename = "120ms")]
+    Ms120,
+}
+
+impl OpusFrameDuration {
+    pub const ALL: [OpusFrameDuration; 9] = [
+        OpusFrameDuration::Ms2_5,
+        OpusFrameDuration::Ms5,
+        OpusFrameDuration::Ms10,
+        OpusFrameDuration::Ms20,
+        OpusFrameDuration::Ms40,
+        OpusFrameDuration::Ms60,
+        OpusFrameDuration::Ms80,
+        OpusFrameDuration::Ms100,
+        OpusFrameDuration::Ms120,
+    ];
+
+    /// Packet duration in 48 kHz samples, the unit granule positions count in.
+    pub fn samples_48k(self) -> usize {
+        match self {
+            OpusFrameDuration::Ms2_5 => 120,
+            OpusFrameDuration::Ms5 => 240,
+            OpusFrameDuration::Ms10 => 480,
+            OpusFrameDuration::Ms20 => 960,
+            OpusFrameDuration::Ms40 => 1920,
+            OpusFrameDuration::Ms60 => 2880,
+            OpusFrameDuration::Ms80 => 3840,
+            OpusFrameDuration::Ms100 => 4800,
+            OpusFrameDuration::Ms120 => 5760,
+        }
+    }
+
+    pub fn milliseconds(self) -> f32 {
+        self.samples_48k() as f32 / 48.0
+    }
+
+    // Length of each frame the codec encodes, in 48 kHz samples; longer
+    // packets are made of 20 ms frames
+    fn codec_frame_48k(self) -> usize {
+        self.samples_48k().min(960)
+    }
+}
+
+/// Highest audio bandwidth the encoder may use.
+///
+/// Opus picks the bandwidth from the rate it encodes at, so a limit below the
+/// configured sample rate lowers the encoding rate to match: narrowband encodes
+/// at 8 kHz, mediumband at 12 kHz, wideband at 16 kHz, superwideband at 24 kHz
+/// and fullband at 48 kHz.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpusBandwidth {
+    /// 4 kHz audio bandwidth.
+    Narrowband,
+    /// 6 kHz audio bandwidth.
+    Mediumband,
+    /// 8 kHz audio bandwidth.
+    Wideband,
+    /// 12 kHz audio bandwidth.
+    Superwideband,
+    /// 20 kHz audio bandwidth.
+    #[default]
+    Fullband,
+}
+
+impl OpusBandwidth {
+    /// Highest audio frequency kept at this bandwidth.
+    pub fn cutoff_hz(self) -> u32 {
+        match self {
+            OpusBandwidth::Narrowband => 4000,
+            OpusBandwidth::Mediumband => 6000,
+            OpusBandwidth::Wideband => 8000,
+            OpusBandwidth::Superwideband => 12000,
+            OpusBandwidth::Fullband => 20000,
+        }
+    }
+
+    /// Lowest Opus sample rate that carries this bandwidth.
+    pub fn sample_rate(self) -> u32 {
+        match self {
+            OpusBandwidth::Narrowband => 8000,
+            OpusBandwidth::Mediumband => 12000,
+            OpusBandwidth::Wideband => 16000,
+            OpusBandwidth::Superwideband => 24000,
+            OpusBandwidth::Fullband => 48000,
+        }
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct OpusEncoder {
     bitrate: i32,
-    sample_rate: u32,
     mode: OpusEncodingMode,
-    vbr_quality: i32,
+    application: OpusApplication,
+    signal: OpusSignal,
+    // Presets from before complexity was its own setting call it VBR quality
+    #[serde(alias = "vbr_quality")]
+    complexity: i32,
+    sample_rate: u32,
+    max_bandwidth: OpusBandwidth,
+    frame_duration: OpusFrameDuration,
+    inband_fec: bool,
+    packet_loss_perc: u8,
+    dtx: bool,
+    resampler_quality: ResamplerQuality,
+    downmix: Downmix,
+}
+
+impl Default for OpusEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OpusEncoder {
     pub fn new() -> Self {
         Self {
             bitrate: 12000, // Default 12kbps
-            sample_rate: 48000,
             mode: OpusEncodingMode::Cbr,
-            vbr_quality: 5,
+            application: OpusApplication::Audio,
+            signal: OpusSignal::Auto,
+            complexity: 5,
+            sample_rate: 48000,
+            max_bandwidth: OpusBandwidth::Fullband,
+            frame_duration: OpusFrameDuration::Ms20,
+            inband_fec: false,
+            packet_loss_perc: 0,
+            dtx: false,
+            resampler_quality: ResamplerQuality::default(),
+            downmix: Downmix::default(),
         }
     }
 
@@ -31,6 +207,31 @@ impl OpusEncoder {
         self.sample_rate = sample_rate;
     }
 
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_max_bandwidth(&mut self, bandwidth: OpusBandwidth) {
+        self.max_bandwidth = bandwidth;
+    }
+
+    pub fn get_max_bandwidth(&self) -> OpusBandwidth {
+        self.max_bandwidth
+    }
+
+    pub fn set_frame_duration(&mut self, duration: OpusFrameDuration) {
+        self.frame_duration = duration;
+    }
+
+    pub fn get_frame_duration(&self) -> OpusFrameDuration {
+        self.frame_duration
+    }
+
+    // The sample rate, lowered to what the bandwidth limit needs
+    fn encoding_sample_rate(&self) -> u32 {
+        self.sample_rate.min(self.max_bandwidth.sample_rate())
+    }
+
     pub fn set_bitrate(&mut self, bitrate: i32) {
         self.bitrate = bitrate;
     }
@@ -47,15 +248,55 @@ impl OpusEncoder {
         self.mode
     }
 
-    pub fn set_vbr_quality(&mut self, quality: i32) {
-        self.vbr_quality = quality.clamp(0, 10);
+    pub fn set_application(&mut self, application: OpusApplication) {
+        self.application = application;
+    }
+
+    pub fn get_application(&self) -> OpusApplication {
+        self.application
+    }
+
+    pub fn set_signal(&mut self, signal: OpusSignal) {
+        self.signal = signal;
+    }
+
+    pub fn get_signal(&self) -> OpusSignal {
+        self.signal
+    }
+
+    pub fn set_complexity(&mut self, complexity: i32) {
+        self.complexity = complexity.clamp(0, 10);
+    }
+
+    pub fn get_complexity(&self) -> i32 {
+        self.complexity
+    }
+
+    pub fn set_inband_fec(&mut self, enabled: bool) {
+        self.inband_fec = enabled;
+    }
+
+    pub fn get_inband_fec(&self) -> bool {
+        self.inband_fec
+    }
+
+    pub fn set_packet_loss_perc(&mut self, percent: u8) {
+        self.packet_loss_perc = percent.min(100);
+    }
+
+    pub fn get_packet_loss_perc(&self) -> u8 {
+        self.packet_loss_perc
+    }
+
+    pub fn set_dtx(&mut self, enabled: bool) {
+        self.dtx = enabled;
     }
 
-    pub fn get_vbr_quality(&self) -> i32 {
-        self.vbr_quality
+    pub fn get_dtx(&self) -> bool {
+        self.dtx
     }
 
-    fn validate_settings(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn validate_settings(&self) -> Result<(), Box<dyn std::error::Error>> {
         if !matches!(self.sample_rate, 8000 | 12000 | 16000 | 24000 | 48000) {
             return Err("Invalid sample rate. Must be 8kHz, 12kHz, 16kHz, 24kHz, or 48kHz".into());
         }
@@ -64,24 +305,52 @@ impl OpusEncoder {
             return Err("Invalid bitrate. Must be between 6kbps and 510kbps".into());
         }
 
-        if !(0..=10).contains(&self.vbr_quality) {
-            return Err("Invalid VBR quality. Must be between 0 and 10".into());
+        // opus-rs codes CELT frames at 48 kHz frame sizes only
+        if self.frame_duration < OpusFrameDuration::Ms10 && self.encoding_sample_rate() != 48000 {
+            return Err(format!(
+                "{} ms frames are CELT-only and need a 48 kHz encoding rate",
+                self.frame_duration.milliseconds()
+            ).into());
+        }
+
+        if self.packet_loss_perc > 100 {
+            return Err("Invalid packet loss. Must be between 0 and 100%".into());
+        }
+
+        if !(0..=10).contains(&self.complexity) {
+            return Err("Invalid complexity. Must be between 0 and 10".into());
         }
 
         Ok(())
     }
 
-    fn frame_size(&self) -> usize {
-        match self.sample_rate {
-            8000 => 160,
-            12000 => 240,
-            16000 => 320,
-            24000 => 480,
-            48000 => 960,
-            _ => 160,
+    // The codec application that carries out the application and signal hint
+    fn codec_application(&self) -> Application {
+        let music_threshold = match self.application {
+            OpusApplication::Voip => MUSIC_CELT_BITRATE + VOIP_MODE_OFFSET,
+            _ => MUSIC_CELT_BITRATE,
+        };
+        // CELT has no mediumband mode, so 12 kHz music stays in SILK
+        let celt_music = self.signal == OpusSignal::Music
+            && self.bitrate >= music_threshold
+            && self.encoding_sample_rate() != 12000;
+
+        // Only CELT codes frames shorter than 10 ms
+        let short_frames = self.frame_duration < OpusFrameDuration::Ms10;
+
+        match self.application {
+            OpusApplication::RestrictedLowDelay => Application::RestrictedLowDelay,
+            _ if celt_music || short_frames => Application::RestrictedLowDelay,
+            OpusApplication::Voip => Application::Voip,
+            OpusApplication::Audio => Application::Audio,
         }
     }
 
+    // Samples per codec frame at the encoder sample rate
+    fn frame_size(&self) -> usize {
+        self.frame_duration.codec_frame_48k() * self.encoding_sample_rate() as usize / 48000
+    }
+
     pub fn encode_wav_to_opus(&self, input_path: &str, output_path: &str) -> Result<(), Box<dyn std::error::Error>> {
         self.validate_settings()?;
         
@@ -94,51 +363,91 @@ impl OpusEncoder {
             reader.samples::<i16>().map(|s| s.unwrap() as f32 / 32768.0).collect()
         };
         
-        let mono_samples: Vec<f32> = if spec.channels == 2 {
-            samples.chunks(2)
-                .map(|chunk| chunk[0])
-                .collect()
-        } else {
-            samples
-        };
+        let mono_samples = self.downmix.apply(&samples, spec.channels as usize)?;
         
-        let resampled_samples = if spec.sample_rate != self.sample_rate {
-            let input_duration = mono_samples.len() as f32 / spec.sample_rate as f32;
-            let output_len = (input_duration * self.sample_rate as f32) as usize;
-            let scale = (mono_samples.len() - 1) as f32 / (output_len - 1) as f32;
-
-            let mut output = Vec::with_capacity(output_len);
-            for i in 0..output_len {
-                let pos = i as f32 * scale;
-                let index = pos as usize;
-                let frac = pos - index as f32;
-
-                let sample = if index + 1 < mono_samples.len() {
-                    mono_samples[index] * (1.0 - frac) + mono_samples[index + 1] * frac
-                } else {
-                    mono_samples[index]
-                };
-
-                output.push(sample);
-            }
-            output
-        } else {
-            mono_samples
-        };
+        let encoding_rate = self.encoding_sample_rate();
+        let resampled_samples = resample(&mono_samples, spec.sample_rate, encoding_rate, self.resampler_quality);
 
         let frame_size = self.frame_size();
-        let mut encoder = CodecEncoder::new(self.sample_rate as i32, 1, Application::Audio)
+        let mut encoder = CodecEncoder::new(encoding_rate as i32, 1, self.codec_application())
             .map_err(std::io::Error::other)?;
         encoder.bitrate_bps = self.bitrate;
         encoder.use_cbr = matches!(self.mode, OpusEncodingMode::Cbr);
-        encoder.complexity = self.vbr_quality;
+        encoder.complexity = self.complexity;
+        encoder.use_inband_fec = self.inband_fec;
+        encoder.packet_loss_perc = self.packet_loss_perc as i32;
         
         println!("Converting to Opus:");
         println!("  Mode: {:?}", self.mode_name());
+        println!("  Application: {:?}, signal: {:?}", self.application, self.signal);
         println!("  Bitrate target: {} bps", self.bitrate);
-        println!("  VBR quality: {}", self.vbr_quality);
-        println!("  Frame size: {} samples (20ms)", frame_size);
-        println!("  Total frames: {}", resampled_samples.len() / frame_size);
+        println!("  Complexity: {}", self.complexity);
+        println!("  FEC: {}, expected loss: {}%, DTX: {}", self.inband_fec, self.packet_loss_perc, self.dtx);
+        println!("  Frame size: {} samples ({} ms at {} Hz), {} ms packets",
+                 frame_size, self.frame_duration.codec_frame_48k() as f32 / 48.0, encoding_rate,
+                 self.frame_duration.milliseconds());
+        // Encode past the end of the input by the lookahead so its last samples come out
+        let granule_scale = (48000 / encoding_rate) as u64;
+        let lookahead = (LOOKAHEAD as usize).div_ceil(granule_scale as usize);
+        let frames = (resampled_samples.len() + lookahead).div_ceil(frame_size);
+        println!("  Total frames: {}", frames);
+
+        let mut input_buffer = vec![0.0f32; frame_size];
+        // Constrained VBR caps every packet at the CBR packet size
+        let max_packet = match self.mode {
+            OpusEncodingMode::ConstrainedVbr => {
+                (self.bitrate as usize * frame_size / encoding_rate as usize).div_ceil(8).clamp(2, 1275)
+            }
+            _ => 1275,
+        };
+        let mut encoded_data = vec![0u8; max_packet];
+        // Each frame as a single frame packet, joined into longer packets below
+        let mut packets = Vec::with_capacity(frames);
+        let mut dtx = Dtx::new(self.frame_duration.codec_frame_48k());
+        // Full packet of the last frame DTX left out
+        let mut skipped = None;
+
+        for frame in 0..frames {
+            let start = (frame * frame_size).min(resampled_samples.len());
+            let end = (start + frame_size).min(resampled_samples.len());
+            input_buffer.clear();
+            input_buffer.extend_from_slice(&resampled_samples[start..end]);
+            input_buffer.resize(frame_size, 0.0);
+
+            let encoded_len = encoder
+                .encode(&input_buffer, frame_size, &mut encoded_data)
+                .map_err(std::io::Error::other)?;
+            let packet = encoded_data[..encoded_len].to_vec();
+            // The frame is still encoded in DTX, so the codec state follows the signal
+            if self.dtx && dtx.skip(&input_buffer) {
+                // Code 0 with an empty frame, in the same configuration
+                packets.push(vec![packet[0] & !0x03]);
+                skipped = Some(packet);
+            } else {
+                // Once the signal returns, the frame before it is sent in full, so
+                // the decoder has the history the next frame is coded against
+                let resumed = skipped.take().filter(|_| !dtx.in_silence());
+                if let (Some(full), Some(last)) = (resumed, packets.last_mut()) {
+                    *last = full;
+                }
+                packets.push(packet);
+            }
+        }
+
+        // Decoders skip the delay at the start and trim the stream to the input
+        // length using the granule position of the last page
+        let pre_skip = if packets.iter().all(|packet| is_celt_only(packet)) {
+            CELT_LOOKAHEAD
+        } else {
+            LOOKAHEAD
+        };
+        let end_granule = pre_skip + resampled_samples.len() as u64 * granule_scale;
+        // Padding frames that hold nothing before the end granule are not needed
+        let codec_frame_48k = self.frame_duration.codec_frame_48k() as u64;
+        while packets.len() > 1 && (packets.len() as u64 - 1) * codec_frame_48k >= end_granule {
+            packets.pop();
+        }
+        let packets = self.join_packets(packets);
 
         let file = BufWriter::new(File::create(output_path)?);
         let serial = std::time::SystemTime::now()
@@ -152,8 +461,8 @@ impl OpusEncoder {
         id_header.extend_from_slice(b"OpusHead");
         id_header.push(1);  // Version
         id_header.push(1);  // Channel count
-        id_header.extend_from_slice(&(0u16).to_le_bytes());  // Pre-skip
-        id_header.extend_from_slice(&(self.sample_rate).to_le_bytes());  // Input sample rate
+        id_header.extend_from_slice(&(pre_skip as u16).to_le_bytes());  // Pre-skip
+        id_header.extend_from_slice(&encoding_rate.to_le_bytes());  // Input sample rate
         id_header.extend_from_slice(&[0, 0]);  // Output gain
         id_header.push(0);  // Channel mapping family
 
@@ -179,49 +488,118 @@ impl OpusEncoder {
             0
         )?;
 
-        let mut input_buffer = vec![0.0f32; frame_size];
-        let mut encoded_data = vec![0u8; 1275];
-        let mut granulepos = 0i64;
-
-        for chunk in resampled_samples.chunks(frame_size) {
-            input_buffer.clear();
-            input_buffer.extend(chunk);
-            if input_buffer.len() < frame_size {
-                input_buffer.resize(frame_size, 0.0);
-            }
+        let last = packets.len() - 1;
+        let mut granule = 0u64;
+        for (index, packet) in packets.into_iter().enumerate() {
+            // Granule positions always count 48kHz samples, whatever the encoding rate
+            granule += packet_samples(&packet).unwrap_or(0) as u64;
+            let (end_info, granulepos) = if index == last {
+                (PacketWriteEndInfo::EndStream, end_granule)
+            } else {
+                (PacketWriteEndInfo::NormalPacket, granule)
+            };
+            packet_writer.write_packet(packet, serial, end_info, granulepos)?;
+        }
 
-            let encoded_len = encoder
-                .encode(&input_buffer, frame_size, &mut encoded_data)
-                .map_err(std::io::Error::other)?;
-            let encoded_packet = &encoded_data[..encoded_len];
+        let final_duration = (end_granule - pre_skip) as f32 / 48000.0;
+        println!("Final Opus duration: {} seconds", final_duration);
 
-            granulepos += frame_size as i64;
+        Ok(())
+    }
 
-            packet_writer.write_packet(
-                encoded_packet.to_vec(),
-                serial,
-                PacketWriteEndInfo::NormalPacket,
-                granulepos as u64
-            )?;
+    // Groups single frame packets into packets of the configured duration.
+    // Frames only share a packet when they share a TOC configuration, so a
+    // change of coding mode or bandwidth starts a new, shorter packet.
+    fn join_packets(&self, frame_packets: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let per_packet = self.frame_duration.samples_48k() / self.frame_duration.codec_frame_48k();
+        if per_packet == 1 {
+            return frame_packets;
         }
 
-        packet_writer.write_packet(
-            Vec::<u8>::new(),
-            serial,
-            PacketWriteEndInfo::EndStream,
-            granulepos as u64
-        )?;
-
-        let final_duration = granulepos as f32 / self.sample_rate as f32;
-        println!("Final Opus duration: {} seconds", final_duration);
+        let mut groups: Vec<Vec<Vec<u8>>> = Vec::new();
+        for packet in frame_packets {
+            match groups.last_mut() {
+                Some(group) if group.len() < per_packet && group[0][0] >> 2 == packet[0] >> 2 => group.push(packet),
+                _ => groups.push(vec![packet]),
+            }
+        }
 
-        Ok(())
+        let mut packets: Vec<Vec<u8>> = groups
+            .iter()
+            .map(|group| {
+                let payloads: Vec<&[u8]> = group
+                    .iter()
+                    .map(|packet| frame_payloads(packet).and_then(|frames| frames.first().copied()).unwrap_or(&[]))
+                    .collect();
+                join_frames(group[0][0], &payloads, None)
+            })
+            .collect();
+
+        // Joining drops the padding of CBR frames, so CBR pads every packet
+        // of whole frames back up to one size
+        if self.mode == OpusEncodingMode::Cbr {
+            let full = |packet: &Vec<u8>| frame_payloads(packet).is_some_and(|frames| frames.iter().all(|f| !f.is_empty()));
+            let size = packets.iter().filter(|packet| full(packet)).map(Vec::len).max().unwrap_or(0);
+            for (packet, group) in packets.iter_mut().zip(&groups) {
+                if full(packet) && packet.len() < size {
+                    let payloads = frame_payloads(packet).unwrap_or_default();
+                    *packet = join_frames(group[0][0], &payloads, Some(size));
+                }
+            }
+        }
+        packets
     }
 
     fn mode_name(&self) -> &'static str {
         match self.mode {
             OpusEncodingMode::Cbr => "CBR",
             OpusEncodingMode::Vbr => "VBR",
+            OpusEncodingMode::ConstrainedVbr => "Constrained VBR",
         }
     }
-}
\ No newline at end of file
+}
+
+// Silence below which DTX may stop sending frames, in dBFS
+const DTX_SILENCE_DB: f32 = -60.0;
+// Silence that must pass before DTX starts, and how often a full frame is still sent
+const DTX_HANGOVER_MS: usize = 200;
+const DTX_REFRESH_MS: usize = 400;
+
+// Decides which frames discontinuous transmission leaves out, counting in 48 kHz samples
+struct Dtx {
+    frame: usize,
+    silent: usize,
+    skipped: usize,
+}
+
+impl Dtx {
+    fn new(frame_48k: usize) -> Self {
+        Self { frame: frame_48k, silent: 0, skipped: 0 }
+    }
+
+    fn skip(&mut self, frame: &[f32]) -> bool {
+        let power = frame.iter().map(|s| s * s).sum::<f32>() / frame.len().max(1) as f32;
+        let silent = 10.0 * power.max(1e-12).log10() < DTX_SILENCE_DB;
+        if !silent {
+            self.silent = 0;
+            self.skipped = 0;
+            return false;
+        }
+
+        self.silent += self.frame;
+        if self.silent <= DTX_HANGOVER_MS * 48 {
+            return false;
+        }
+        if self.skipped + self.frame > DTX_REFRESH_MS * 48 {
+            self.skipped = 0;
+            return false;
+        }
+        self.skipped += self.frame;
+        true
+    }
+
+    // Whether the last frame was silent, whether or not it was skipped
+    fn in_silence(&self) -> bool {
+        self.silent > 0
+    }
+}
@@ -5,26 +5,47 @@ use std::io::BufReader;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crate::opus_packet::{frame_payloads, packet_frames};
 
 pub struct OpusFileInfo {
     sample_rate: u32,
+    // Encoder delay and final granule position, in 48 kHz samples
+    pre_skip: u64,
+    end_granule: u64,
 }
 
 impl OpusFileInfo {
     pub fn get_sample_rate(&self) -> u32 {
         self.sample_rate
     }
+
+    // Range of decoded samples at the file's rate that hold the input audio
+    fn playable_samples(&self) -> (u64, u64) {
+        let scale = |granule: u64| granule * self.sample_rate as u64 / 48000;
+        (scale(self.pre_skip), scale(self.end_granule))
+    }
 }
 
-fn frame_size_for(sample_rate: u32) -> usize {
-    match sample_rate {
-        8000 => 160,
-        12000 => 240,
-        16000 => 320,
-        24000 => 480,
-        48000 => 960,
-        _ => 160,
+// Decodes each frame of a packet on its own at `sample_rate`, so packets of
+// several frames play too; frames without data (DTX) are silence
+fn decode_packet(decoder: &mut OpusDecoder, packet: &[u8], sample_rate: u32, output: &mut Vec<f32>) -> Result<usize, Box<dyn std::error::Error>> {
+    let (frame_samples_48k, _) = packet_frames(packet).ok_or("Invalid Opus packet")?;
+    let payloads = frame_payloads(packet).ok_or("Invalid Opus packet")?;
+    let frame_samples = frame_samples_48k * sample_rate as usize / 48000;
+
+    output.clear();
+    output.resize(frame_samples * payloads.len(), 0.0);
+    let toc = packet[0] & !0x03;
+    for (payload, frame) in payloads.iter().zip(output.chunks_mut(frame_samples)) {
+        if payload.is_empty() {
+            continue;
+        }
+        let mut single = Vec::with_capacity(payload.len() + 1);
+        single.push(toc);
+        single.extend_from_slice(payload);
+        decoder.decode(&single, frame_samples, frame)?;
     }
+    Ok(output.len())
 }
 
 pub fn get_opus_info(file_path: &str) -> Result<(u64, f64, OpusFileInfo), Box<dyn std::error::Error>> {
@@ -44,19 +65,23 @@ pub fn get_opus_info(file_path: &str) -> Result<(u64, f64, OpusFileInfo), Box<dy
         head_data[12], head_data[13], head_data[14], head_data[15]
     ]);
     
+    let pre_skip = u16::from_le_bytes([head_data[10], head_data[11]]) as u64;
+    
     // Skip OpusTags
     packet_reader.read_packet()?;
     
-    let mut packet_count = 0;
-    while let Ok(Some(_)) = packet_reader.read_packet() {
-        packet_count += 1;
+    // The last page's granule position marks the end of the input audio
+    let mut end_granule = 0;
+    while let Ok(Some(packet)) = packet_reader.read_packet() {
+        end_granule = packet.absgp_page();
     }
     
-    let frame_size = frame_size_for(sample_rate);
-    let duration = (packet_count as f64 * frame_size as f64) / sample_rate as f64;
+    let duration = end_granule.saturating_sub(pre_skip) as f64 / 48000.0;
     
     Ok((file_size, duration, OpusFileInfo {
         sample_rate,
+        pre_skip,
+        end_granule,
     }))
 }
 
@@ -64,12 +89,11 @@ pub fn playback_opus(file_path: &str, is_playing_flag: Arc<AtomicBool>) -> Resul
     // Get file info first
     let (_, _, file_info) = get_opus_info(file_path)?;
     let sample_rate = file_info.get_sample_rate();
+    let (skip_until, play_until) = file_info.playable_samples();
     
     let decoder = OpusDecoder::new(sample_rate as i32, 1)
         .map_err(std::io::Error::other)?;
 
-    let frame_size = frame_size_for(sample_rate);
-
     // Open Opus file
     let file = BufReader::new(File::open(file_path)?);
     let packet_reader = PacketReader::new(file);
@@ -98,10 +122,11 @@ pub fn playback_opus(file_path: &str, is_playing_flag: Arc<AtomicBool>) -> Resul
             let packet_reader = Arc::new(std::sync::Mutex::new(packet_reader));
             let is_playing = Arc::clone(&is_playing_flag);
             
-            // Calculate buffer size based on frame size
-            let decoded_buffer = Arc::new(std::sync::Mutex::new(vec![0f32; frame_size]));
+            // Sized by each packet as it is decoded
+            let decoded_buffer = Arc::new(std::sync::Mutex::new(Vec::new()));
             let decoded_samples = Arc::new(std::sync::Mutex::new(0));
             let buffer_position = Arc::new(std::sync::Mutex::new(0));
+            let mut stream_position = 0u64; // Samples decoded so far
 
             device.build_output_stream(
                 &output_config,
@@ -124,8 +149,14 @@ pub fn playback_opus(file_path: &str, is_playing_flag: Arc<AtomicBool>) -> Resul
                                     if let Ok(Some(packet)) = reader.read_packet() {
                                         if let Ok(mut decoder) = decoder.lock() {
                                             if let Ok(mut buffer) = decoded_buffer.lock() {
-                                                if let Ok(n_samples) = decoder.decode(&packet.data, frame_size, &mut buffer) {
-                                                    *decoded_samples.lock().unwrap() = n_samples;
+                                                if let Ok(n_samples) = decode_packet(&mut decoder, &packet.data, sample_rate, &mut buffer) {
+                                                    // Skip the encoder delay and stop at the end of the input
+                                                    let clamp = |limit: u64| limit.saturating_sub(stream_position).min(n_samples as u64) as usize;
+                                                    let from = clamp(skip_until);
+                                                    let to = clamp(play_until).max(from);
+                                                    stream_position += n_samples as u64;
+                                                    *buffer_position.lock().unwrap() = from;
+                                                    *decoded_samples.lock().unwrap() = to;
                                                 } else {
                                                     is_playing.store(false, Ordering::Relaxed);
                                                     break;
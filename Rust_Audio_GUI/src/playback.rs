@@ -4,30 +4,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::error::Error;
-
-// Function to resample audio
-fn resample_audio(input_samples: &[f32], input_rate: f32, output_rate: f32) -> Vec<f32> {
-    let input_duration = input_samples.len() as f32 / input_rate;
-    let output_len = (input_duration * output_rate) as usize;
-    let scale = (input_samples.len() - 1) as f32 / (output_len - 1).max(1) as f32;
-    
-    let mut output = Vec::with_capacity(output_len);
-    for i in 0..output_len {
-        let pos = i as f32 * scale;
-        let index = pos.floor() as usize;
-        let frac = pos - index as f32;
-        
-        let sample = if index + 1 < input_samples.len() {
-            input_samples[index] * (1.0 - frac) + input_samples[index + 1] * frac
-        } else {
-            input_samples[index.min(input_samples.len() - 1)]
-        };
-        
-        output.push(sample);
-    }
-    
-    output
-}
+use crate::resample::{resample, ResamplerQuality};
 
 pub fn playback_audio(file_path: &str, is_playing_flag: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
     let mut reader = hound::WavReader::open(file_path)?;
@@ -72,7 +49,7 @@ pub fn playback_audio(file_path: &str, is_playing_flag: Arc<AtomicBool>) -> Resu
     if stream_result.is_err() {
         println!("WAV sample rate {} not supported by device, resampling to {}", 
                  spec.sample_rate, default_sample_rate);
-        samples = resample_audio(&samples, spec.sample_rate as f32, default_sample_rate as f32);
+        samples = resample(&samples, spec.sample_rate, default_sample_rate, ResamplerQuality::default());
         using_original_rate = false;
     } else {
         println!("Using original sample rate: {}", spec.sample_rate);
@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use crate::dsp::AudioProcessor;
+use crate::opus_encoder::{OpusApplication, OpusEncoder, OpusEncodingMode, OpusSignal};
+
+// Same schema as the library's presets, so files move freely between the GUI and the CLI
+pub const PRESET_VERSION: u32 = 1;
+
+pub const BUILTIN_PRESETS: &[&str] = &["voice-message", "high-quality", "low-bandwidth", "unprocessed"];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    pub version: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub processor: AudioProcessor,
+    #[serde(default)]
+    pub opus: OpusEncoder,
+}
+
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: Option<u32>,
+}
+
+impl Preset {
+    pub fn from_settings(name: &str, processor: &AudioProcessor, opus: &OpusEncoder) -> Self {
+        Self {
+            version: PRESET_VERSION,
+            name: name.to_string(),
+            description: String::new(),
+            processor: processor.clone(),
+            opus: opus.clone(),
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        // The GUI's processor defaults are the voice chain
+        let mut processor = AudioProcessor::new(48000.0);
+
+        let mut opus = OpusEncoder::new();
+        opus.set_mode(OpusEncodingMode::Vbr);
+        opus.set_complexity(10);
+        opus.set_application(OpusApplication::Voip);
+        opus.set_signal(OpusSignal::Voice);
+
+        let description = match name {
+            "voice-message" => {
+                // Constrained, so a busy recording cannot overshoot the budget
+                opus.set_mode(OpusEncodingMode::ConstrainedVbr);
+                opus.set_bitrate(10000);
+                "30 s voice message under 45 kB: clean voice chain, 10 kbps constrained VBR"
+            }
+            "high-quality" => {
+                opus.set_bitrate(24000);
+                "Clean voice chain at 24 kbps VBR"
+            }
+            "low-bandwidth" => {
+                processor.lowpass_freq = 4000.0;
+                opus.set_mode(OpusEncodingMode::Cbr);
+                opus.set_bitrate(6000);
+                opus.set_sample_rate(8000);
+                "Narrowband voice at 6 kbps CBR for the smallest files"
+            }
+            "unprocessed" => {
+                processor.threshold_db = 1.0;
+                processor.limiter_threshold_db = -12.0;
+                processor.rms_enabled = false;
+                processor.filters_enabled = false;
+                processor.spectral_gate_enabled = false;
+                processor.amplitude_gate_enabled = false;
+                processor.limiter_enabled = false;
+                opus.set_application(OpusApplication::Audio);
+                opus.set_signal(OpusSignal::Auto);
+                "No DSP, 12 kbps VBR"
+            }
+            _ => return None,
+        };
+
+        let mut preset = Self::from_settings(name, &processor, &opus);
+        preset.description = description.to_string();
+        Some(preset)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let is_json = is_json(path);
+
+        let probe: VersionProbe = if is_json { serde_json::from_str(&text)? } else { toml::from_str(&text)? };
+        match probe.version {
+            None => return Err("Preset is missing `version`".into()),
+            Some(version) if version == 0 || version > PRESET_VERSION => {
+                return Err(format!(
+                    "Preset version {} is not supported (this build reads versions 1 to {})",
+                    version, PRESET_VERSION
+                ).into());
+            }
+            Some(_) => {}
+        }
+
+        let preset: Preset = if is_json { serde_json::from_str(&text)? } else { toml::from_str(&text)? };
+        preset.validate()?;
+        Ok(preset)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        self.validate()?;
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            toml::to_string_pretty(self)?
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let p = &self.processor;
+
+        let ranges = [
+            ("processor.sample_rate", p.sample_rate, 8000.0, 384000.0),
+            ("processor.threshold_db", p.threshold_db, -120.0, 60.0),
+            ("processor.amplitude_threshold_db", p.amplitude_threshold_db, -120.0, 0.0),
+            ("processor.amplitude_attack_ms", p.amplitude_attack_ms, 0.01, 1000.0),
+            ("processor.amplitude_release_ms", p.amplitude_release_ms, 0.01, 5000.0),
+            ("processor.amplitude_lookahead_ms", p.amplitude_lookahead_ms, 0.0, 100.0),
+            ("processor.gain_db", p.gain_db, -60.0, 40.0),
+            ("processor.limiter_threshold_db", p.limiter_threshold_db, -60.0, 0.0),
+            ("processor.limiter_ceiling_db", p.limiter_ceiling_db, -60.0, 0.0),
+            ("processor.limiter_attack_ms", p.limiter_attack_ms, 0.0, 1000.0),
+            ("processor.limiter_release_ms", p.limiter_release_ms, 0.01, 5000.0),
+            ("processor.limiter_lookahead_ms", p.limiter_lookahead_ms, 0.0, 100.0),
+            ("processor.lowpass_freq", p.lowpass_freq, 0.0, 96000.0),
+            ("processor.highpass_freq", p.highpass_freq, 0.0, 96000.0),
+            ("processor.rms_target_db", p.rms_target_db, -60.0, 0.0),
+        ];
+        for (field, value, min, max) in ranges {
+            if !(min..=max).contains(&value) {
+                return Err(format!("{} = {} is outside {}..={}", field, value, min, max).into());
+            }
+        }
+
+        if p.highpass_freq >= p.lowpass_freq {
+            return Err("processor.highpass_freq must be below processor.lowpass_freq".into());
+        }
+
+        self.opus.validate_settings()
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Preset;
+    use crate::opus_encoder::{OpusApplication, OpusEncodingMode, OpusFrameDuration, OpusSignal};
+
+    // A preset as the library and its CLI save it
+    const LIBRARY_PRESET: &str = r#"version = 1
+name = "voice"
+description = ""
+
+[processor]
+sample_rate = 48000.0
+threshold_db = 5.0
+amplitude_threshold_db = -20.0
+amplitude_attack_ms = 10.0
+amplitude_release_ms = 100.0
+amplitude_lookahead_ms = 5.0
+gain_db = 6.0
+limiter_threshold_db = -1.0
+limiter_ceiling_db = -2.0
+limiter_attack_ms = 5.0
+limiter_release_ms = 50.0
+limiter_lookahead_ms = 5.0
+lowpass_freq = 20000.0
+highpass_freq = 75.0
+rms_target_db = -20.0
+rms_enabled = true
+filters_enabled = true
+spectral_gate_enabled = true
+amplitude_gate_enabled = true
+gain_boost_enabled = false
+limiter_enabled = true
+downmix = "average"
+downmix_enabled = false
+
+[opus]
+bitrate = 12000
+mode = "constrained_vbr"
+application = "voip"
+signal = "voice"
+complexity = 10
+sample_rate = 48000
+max_bandwidth = "fullband"
+frame_duration = "60ms"
+inband_fec = true
+packet_loss_perc = 10
+dtx = true
+resampler_quality = "medium"
+downmix = "average"
+"#;
+
+    fn load(name: &str, text: &str) -> Result<Preset, Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("rustic_gui_{}_{}.toml", name, std::process::id()));
+        std::fs::write(&path, text)?;
+        let preset = Preset::load(&path);
+        std::fs::remove_file(&path)?;
+        preset
+    }
+
+    #[test]
+    fn loads_presets_saved_by_the_library() {
+        let preset = load("library", LIBRARY_PRESET).unwrap();
+        assert_eq!(preset.opus.get_mode(), OpusEncodingMode::ConstrainedVbr);
+        assert_eq!(preset.opus.get_application(), OpusApplication::Voip);
+        assert_eq!(preset.opus.get_signal(), OpusSignal::Voice);
+        assert_eq!(preset.opus.get_complexity(), 10);
+        assert!(preset.opus.get_inband_fec());
+        assert_eq!(preset.opus.get_packet_loss_perc(), 10);
+        assert!(preset.opus.get_dtx());
+        assert_eq!(preset.opus.get_frame_duration(), OpusFrameDuration::Ms60);
+
+        // Older files call complexity VBR quality
+        let old = load("old", "version = 1\n[opus]\nvbr_quality = 7\n").unwrap();
+        assert_eq!(old.opus.get_complexity(), 7);
+    }
+}
@@ -5,6 +5,7 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::error::Error;
 use crate::dsp::AudioProcessor;
+use crate::resample::{resample, ResamplerQuality};
 
 const RAW_BASELINE_FILE: &str = "raw_baseline.wav";
 
@@ -162,19 +163,11 @@ pub fn record_audio(file_path: &str, is_recording_flag: Arc<AtomicBool>, process
         .filter_map(Result::ok)
         .collect();
     
-    // Convert to mono if stereo (take left channel)
-    let mono_samples: Vec<i16> = if input_spec.channels == 2 {
-        samples.chunks(2)
-            .map(|chunk| chunk[0]) // Take left channel
-            .collect()
-    } else {
-        samples
-    };
-    
-    // Convert to float for processing
-    let mut mono_float: Vec<f32> = mono_samples.iter()
+    // Convert to float for processing and reduce every channel to mono
+    let float_samples: Vec<f32> = samples.iter()
         .map(|&s| s as f32 / 32768.0)
         .collect();
+    let mut mono_float = processor.downmix.apply(&float_samples, input_spec.channels as usize)?;
 
     // Apply highpass filter at 20Hz
     apply_highpass_filter(&mut mono_float, 20.0, input_spec.sample_rate as f32);
@@ -194,31 +187,9 @@ pub fn record_audio(file_path: &str, is_recording_flag: Arc<AtomicBool>, process
 
     let mut output_writer = hound::WavWriter::create(file_path, output_spec)?;
 
-    if input_spec.sample_rate != 48000 {
-        let input_duration = mono_float.len() as f32 / input_spec.sample_rate as f32;
-        let output_len = (input_duration * 48000.0) as usize;
-        let scale = (mono_float.len() - 1) as f32 / (output_len - 1) as f32;
-        
-        for i in 0..output_len {
-            let pos = i as f32 * scale;
-            let index = pos as usize;
-            let frac = pos - index as f32;
-            
-            let sample = if index + 1 < mono_float.len() {
-                mono_float[index] * (1.0 - frac) + mono_float[index + 1] * frac
-            } else {
-                mono_float[index]
-            };
-            
-            let sample_i16 = (sample * 32767.0).min(32767.0).max(-32768.0) as i16;
-            output_writer.write_sample(sample_i16)?;
-        }
-    } else {
-        // No resampling needed, just write normalized float samples as i16
-        for &sample in &mono_float {
-            let sample_i16 = (sample * 32767.0).min(32767.0).max(-32768.0) as i16;
-            output_writer.write_sample(sample_i16)?;
-        }
+    for sample in resample(&mono_float, input_spec.sample_rate, 48000, ResamplerQuality::default()) {
+        let sample_i16 = (sample * 32767.0).min(32767.0).max(-32768.0) as i16;
+        output_writer.write_sample(sample_i16)?;
     }
 
     output_writer.finalize()?;
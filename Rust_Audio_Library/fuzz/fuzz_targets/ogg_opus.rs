@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustic_audio_tool::{decode_opus_bytes, strip_padding, validate_opus, OpusFileInfo, OpusTags, ParseLimits};
+
+// Every Ogg Opus reader must return an error for bad input, never panic
+fuzz_target!(|data: &[u8]| {
+    let _ = validate_opus(data, &ParseLimits::default());
+    let _ = OpusFileInfo::from_stream(data);
+    let _ = OpusTags::from_stream(data);
+    let _ = strip_padding(data);
+    let _ = decode_opus_bytes(data);
+});
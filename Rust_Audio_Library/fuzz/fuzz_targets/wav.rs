@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustic_audio_tool::{read_wav_bytes, ParseLimits};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = read_wav_bytes(data, &ParseLimits::default());
+});
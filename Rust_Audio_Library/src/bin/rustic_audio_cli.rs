@@ -1,15 +1,66 @@
-use rustic_audio_tool::RusticAudio;
+use rustic_audio_tool::{list_devices, read_opus_info, Preset, RecordingEvent, RusticAudio};
 use std::env;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    
+    // `--preset <name|file>`, `--device <name|id>` and `--max-duration <seconds>` may appear anywhere
+    // and apply before the command runs
+    let Ok(preset) = take_option(&mut args, "--preset", "a preset name or file path") else {
+        return;
+    };
+    let Ok(device) = take_option(&mut args, "--device", "a device name or id") else {
+        return;
+    };
+    let Ok(max_duration) = take_option(&mut args, "--max-duration", "a number of seconds") else {
+        return;
+    };
     
     if args.len() < 2 {
-        println!("Usage: rustic_audio_cli [record|process|play] [file_path]");
+        println!("Usage: rustic_audio_cli [record|process|play|info|devices|presets|save-preset] [file_path] [--preset name|file] [--device name|id] [--max-duration seconds]");
         return;
     }
     
     let mut audio = RusticAudio::new();
+    if let Some(preset) = preset {
+        if let Err(e) = audio.load_preset(&preset) {
+            println!("Error loading preset {}: {}", preset, e);
+            return;
+        }
+        println!("Using preset {}", preset);
+    }
+    if let Some(device) = &device {
+        // Recording reads from the device and playback plays on it
+        let selected = match args[1].as_str() {
+            "record" => audio.set_input_device(Some(device)),
+            _ => audio.set_output_device(Some(device)),
+        };
+        if let Err(e) = selected {
+            println!("Error selecting device {}: {}", device, e);
+            return;
+        }
+    }
+    
+    if let Some(seconds) = &max_duration {
+        let limit = seconds.parse::<f64>().ok().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok());
+        let Some(limit) = limit else {
+            println!("--max-duration needs a number of seconds, not {}", seconds);
+            return;
+        };
+        if let Err(e) = audio.set_max_recording_duration(Some(limit)) {
+            println!("Error setting the maximum duration: {}", e);
+            return;
+        }
+        audio.set_recording_warning(Some(Duration::from_secs(5).min(limit / 2)));
+        audio.on_recording_event(|event| match event {
+            RecordingEvent::Warning(left) => println!("{:.1} s left", left.as_secs_f64()),
+            RecordingEvent::LimitReached => println!("Maximum duration reached"),
+            RecordingEvent::Remaining(_) => {}
+        });
+    }
     
     match args[1].as_str() {
         "record" => {
@@ -24,11 +75,26 @@ fn main() {
                 return;
             }
             
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
+            // Enter stops the recording, unless the maximum duration stops it first
+            let (enter_tx, enter_rx) = mpsc::channel();
+            thread::spawn(move || {
+                let mut input = String::new();
+                let _ = std::io::stdin().read_line(&mut input);
+                let _ = enter_tx.send(());
+            });
+            while audio.is_recording() && enter_rx.recv_timeout(Duration::from_millis(50)).is_err() {}
             
             if let Err(e) = audio.stop_recording() {
                 println!("Error stopping recording: {}", e);
+                return;
+            }
+            match audio.last_recording() {
+                Some(artifacts) => {
+                    for path in artifacts.paths() {
+                        println!("Wrote {}", path.display());
+                    }
+                }
+                None => println!("{}", audio.get_audio_info().last_message),
             }
         },
         "process" => {
@@ -39,7 +105,7 @@ fn main() {
             
             println!("Processing {} to {}", args[2], args[3]);
             if let Err(e) = audio.process_file(&args[2], &args[3]) {
-                println!("Error processing file: {:?}", e);
+                println!("Error processing file: {}", e);
             }
         },
         "play" => {
@@ -48,11 +114,14 @@ fn main() {
                 return;
             }
             
-            println!("Playing {}. Press Enter to stop...", args[2]);
+            // WAV and Opus both play through the same `Player`
             if let Err(e) = audio.play_processed_wav(&args[2]) {
                 println!("Error playing file: {}", e);
                 return;
             }
+            if let Some(player) = audio.player() {
+                println!("Playing {} ({:.1} s). Press Enter to stop...", args[2], player.duration().as_secs_f64());
+            }
             
             let mut input = String::new();
             std::io::stdin().read_line(&mut input).unwrap();
@@ -61,6 +130,83 @@ fn main() {
                 println!("Error stopping playback: {}", e);
             }
         },
+        "info" => {
+            if args.len() < 3 {
+                println!("Please provide an .opus file path");
+                return;
+            }
+            
+            match read_opus_info(&args[2]) {
+                Ok(info) => println!("{}", info),
+                Err(e) => println!("Error reading {}: {}", args[2], e),
+            }
+        },
+        "devices" => {
+            let hosts = match list_devices() {
+                Ok(hosts) => hosts,
+                Err(e) => {
+                    println!("Error listing devices: {}", e);
+                    return;
+                }
+            };
+            for host in hosts {
+                println!("{}{}", host.name, if host.is_default { " (default host)" } else { "" });
+                for device in host.devices {
+                    let mut defaults = Vec::new();
+                    if device.is_default_input {
+                        defaults.push("default input");
+                    }
+                    if device.is_default_output {
+                        defaults.push("default output");
+                    }
+                    if defaults.is_empty() {
+                        println!("  {}", device.name);
+                    } else {
+                        println!("  {} ({})", device.name, defaults.join(", "));
+                    }
+                    println!("    id: {}", device.id);
+                    for config in &device.input_configs {
+                        println!("    input:  {}", config);
+                    }
+                    for config in &device.output_configs {
+                        println!("    output: {}", config);
+                    }
+                }
+            }
+        },
+        "presets" => {
+            for name in Preset::builtin_names() {
+                if let Some(preset) = Preset::builtin(name) {
+                    println!("{:<15} {}", name, preset.description);
+                }
+            }
+        },
+        "save-preset" => {
+            if args.len() < 3 {
+                println!("Please provide a .toml or .json file path");
+                return;
+            }
+            
+            match audio.save_preset(&args[2]) {
+                Ok(()) => println!("Saved preset to {}", args[2]),
+                Err(e) => println!("Error saving preset: {}", e),
+            }
+        },
         _ => println!("Unknown command: {}", args[1]),
     }
-}
\ No newline at end of file
+}
+// Removes `flag` and the value after it from `args`; Err once the missing value is reported
+fn take_option(args: &mut Vec<String>, flag: &str, what: &str) -> Result<Option<String>, ()> {
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) if index + 1 < args.len() => {
+            let value = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(value))
+        }
+        Some(_) => {
+            println!("{} needs {}", flag, what);
+            Err(())
+        }
+        None => Ok(None),
+    }
+}
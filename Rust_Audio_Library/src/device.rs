@@ -0,0 +1,323 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+use std::fmt;
+use std::str::FromStr;
+use crate::error::{Result, RusticAudioError};
+
+/// Which way audio flows through a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceDirection {
+    Input,
+    Output,
+}
+
+/// Which device a stream opens and how.
+///
+/// Every field is optional; an empty `DeviceSettings` opens the default
+/// device of the default host with its default configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceSettings {
+    /// A device id as listed by `list_devices`, such as `alsa:hw:CARD=Headset,DEV=0`,
+    /// or a name. Names match exactly first, then as a unique case-insensitive part
+    /// of a device name, so `headset` finds `USB Headset Mono`.
+    pub device: Option<String>,
+    /// Sample rate to open the stream at when the device supports it; otherwise
+    /// the device's default rate is used.
+    pub sample_rate: Option<u32>,
+    /// Frames per callback to request, clamped to the range the device reports.
+    pub buffer_size: Option<u32>,
+}
+
+/// A range of stream configurations a device supports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    /// Sample format as cpal names it, such as `f32` or `i16`.
+    pub sample_format: String,
+    /// Smallest and largest buffer in frames, when the host reports them.
+    pub buffer_size: Option<(u32, u32)>,
+}
+
+/// An audio device and the configurations it supports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Stable id that `DeviceSettings::device` accepts, as `host:device`.
+    pub id: String,
+    pub name: String,
+    pub is_default_input: bool,
+    pub is_default_output: bool,
+    /// Empty when the device cannot record.
+    pub input_configs: Vec<DeviceConfigRange>,
+    /// Empty when the device cannot play.
+    pub output_configs: Vec<DeviceConfigRange>,
+}
+
+/// An audio host, such as ALSA, JACK or WASAPI, and its devices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub devices: Vec<DeviceInfo>,
+}
+
+/// Lists the hosts available on this system with their devices.
+///
+/// Hosts that are compiled in but cannot be opened, such as JACK without a
+/// running server, are left out.
+///
+/// # Returns
+/// * `Err(RusticAudioError::DeviceUnavailable)` if an available host fails to enumerate its devices
+pub fn list_devices() -> Result<Vec<HostInfo>> {
+    let default_host = cpal::default_host().id();
+    let mut hosts = Vec::new();
+
+    for host_id in cpal::available_hosts() {
+        let Ok(host) = cpal::host_from_id(host_id) else {
+            continue;
+        };
+        let default_input = host.default_input_device().and_then(|device| device.id().ok());
+        let default_output = host.default_output_device().and_then(|device| device.id().ok());
+
+        let mut devices = Vec::new();
+        for device in host.devices()? {
+            let Ok(id) = device.id() else {
+                continue;
+            };
+            devices.push(DeviceInfo {
+                name: device_name(&device),
+                is_default_input: default_input.as_ref() == Some(&id),
+                is_default_output: default_output.as_ref() == Some(&id),
+                input_configs: device.supported_input_configs()
+                    .map(|configs| configs.map(config_range).collect())
+                    .unwrap_or_default(),
+                output_configs: device.supported_output_configs()
+                    .map(|configs| configs.map(config_range).collect())
+                    .unwrap_or_default(),
+                id: id.to_string(),
+            });
+        }
+
+        hosts.push(HostInfo {
+            name: host_id.name().to_string(),
+            is_default: host_id == default_host,
+            devices,
+        });
+    }
+    Ok(hosts)
+}
+
+// Finds the device `selector` names, or the default device for `direction`
+pub(crate) fn find_device(selector: Option<&str>, direction: DeviceDirection) -> Result<cpal::Device> {
+    let Some(selector) = selector else {
+        let host = cpal::default_host();
+        let device = match direction {
+            DeviceDirection::Input => host.default_input_device(),
+            DeviceDirection::Output => host.default_output_device(),
+        };
+        return device.ok_or_else(|| {
+            RusticAudioError::DeviceUnavailable(format!("no default {} device", direction))
+        });
+    };
+
+    if let Ok(id) = cpal::DeviceId::from_str(selector) {
+        if let Some(device) = cpal::host_from_id(id.0).ok().and_then(|host| host.device_by_id(&id)) {
+            return Ok(device);
+        }
+    }
+
+    let mut devices = Vec::new();
+    for host_id in cpal::available_hosts() {
+        let Ok(host) = cpal::host_from_id(host_id) else {
+            continue;
+        };
+        let found = match direction {
+            DeviceDirection::Input => host.input_devices().map(|devices| devices.collect::<Vec<_>>()),
+            DeviceDirection::Output => host.output_devices().map(|devices| devices.collect::<Vec<_>>()),
+        };
+        devices.extend(found?);
+    }
+    let names: Vec<String> = devices.iter().map(device_name).collect();
+    let index = match_name(&names, selector, direction)?;
+    Ok(devices.swap_remove(index))
+}
+
+// Picks the device name `selector` refers to: an exact match, else the only
+// name containing it
+fn match_name(names: &[String], selector: &str, direction: DeviceDirection) -> Result<usize> {
+    if let Some(index) = names.iter().position(|name| name == selector) {
+        return Ok(index);
+    }
+
+    let wanted = selector.to_lowercase();
+    let matches: Vec<usize> = (0..names.len())
+        .filter(|&index| names[index].to_lowercase().contains(&wanted))
+        .collect();
+    match matches[..] {
+        [index] => Ok(index),
+        [] => Err(RusticAudioError::DeviceUnavailable(format!("no {} device matches {:?}", direction, selector))),
+        _ => Err(RusticAudioError::InvalidArgument(format!(
+            "{:?} matches several {} devices: {}",
+            selector,
+            direction,
+            matches.iter().map(|&index| names[index].as_str()).collect::<Vec<_>>().join(", ")
+        ))),
+    }
+}
+
+// Opens `settings.device` for `direction` and picks the stream configuration
+pub(crate) fn open_device(
+    settings: &DeviceSettings,
+    direction: DeviceDirection,
+) -> Result<(cpal::Device, cpal::StreamConfig, cpal::SampleFormat)> {
+    let device = find_device(settings.device.as_deref(), direction)?;
+    let (default, supported): (_, Vec<_>) = match direction {
+        DeviceDirection::Input => (
+            device.default_input_config()?,
+            device.supported_input_configs().map(|configs| configs.collect()).unwrap_or_default(),
+        ),
+        DeviceDirection::Output => (
+            device.default_output_config()?,
+            device.supported_output_configs().map(|configs| configs.collect()).unwrap_or_default(),
+        ),
+    };
+    let (config, sample_format) = choose_config(default, &supported, settings);
+    Ok((device, config, sample_format))
+}
+
+// Applies the preferred sample rate and buffer size to the device's default
+// configuration. A rate is only taken from a supported range, preferring one
+// with the default channel count and sample format, so a preference the
+// device cannot meet falls back to its default rate instead of failing.
+fn choose_config(
+    default: cpal::SupportedStreamConfig,
+    supported: &[cpal::SupportedStreamConfigRange],
+    settings: &DeviceSettings,
+) -> (cpal::StreamConfig, cpal::SampleFormat) {
+    let mut chosen = default;
+    if let Some(rate) = settings.sample_rate.filter(|&rate| rate != chosen.sample_rate()) {
+        let covers = |range: &&cpal::SupportedStreamConfigRange| {
+            (range.min_sample_rate()..=range.max_sample_rate()).contains(&rate)
+        };
+        let same_layout = supported.iter()
+            .filter(covers)
+            .find(|range| range.channels() == chosen.channels() && range.sample_format() == chosen.sample_format());
+        if let Some(range) = same_layout.or_else(|| supported.iter().find(covers)) {
+            chosen = range.with_sample_rate(rate);
+        }
+    }
+
+    let mut config = chosen.config();
+    if let Some(frames) = settings.buffer_size {
+        let frames = match *chosen.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } => frames.clamp(min, max.max(min)),
+            cpal::SupportedBufferSize::Unknown => frames,
+        };
+        config.buffer_size = cpal::BufferSize::Fixed(frames);
+    }
+    (config, chosen.sample_format())
+}
+
+fn device_name(device: &cpal::Device) -> String {
+    device.description()
+        .map(|description| description.name().to_string())
+        .unwrap_or_else(|_| "(unnamed)".to_string())
+}
+
+fn config_range(range: cpal::SupportedStreamConfigRange) -> DeviceConfigRange {
+    DeviceConfigRange {
+        channels: range.channels(),
+        min_sample_rate: range.min_sample_rate(),
+        max_sample_rate: range.max_sample_rate(),
+        sample_format: range.sample_format().to_string(),
+        buffer_size: match *range.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } => Some((min, max)),
+            cpal::SupportedBufferSize::Unknown => None,
+        },
+    }
+}
+
+impl fmt::Display for DeviceDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DeviceDirection::Input => "input",
+            DeviceDirection::Output => "output",
+        })
+    }
+}
+
+impl fmt::Display for DeviceConfigRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ch, ", self.channels)?;
+        if self.min_sample_rate == self.max_sample_rate {
+            write!(f, "{} Hz", self.min_sample_rate)?;
+        } else {
+            write!(f, "{}-{} Hz", self.min_sample_rate, self.max_sample_rate)?;
+        }
+        write!(f, ", {}", self.sample_format)?;
+        if let Some((min, max)) = self.buffer_size {
+            write!(f, ", buffer {}-{} frames", min, max)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{SampleFormat, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn devices_match_by_exact_name_then_unique_part() {
+        let devices = names(&["HD Webcam C920", "USB Headset Mono", "USB Headset"]);
+        assert_eq!(match_name(&devices, "USB Headset", DeviceDirection::Input).unwrap(), 2);
+        assert_eq!(match_name(&devices, "webcam", DeviceDirection::Input).unwrap(), 0);
+        assert_eq!(match_name(&devices, "mono", DeviceDirection::Input).unwrap(), 1);
+        assert!(matches!(
+            match_name(&devices, "headset", DeviceDirection::Input),
+            Err(RusticAudioError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            match_name(&devices, "speaker", DeviceDirection::Output),
+            Err(RusticAudioError::DeviceUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn preferred_rate_and_buffer_size_fit_what_the_device_supports() {
+        let buffer = SupportedBufferSize::Range { min: 64, max: 4096 };
+        let default = SupportedStreamConfig::new(2, 44_100, buffer, SampleFormat::I16);
+        let supported = [
+            SupportedStreamConfigRange::new(1, 8_000, 96_000, buffer, SampleFormat::F32),
+            SupportedStreamConfigRange::new(2, 8_000, 48_000, buffer, SampleFormat::I16),
+        ];
+
+        let settings = DeviceSettings { sample_rate: Some(48_000), buffer_size: Some(256), ..Default::default() };
+        let (config, format) = choose_config(default.clone(), &supported, &settings);
+        assert_eq!((config.channels, config.sample_rate, format), (2, 48_000, SampleFormat::I16));
+        assert_eq!(config.buffer_size, cpal::BufferSize::Fixed(256));
+
+        // Only another layout reaches 96 kHz
+        let settings = DeviceSettings { sample_rate: Some(96_000), buffer_size: Some(8), ..Default::default() };
+        let (config, format) = choose_config(default.clone(), &supported, &settings);
+        assert_eq!((config.channels, config.sample_rate, format), (1, 96_000, SampleFormat::F32));
+        assert_eq!(config.buffer_size, cpal::BufferSize::Fixed(64));
+
+        // Nothing reaches 192 kHz, so the default stays
+        let settings = DeviceSettings { sample_rate: Some(192_000), ..Default::default() };
+        let (config, _) = choose_config(default, &supported, &settings);
+        assert_eq!((config.sample_rate, config.buffer_size), (44_100, cpal::BufferSize::Default));
+    }
+
+    #[test]
+    fn config_ranges_print_on_one_line() {
+        let range = config_range(SupportedStreamConfigRange::new(
+            2, 44_100, 48_000, SupportedBufferSize::Range { min: 32, max: 8192 }, SampleFormat::F32,
+        ));
+        assert_eq!(range.to_string(), "2 ch, 44100-48000 Hz, f32, buffer 32-8192 frames");
+    }
+}
@@ -1,9 +1,15 @@
-use rustfft::num_complex::Complex;
-use rustfft::FftPlanner;
-use rustfft::num_traits::Zero;
-use std::collections::VecDeque;
-
-#[derive(Clone)]
+use crate::effect::{
+    AmplitudeGate, Chain, Effect, FadeIn, Gain, LookaheadLimiter, RmsNormalizer, SpectralFilter, SpectralGate,
+};
+use crate::downmix::Downmix;
+use crate::error::{Result, RusticAudioError};
+use crate::validate::{read_wav_file, ParseLimits};
+use serde::{Deserialize, Serialize};
+
+pub(crate) const FADE_IN_MS: f32 = 200.0;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct AudioProcessor {
     pub sample_rate: f32,
     pub threshold_db: f32,
@@ -26,6 +32,9 @@ pub struct AudioProcessor {
     pub amplitude_gate_enabled: bool,
     pub gain_boost_enabled: bool,
     pub limiter_enabled: bool,
+    /// How recordings, and files when `downmix_enabled` is set, are reduced to mono.
+    pub downmix: Downmix,
+    pub downmix_enabled: bool,
 }
 //AudioProcessor Default 
 impl AudioProcessor {
@@ -52,50 +61,29 @@ impl AudioProcessor {
             amplitude_gate_enabled: false,
             gain_boost_enabled: false,
             limiter_enabled: true,
+            downmix: Downmix::Average,
+            downmix_enabled: false,
         }
     }
 
-    pub fn process_file(&mut self, input_path: &str, output_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn process_file(&mut self, input_path: &str, output_path: &str) -> Result<()> {
         // Read input file
-        let mut reader = hound::WavReader::open(input_path)?;
-        let spec = reader.spec();
+        let (mut samples, spec) = read_wav_file(input_path, &ParseLimits::trusted())?;
         self.sample_rate = spec.sample_rate as f32;
         
-        // Read samples
-        let mut samples: Vec<f32> = if spec.sample_format == hound::SampleFormat::Float {
-            reader.samples::<f32>().map(|s| s.unwrap()).collect()
+        // Optionally reduce to mono before the chain, so it only runs once
+        let channels = if self.downmix_enabled && spec.channels > 1 {
+            samples = self.downmix.apply(&samples, spec.channels as usize)?;
+            1
         } else {
-            reader.samples::<i16>().map(|s| s.unwrap() as f32 / 32768.0).collect()
+            spec.channels
         };
-        
-        // Apply RMS normalization if enabled
-        if self.rms_enabled {
-            self.apply_rms_normalization(&mut samples);
-        }
-        
-        // Apply processing in order, but only if enabled
-        if self.filters_enabled {
-            self.apply_filters(&mut samples);         // 1. Filters
-        }
-        if self.spectral_gate_enabled {
-            self.apply_noise_gate(&mut samples);      // 2. Spectral Gate
-        }
-        if self.amplitude_gate_enabled {
-            self.apply_amplitude_gate(&mut samples);  // 3. Amplitude Gate
-        }
-        if self.gain_boost_enabled {
-            self.apply_gain_boost(&mut samples);      // 4. Gain Boost
-        }
-        if self.limiter_enabled {
-            self.apply_lookahead_limiter(&mut samples); // 5. Limiter
-        }
-        
-        // Apply a 200ms fade-in to avoid clicks
-        self.apply_fade_in(&mut samples, 200.0);
+
+        self.process_samples(&mut samples, spec.sample_rate, channels)?;
         
         // Write output file - use the SAME spec as input
         let spec = hound::WavSpec {
-            channels: spec.channels,           // Keep original channel count
+            channels,                          // Original channel count unless downmixed
             sample_rate: spec.sample_rate,     // Keep original sample rate
             bits_per_sample: spec.bits_per_sample,  // Keep original bit depth
             sample_format: spec.sample_format, // Keep original format
@@ -111,9 +99,9 @@ impl AudioProcessor {
                 }
             },
             hound::SampleFormat::Int => {
+                let max = ((1i64 << (spec.bits_per_sample - 1)) - 1) as f32;
                 for &sample in &samples {
-                    let sample_i16 = (sample * 32767.0).min(32767.0).max(-32768.0) as i16;
-                    writer.write_sample(sample_i16)?;
+                    writer.write_sample((sample * max).clamp(-max - 1.0, max) as i32)?;
                 }
             }
         }
@@ -122,318 +110,106 @@ impl AudioProcessor {
         Ok(())
     }
 
-    // separate filter function
-    fn apply_filters(&mut self, samples: &mut Vec<f32>) {
-        let fft_size = 4096;
-        let hop_size = fft_size / 2;
-        
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(fft_size);
-        let ifft = planner.plan_fft_inverse(fft_size);
-
-        let window: Vec<f32> = (0..fft_size)
-            .map(|n| {
-                0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / fft_size as f32).cos()
-            })
-            .collect();
-
-        let mut output = vec![0.0; samples.len()];
-        let mut normalization = vec![0.0; samples.len()];
-        let mut pos = 0;
-
-        while pos < samples.len() {
-            let mut complex_input: Vec<Complex<f32>> = vec![Complex::zero(); fft_size];
-            let copy_len = fft_size.min(samples.len() - pos);
-            
-            for i in 0..copy_len {
-                complex_input[i] = Complex::new(samples[pos + i] * window[i], 0.0);
-            }
-
-            fft.process(&mut complex_input);
-
-            for i in 0..complex_input.len() {
-                let frequency = if i <= fft_size/2 {
-                    i as f32
-                } else {
-                    i as f32 - fft_size as f32
-                } * self.sample_rate / fft_size as f32;
-
-                let freq_abs = frequency.abs();
-
-                // Apply highpass and lowpass filters
-                if freq_abs < self.highpass_freq || freq_abs > self.lowpass_freq {
-                    complex_input[i] = Complex::zero();
-                    continue;
-                }
-
-                if complex_input[i].norm() < 1e-10 {
-                    complex_input[i] = Complex::zero();
-                }
-            }
-
-            ifft.process(&mut complex_input);
-
-            for i in 0..fft_size {
-                if pos + i < output.len() {
-                    output[pos + i] += complex_input[i].re * window[i] / fft_size as f32;
-                    normalization[pos + i] += window[i] * window[i];
-                }
-            }
-
-            pos += hop_size;
+    /// Runs the default effect chain over interleaved `samples` in place.
+    ///
+    /// Each channel is processed independently, so `samples.len()` must be a
+    /// multiple of `channels`. The chain's latency is removed, so the output
+    /// lines up with the input. This is the in-memory counterpart of `process_file`.
+    pub fn process_samples(&mut self, samples: &mut [f32], sample_rate: u32, channels: u16) -> Result<()> {
+        if sample_rate == 0 || channels == 0 {
+            return Err(RusticAudioError::InvalidArgument(
+                "sample rate and channel count must be greater than zero".to_string(),
+            ));
+        }
+        if !samples.len().is_multiple_of(channels as usize) {
+            return Err(RusticAudioError::InvalidArgument(format!(
+                "{} samples is not a whole number of {}-channel frames", samples.len(), channels
+            )));
         }
 
-        for i in 0..samples.len() {
-            if normalization[i] > 1e-10 {
-                output[i] /= normalization[i];
-            }
+        self.sample_rate = sample_rate as f32;
+        if samples.is_empty() {
+            return Ok(());
         }
 
-        samples.copy_from_slice(&output);
-    }
+        let channels = channels as usize;
+        let frames = samples.len() / channels;
 
-    // Spectral noise gate function
-    fn apply_noise_gate(&self, samples: &mut Vec<f32>) {
-        let fft_size = 4096;
-        let hop_size = fft_size / 2;
-        
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(fft_size);
-        let ifft = planner.plan_fft_inverse(fft_size);
-
-        let window: Vec<f32> = (0..fft_size)
-            .map(|n| {
-                0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / fft_size as f32).cos()
-            })
-            .collect();
-
-        let mut output = vec![0.0; samples.len()];
-        let mut normalization = vec![0.0; samples.len()];
-        let mut pos = 0;
-
-        let threshold = 10.0f32.powf(self.threshold_db / 20.0);
-
-        while pos < samples.len() {
-            let mut complex_input: Vec<Complex<f32>> = vec![Complex::zero(); fft_size];
-            let copy_len = fft_size.min(samples.len() - pos);
-            
-            for i in 0..copy_len {
-                complex_input[i] = Complex::new(samples[pos + i] * window[i], 0.0);
-            }
+        // Apply a 200ms fade-in to avoid clicks
+        let fade_samples = ((FADE_IN_MS / 1000.0 * self.sample_rate) as usize).min(frames);
+        println!("Applying {:.0}ms fade-in ({} samples)", FADE_IN_MS, fade_samples);
 
-            fft.process(&mut complex_input);
+        let mut chain = self.build_chain(self.rms_enabled, fade_samples);
+        if channels == 1 {
+            chain.process_offline(samples);
+            return Ok(());
+        }
 
-            // Apply spectral noise gate
-            for i in 0..complex_input.len() {
-                let magnitude = complex_input[i].norm();
-                if magnitude < threshold {
-                    complex_input[i] = Complex::zero();
-                }
+        let mut channel_samples = vec![0.0; frames];
+        for channel in 0..channels {
+            for (frame, sample) in channel_samples.iter_mut().enumerate() {
+                *sample = samples[frame * channels + channel];
             }
-
-            ifft.process(&mut complex_input);
-
-            for i in 0..fft_size {
-                if pos + i < output.len() {
-                    output[pos + i] += complex_input[i].re * window[i] / fft_size as f32;
-                    normalization[pos + i] += window[i] * window[i];
-                }
+            chain.process_offline(&mut channel_samples);
+            for (frame, &sample) in channel_samples.iter().enumerate() {
+                samples[frame * channels + channel] = sample;
             }
-
-            pos += hop_size;
         }
 
-        for i in 0..samples.len() {
-            if normalization[i] > 1e-10 {
-                output[i] /= normalization[i];
-            }
-        }
+        Ok(())
+    }
 
-        samples.copy_from_slice(&output);
+    /// Builds the default effect chain for one channel from these settings.
+    ///
+    /// The order is RMS normalization, filters, spectral gate, amplitude gate,
+    /// gain boost, limiter and a 200ms fade-in, skipping disabled stages. The
+    /// returned chain can be edited before use.
+    pub fn chain(&self) -> Chain {
+        self.build_chain(self.rms_enabled, (FADE_IN_MS / 1000.0 * self.sample_rate) as usize)
     }
-    
-    // amplitude gate function
-    fn apply_amplitude_gate(&self, samples: &mut Vec<f32>) {
-        let threshold = 10.0f32.powf(self.amplitude_threshold_db / 20.0);
-        let lookahead_samples = (self.amplitude_lookahead_ms / 1000.0 * self.sample_rate) as usize;
-        let attack_coef = (-2.2 / (self.amplitude_attack_ms / 1000.0 * self.sample_rate)).exp();
-        let release_coef = (-2.2 / (self.amplitude_release_ms / 1000.0 * self.sample_rate)).exp();
-        
-        let mut lookahead_buffer = VecDeque::with_capacity(lookahead_samples + 1);
-        let mut gate_gain = 0.0;
-        let mut output = vec![0.0; samples.len()];
-        let mut output_idx = 0;
-        
-        // Pre-fill lookahead buffer
-        for _ in 0..lookahead_samples {
-            lookahead_buffer.push_back(0.0);
-        }
-        
-        // Process all input samples
-        for &sample in samples.iter() {
-            lookahead_buffer.push_back(sample);
-            
-            // Find peak in lookahead window
-            let peak = lookahead_buffer.iter().map(|&s| s.abs()).fold(0.0, f32::max);
-            
-            // Calculate target gate gain
-            let target_gain = if peak >= threshold { 1.0 } else { 0.0 };
-            
-            // Apply attack/release smoothing
-            if target_gain > gate_gain {
-                gate_gain = gate_gain * attack_coef + target_gain * (1.0 - attack_coef);
-            } else {
-                gate_gain = gate_gain * release_coef + target_gain * (1.0 - release_coef);
-            }
-            
-            // Apply gain to the oldest sample in buffer
-            if let Some(oldest_sample) = lookahead_buffer.pop_front() {
-                if output_idx < output.len() {
-                    output[output_idx] = oldest_sample * gate_gain;
-                    output_idx += 1;
-                }
-            }
+
+    pub(crate) fn build_chain(&self, rms: bool, fade_samples: usize) -> Chain {
+        let mut chain = Chain::new();
+
+        if rms {
+            chain.push(RmsNormalizer::new(self.rms_target_db));
         }
-        
-        // Process remaining samples in buffer
-        while !lookahead_buffer.is_empty() && output_idx < output.len() {
-            if let Some(oldest_sample) = lookahead_buffer.pop_front() {
-                output[output_idx] = oldest_sample * gate_gain;
-                output_idx += 1;
-            }
+        if self.filters_enabled {
+            chain.push(SpectralFilter::new(self.sample_rate, self.highpass_freq, self.lowpass_freq));
         }
-        
-        samples.copy_from_slice(&output);
-    }
-    
-    // gain boost function
-    fn apply_gain_boost(&self, samples: &mut Vec<f32>) {
-        let gain_linear = 10.0f32.powf(self.gain_db / 20.0);
-        
-        for sample in samples.iter_mut() {
-            *sample *= gain_linear;
+        if self.spectral_gate_enabled {
+            chain.push(SpectralGate::new(self.threshold_db));
         }
-    }
-    
-    // lookahead limiter function
-    fn apply_lookahead_limiter(&self, samples: &mut Vec<f32>) {
-        let threshold = 10.0f32.powf(self.limiter_threshold_db / 20.0);
-        let ceiling = 10.0f32.powf(self.limiter_ceiling_db / 20.0);
-        let lookahead_samples = (self.limiter_lookahead_ms / 1000.0 * self.sample_rate) as usize;
-        let attack_samples = (self.limiter_attack_ms / 1000.0 * self.sample_rate).max(1.0);
-        let attack_coef = (-2.2 / attack_samples).exp();
-        let release_coef = (-2.2 / (self.limiter_release_ms / 1000.0 * self.sample_rate)).exp();
-        
-        let mut lookahead_buffer = VecDeque::with_capacity(lookahead_samples + 1);
-        let mut limiter_gain = 1.0f32;
-        
-        let mut output = vec![0.0; samples.len()];  // Initialize with correct size
-        let mut output_idx = 0;
-        
-        // Pre-fill lookahead buffer
-        for _ in 0..lookahead_samples {
-            lookahead_buffer.push_back(0.0);
+        if self.amplitude_gate_enabled {
+            chain.push(AmplitudeGate::new(
+                self.sample_rate,
+                self.amplitude_threshold_db,
+                self.amplitude_attack_ms,
+                self.amplitude_release_ms,
+                self.amplitude_lookahead_ms,
+            ));
         }
-        
-        // Process all input samples
-        for &sample in samples.iter() {
-            // Add sample to lookahead buffer
-            lookahead_buffer.push_back(sample);
-            
-            // Find peak in lookahead window
-            let peak = lookahead_buffer.iter().map(|&s| s.abs()).fold(0.0, f32::max);
-            
-            // When the lookahead peak crosses threshold, target the ceiling.
-            let target_gain = if peak > threshold {
-                ceiling / peak
-            } else {
-                1.0
-            };
-            
-            // Use attack when moving farther away from unity, release when relaxing.
-            let current_distance = (limiter_gain - 1.0).abs();
-            let target_distance = (target_gain - 1.0).abs();
-            if target_distance >= current_distance {
-                limiter_gain = limiter_gain * attack_coef + target_gain * (1.0 - attack_coef);
-            } else {
-                limiter_gain = limiter_gain * release_coef + target_gain * (1.0 - release_coef);
-            }
-            
-            // Apply gain reduction to the oldest sample in buffer
-            if let Some(oldest_sample) = lookahead_buffer.pop_front() {
-                if output_idx < output.len() {
-                    output[output_idx] = (oldest_sample * limiter_gain).clamp(-ceiling, ceiling);
-                    output_idx += 1;
-                }
-            }
+        if self.gain_boost_enabled {
+            chain.push(Gain::new(self.gain_db));
         }
-        
-        // Process remaining samples in buffer
-        while !lookahead_buffer.is_empty() && output_idx < output.len() {
-            if let Some(oldest_sample) = lookahead_buffer.pop_front() {
-                output[output_idx] = (oldest_sample * limiter_gain).clamp(-ceiling, ceiling);
-                output_idx += 1;
-            }
+        if self.limiter_enabled {
+            chain.push(self.limiter());
         }
-        
-        // Ensure output length matches input length
-        output.truncate(samples.len());
-        samples.copy_from_slice(&output);
-    }
+        let latency = chain.latency_samples();
+        chain.push(FadeIn::with_delay(fade_samples, latency));
 
-    // The Root Mean Square (RMS) normalization function
-    fn apply_rms_normalization(&self, samples: &mut Vec<f32>) {
-        // Calculate current RMS
-        let rms_current = (samples.iter().map(|&x| x * x).sum::<f32>() / samples.len() as f32).sqrt();
-        let rms_current_db = 20.0 * rms_current.log10();
-        
-        // Convert target RMS from dB to linear
-        let target_rms = 10.0f32.powf(self.rms_target_db / 20.0);
-        
-        // Calculate gain factor
-        let gain_factor = target_rms / rms_current;
-        
-        println!("RMS Normalization:");
-        println!("  Current RMS: {:.2} dB", rms_current_db);
-        println!("  Target RMS: {:.2} dB", self.rms_target_db);
-        println!("  Gain factor: {:.2}x", gain_factor);
-        
-        // Apply gain with peak limiting
-        for sample in samples.iter_mut() {
-            // Apply gain
-            *sample *= gain_factor;
-            
-            // Apply soft clipping to prevent hard clipping
-            if *sample > 0.95 {
-                *sample = 0.95 + (1.0 - 0.95) * (1.0 - (1.0 - (*sample - 0.95) / (1.0 - 0.95)).powi(2));
-            } else if *sample < -0.95 {
-                *sample = -0.95 - (1.0 - 0.95) * (1.0 - (1.0 - (-*sample - 0.95) / (1.0 - 0.95)).powi(2));
-            }
-            
-            // Hard limit as a safety measure
-            *sample = sample.max(-1.0).min(1.0);
-        }
-        
-        // Calculate new RMS after normalization
-        let new_rms = (samples.iter().map(|&x| x * x).sum::<f32>() / samples.len() as f32).sqrt();
-        let new_rms_db = 20.0 * new_rms.log10();
-        
-        println!("  New RMS after normalization: {:.2} dB", new_rms_db);
+        chain
     }
 
-    // Add a fade-in function to the processor
-    fn apply_fade_in(&self, samples: &mut Vec<f32>, fade_ms: f32) {
-        let fade_samples = (fade_ms / 1000.0 * self.sample_rate) as usize;
-        let fade_samples = fade_samples.min(samples.len());
-        
-        println!("Applying {:.0}ms fade-in ({} samples)", fade_ms, fade_samples);
-        
-        for i in 0..fade_samples {
-            let gain = (i as f32) / (fade_samples as f32);
-            // Use a smooth curve for the fade (cubic)
-            let smooth_gain = gain * gain * (3.0 - 2.0 * gain);
-            samples[i] *= smooth_gain;
-        }
+    fn limiter(&self) -> LookaheadLimiter {
+        LookaheadLimiter::new(
+            self.sample_rate,
+            self.limiter_threshold_db,
+            self.limiter_ceiling_db,
+            self.limiter_attack_ms,
+            self.limiter_release_ms,
+            self.limiter_lookahead_ms,
+        )
     }
 }
 
@@ -446,6 +222,7 @@ impl Default for AudioProcessor {
 #[cfg(test)]
 mod tests {
     use super::AudioProcessor;
+    use crate::effect::Effect;
 
     fn peak(samples: &[f32]) -> f32 {
         samples.iter().map(|sample| sample.abs()).fold(0.0, f32::max)
@@ -467,7 +244,7 @@ mod tests {
         let input_level = 0.2;
         let mut samples = vec![input_level; 4096];
 
-        processor.apply_lookahead_limiter(&mut samples);
+        processor.limiter().process_offline(&mut samples);
 
         let settled_peak = peak(&samples[1024..]);
         assert!((settled_peak - input_level).abs() < 1e-3);
@@ -478,7 +255,7 @@ mod tests {
         let processor = configured_processor();
         let mut samples = vec![0.4; 4096];
 
-        processor.apply_lookahead_limiter(&mut samples);
+        processor.limiter().process_offline(&mut samples);
 
         let ceiling = 10.0f32.powf(processor.limiter_ceiling_db / 20.0);
         let settled_peak = peak(&samples[1024..]);
@@ -491,7 +268,7 @@ mod tests {
         let processor = configured_processor();
         let mut samples = vec![0.95; 4096];
 
-        processor.apply_lookahead_limiter(&mut samples);
+        processor.limiter().process_offline(&mut samples);
 
         let ceiling = 10.0f32.powf(processor.limiter_ceiling_db / 20.0);
         let settled_peak = peak(&samples[1024..]);
@@ -505,7 +282,7 @@ mod tests {
         processor.limiter_attack_ms = 5.0;
         let mut samples = vec![0.4; 4096];
 
-        processor.apply_lookahead_limiter(&mut samples);
+        processor.limiter().process_offline(&mut samples);
 
         let ceiling = 10.0f32.powf(processor.limiter_ceiling_db / 20.0);
         let early_peak = peak(&samples[48..256]);
@@ -513,4 +290,46 @@ mod tests {
         assert!(early_peak < settled_peak);
         assert!(settled_peak <= ceiling + 1e-4);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn process_samples_keeps_channels_separate() {
+        let mut processor = configured_processor();
+        let mut samples: Vec<f32> = (0..4096).flat_map(|_| [0.4, 0.0]).collect();
+
+        processor.process_samples(&mut samples, 48_000, 2).unwrap();
+
+        let right: Vec<f32> = samples.iter().skip(1).step_by(2).copied().collect();
+        assert!(peak(&right) < 1e-6);
+        assert!(peak(&samples) > 0.4);
+    }
+
+    #[test]
+    fn process_samples_rejects_partial_frames() {
+        let mut processor = configured_processor();
+        let mut samples = vec![0.0; 5];
+
+        assert!(processor.process_samples(&mut samples, 48_000, 2).is_err());
+    }
+
+    #[test]
+    fn process_file_reads_local_recordings_past_the_untrusted_duration_limit() {
+        let dir = std::env::temp_dir().join(format!("rustic_dsp_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("long.wav");
+        let output = dir.join("long_processed.wav");
+
+        // Longer than the 600 s ParseLimits::default() allows for foreign input
+        let spec = hound::WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&input, spec).unwrap();
+        for i in 0..601 * 8000 {
+            writer.write_sample(((i % 80) as i16 - 40) * 100).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut processor = AudioProcessor::new(8000.0);
+        processor.process_file(input.to_str().unwrap(), output.to_str().unwrap()).unwrap();
+        assert_eq!(hound::WavReader::open(&output).unwrap().duration(), 601 * 8000);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,727 @@
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use rustfft::num_traits::Zero;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// A single processing stage working on one channel of audio.
+///
+/// Effects are fed consecutive blocks of any size and process them in place.
+/// An effect that needs to look ahead reports the delay it adds through
+/// `latency_samples`; everything else about its state is private. Multi-channel
+/// audio uses one instance per channel.
+pub trait Effect: Send {
+    /// Short identifier used in logs and chain descriptions.
+    fn name(&self) -> &str;
+
+    /// Processes the next block in place. Output lags input by `latency_samples()`.
+    fn process(&mut self, samples: &mut [f32]);
+
+    /// Discards all carried state, as if the effect had just been created.
+    fn reset(&mut self);
+
+    /// Delay between a sample entering `process` and leaving it.
+    fn latency_samples(&self) -> usize {
+        0
+    }
+
+    /// Returns the `latency_samples()` samples still held back and resets the effect.
+    ///
+    /// The default pushes silence through `process` to drain them.
+    fn flush(&mut self) -> Vec<f32> {
+        let mut tail = vec![0.0; self.latency_samples()];
+        self.process(&mut tail);
+        self.reset();
+        tail
+    }
+
+    /// Processes a complete signal in place with the latency removed, then resets.
+    fn process_offline(&mut self, samples: &mut [f32]) {
+        let latency = self.latency_samples();
+        self.process(samples);
+        let tail = self.flush();
+
+        let len = samples.len();
+        if latency == 0 {
+            return;
+        }
+        if latency < len {
+            samples.copy_within(latency.., 0);
+            samples[len - latency..].copy_from_slice(&tail);
+        } else {
+            samples.copy_from_slice(&tail[latency - len..]);
+        }
+    }
+}
+
+/// An ordered list of effects run one after another.
+///
+/// `AudioProcessor::chain` builds the default chain from its settings; effects
+/// can then be added, removed or reordered freely, including custom `Effect`
+/// implementations. A chain is itself an `Effect`, so chains can be nested.
+#[derive(Default)]
+pub struct Chain {
+    effects: Vec<Box<dyn Effect>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `effect` and returns the chain, for builder-style construction.
+    pub fn with(mut self, effect: impl Effect + 'static) -> Self {
+        self.push(effect);
+        self
+    }
+
+    /// Appends `effect` to the end of the chain.
+    pub fn push(&mut self, effect: impl Effect + 'static) {
+        self.effects.push(Box::new(effect));
+    }
+
+    /// Inserts `effect` at `index`, shifting later effects back.
+    ///
+    /// Panics if `index > len()`, like `Vec::insert`.
+    pub fn insert(&mut self, index: usize, effect: impl Effect + 'static) {
+        self.effects.insert(index, Box::new(effect));
+    }
+
+    /// Removes and returns the effect at `index`, if there is one.
+    pub fn remove(&mut self, index: usize) -> Option<Box<dyn Effect>> {
+        (index < self.effects.len()).then(|| self.effects.remove(index))
+    }
+
+    pub fn effects(&self) -> &[Box<dyn Effect>] {
+        &self.effects
+    }
+
+    /// Mutable access to the effect list, e.g. to reorder it with `swap` or `sort_by_key`.
+    pub fn effects_mut(&mut self) -> &mut Vec<Box<dyn Effect>> {
+        &mut self.effects
+    }
+
+    /// Names of the effects in processing order.
+    pub fn names(&self) -> Vec<&str> {
+        self.effects.iter().map(|effect| effect.name()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+}
+
+impl Effect for Chain {
+    fn name(&self) -> &str {
+        "chain"
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for effect in self.effects.iter_mut() {
+            effect.process(samples);
+        }
+    }
+
+    fn reset(&mut self) {
+        for effect in self.effects.iter_mut() {
+            effect.reset();
+        }
+    }
+
+    fn latency_samples(&self) -> usize {
+        self.effects.iter().map(|effect| effect.latency_samples()).sum()
+    }
+
+    fn flush(&mut self) -> Vec<f32> {
+        // Each effect's tail still has to pass through every effect after it
+        let mut tail = Vec::new();
+        for effect in self.effects.iter_mut() {
+            effect.process(&mut tail);
+            tail.extend(effect.flush());
+        }
+        tail
+    }
+}
+
+pub(crate) const STFT_SIZE: usize = 4096;
+const STFT_HOP: usize = STFT_SIZE / 2;
+
+enum SpectralMode {
+    Filters { sample_rate: f32, highpass_freq: f32, lowpass_freq: f32 },
+    NoiseGate { threshold: f32 },
+}
+
+/// Overlap-add STFT stage shared by the FFT filters and the spectral gate.
+///
+/// Output is emitted one hop at a time once every frame overlapping a sample
+/// has been added. `pending` is pre-filled with `STFT_SIZE - 1` zeros, which is
+/// the longest a sample can be held back, so the effect has a fixed latency.
+struct SpectralStage {
+    mode: SpectralMode,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    frame: Vec<Complex<f32>>,
+    // Pending input, starting at the next frame position
+    input: Vec<f32>,
+    // Overlap-add output and window normalization, aligned with `input`
+    output: Vec<f32>,
+    normalization: Vec<f32>,
+    // Finished samples waiting to be handed out
+    pending: VecDeque<f32>,
+}
+
+impl SpectralStage {
+    fn new(mode: SpectralMode) -> Self {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(STFT_SIZE);
+        let ifft = planner.plan_fft_inverse(STFT_SIZE);
+
+        let window: Vec<f32> = (0..STFT_SIZE)
+            .map(|n| {
+                0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / STFT_SIZE as f32).cos()
+            })
+            .collect();
+
+        let mut stage = Self {
+            mode,
+            fft,
+            ifft,
+            window,
+            frame: vec![Complex::zero(); STFT_SIZE],
+            input: Vec::with_capacity(STFT_SIZE * 2),
+            output: vec![0.0; STFT_SIZE],
+            normalization: vec![0.0; STFT_SIZE],
+            pending: VecDeque::with_capacity(STFT_SIZE * 2),
+        };
+        stage.reset();
+        stage
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        self.input.extend_from_slice(samples);
+        while self.input.len() >= STFT_SIZE {
+            self.process_frame();
+            self.emit(STFT_HOP);
+        }
+
+        for sample in samples.iter_mut() {
+            *sample = self.pending.pop_front().unwrap_or(0.0);
+        }
+    }
+
+    // Zero-pads the remaining input and hands out everything still pending
+    fn flush(&mut self) -> Vec<f32> {
+        while !self.input.is_empty() {
+            self.process_frame();
+            let count = STFT_HOP.min(self.input.len());
+            self.emit(count);
+        }
+
+        let tail = self.pending.drain(..).collect();
+        self.reset();
+        tail
+    }
+
+    fn reset(&mut self) {
+        self.input.clear();
+        self.output.fill(0.0);
+        self.normalization.fill(0.0);
+        self.pending.clear();
+        self.pending.resize(STFT_SIZE - 1, 0.0);
+    }
+
+    fn process_frame(&mut self) {
+        let copy_len = STFT_SIZE.min(self.input.len());
+        self.frame.fill(Complex::zero());
+        for (i, bin) in self.frame.iter_mut().enumerate().take(copy_len) {
+            *bin = Complex::new(self.input[i] * self.window[i], 0.0);
+        }
+
+        self.fft.process(&mut self.frame);
+
+        match self.mode {
+            SpectralMode::Filters { sample_rate, highpass_freq, lowpass_freq } => {
+                for (i, bin) in self.frame.iter_mut().enumerate() {
+                    let frequency = if i <= STFT_SIZE/2 {
+                        i as f32
+                    } else {
+                        i as f32 - STFT_SIZE as f32
+                    } * sample_rate / STFT_SIZE as f32;
+
+                    let freq_abs = frequency.abs();
+
+                    // Apply highpass and lowpass filters
+                    if freq_abs < highpass_freq || freq_abs > lowpass_freq {
+                        *bin = Complex::zero();
+                        continue;
+                    }
+
+                    if bin.norm() < 1e-10 {
+                        *bin = Complex::zero();
+                    }
+                }
+            }
+            SpectralMode::NoiseGate { threshold } => {
+                // Apply spectral noise gate
+                for bin in self.frame.iter_mut() {
+                    let magnitude = bin.norm();
+                    if magnitude < threshold {
+                        *bin = Complex::zero();
+                    }
+                }
+            }
+        }
+
+        self.ifft.process(&mut self.frame);
+
+        for i in 0..STFT_SIZE {
+            self.output[i] += self.frame[i].re * self.window[i] / STFT_SIZE as f32;
+            self.normalization[i] += self.window[i] * self.window[i];
+        }
+    }
+
+    // Queues `count` finished samples and shifts the buffers to the next frame
+    fn emit(&mut self, count: usize) {
+        for i in 0..count {
+            let sample = if self.normalization[i] > 1e-10 {
+                self.output[i] / self.normalization[i]
+            } else {
+                self.output[i]
+            };
+            self.pending.push_back(sample);
+        }
+
+        self.input.drain(..count);
+        self.output.copy_within(STFT_HOP.., 0);
+        self.output[STFT_SIZE - STFT_HOP..].fill(0.0);
+        self.normalization.copy_within(STFT_HOP.., 0);
+        self.normalization[STFT_SIZE - STFT_HOP..].fill(0.0);
+    }
+}
+
+/// FFT brick-wall highpass and lowpass filter.
+pub struct SpectralFilter {
+    stage: SpectralStage,
+}
+
+impl SpectralFilter {
+    pub fn new(sample_rate: f32, highpass_freq: f32, lowpass_freq: f32) -> Self {
+        Self {
+            stage: SpectralStage::new(SpectralMode::Filters { sample_rate, highpass_freq, lowpass_freq }),
+        }
+    }
+}
+
+impl Effect for SpectralFilter {
+    fn name(&self) -> &str {
+        "filters"
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        self.stage.process(samples);
+    }
+
+    fn reset(&mut self) {
+        self.stage.reset();
+    }
+
+    fn latency_samples(&self) -> usize {
+        STFT_SIZE - 1
+    }
+
+    fn flush(&mut self) -> Vec<f32> {
+        self.stage.flush()
+    }
+}
+
+/// Spectral noise gate that zeroes every FFT bin below a magnitude threshold.
+pub struct SpectralGate {
+    stage: SpectralStage,
+}
+
+impl SpectralGate {
+    pub fn new(threshold_db: f32) -> Self {
+        Self {
+            stage: SpectralStage::new(SpectralMode::NoiseGate { threshold: 10.0f32.powf(threshold_db / 20.0) }),
+        }
+    }
+}
+
+impl Effect for SpectralGate {
+    fn name(&self) -> &str {
+        "spectral_gate"
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        self.stage.process(samples);
+    }
+
+    fn reset(&mut self) {
+        self.stage.reset();
+    }
+
+    fn latency_samples(&self) -> usize {
+        STFT_SIZE - 1
+    }
+
+    fn flush(&mut self) -> Vec<f32> {
+        self.stage.flush()
+    }
+}
+
+/// Amplitude gate with lookahead and attack/release smoothing.
+pub struct AmplitudeGate {
+    threshold: f32,
+    attack_coef: f32,
+    release_coef: f32,
+    lookahead_samples: usize,
+    lookahead_buffer: VecDeque<f32>,
+    gate_gain: f32,
+}
+
+impl AmplitudeGate {
+    pub fn new(sample_rate: f32, threshold_db: f32, attack_ms: f32, release_ms: f32, lookahead_ms: f32) -> Self {
+        let lookahead_samples = (lookahead_ms / 1000.0 * sample_rate) as usize;
+
+        let mut gate = Self {
+            threshold: 10.0f32.powf(threshold_db / 20.0),
+            attack_coef: (-2.2 / (attack_ms / 1000.0 * sample_rate)).exp(),
+            release_coef: (-2.2 / (release_ms / 1000.0 * sample_rate)).exp(),
+            lookahead_samples,
+            lookahead_buffer: VecDeque::with_capacity(lookahead_samples + 1),
+            gate_gain: 0.0,
+        };
+        gate.reset();
+        gate
+    }
+}
+
+impl Effect for AmplitudeGate {
+    fn name(&self) -> &str {
+        "amplitude_gate"
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            self.lookahead_buffer.push_back(*sample);
+
+            // Find peak in lookahead window
+            let peak = self.lookahead_buffer.iter().map(|&s| s.abs()).fold(0.0, f32::max);
+
+            // Calculate target gate gain
+            let target_gain = if peak >= self.threshold { 1.0 } else { 0.0 };
+
+            // Apply attack/release smoothing
+            if target_gain > self.gate_gain {
+                self.gate_gain = self.gate_gain * self.attack_coef + target_gain * (1.0 - self.attack_coef);
+            } else {
+                self.gate_gain = self.gate_gain * self.release_coef + target_gain * (1.0 - self.release_coef);
+            }
+
+            // Apply gain to the oldest sample in buffer
+            let oldest_sample = self.lookahead_buffer.pop_front().unwrap_or(0.0);
+            *sample = oldest_sample * self.gate_gain;
+        }
+    }
+
+    fn reset(&mut self) {
+        // Pre-fill lookahead buffer
+        self.lookahead_buffer.clear();
+        self.lookahead_buffer.resize(self.lookahead_samples, 0.0);
+        self.gate_gain = 0.0;
+    }
+
+    fn latency_samples(&self) -> usize {
+        self.lookahead_samples
+    }
+}
+
+/// Fixed gain in dB.
+pub struct Gain {
+    gain_linear: f32,
+}
+
+impl Gain {
+    pub fn new(gain_db: f32) -> Self {
+        Self { gain_linear: 10.0f32.powf(gain_db / 20.0) }
+    }
+}
+
+impl Effect for Gain {
+    fn name(&self) -> &str {
+        "gain"
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            *sample *= self.gain_linear;
+        }
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Lookahead limiter that pulls peaks over the threshold to the ceiling.
+pub struct LookaheadLimiter {
+    threshold: f32,
+    ceiling: f32,
+    attack_coef: f32,
+    release_coef: f32,
+    lookahead_samples: usize,
+    lookahead_buffer: VecDeque<f32>,
+    limiter_gain: f32,
+}
+
+impl LookaheadLimiter {
+    pub fn new(
+        sample_rate: f32,
+        threshold_db: f32,
+        ceiling_db: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        lookahead_ms: f32,
+    ) -> Self {
+        let lookahead_samples = (lookahead_ms / 1000.0 * sample_rate) as usize;
+        let attack_samples = (attack_ms / 1000.0 * sample_rate).max(1.0);
+
+        let mut limiter = Self {
+            threshold: 10.0f32.powf(threshold_db / 20.0),
+            ceiling: 10.0f32.powf(ceiling_db / 20.0),
+            attack_coef: (-2.2 / attack_samples).exp(),
+            release_coef: (-2.2 / (release_ms / 1000.0 * sample_rate)).exp(),
+            lookahead_samples,
+            lookahead_buffer: VecDeque::with_capacity(lookahead_samples + 1),
+            limiter_gain: 1.0,
+        };
+        limiter.reset();
+        limiter
+    }
+}
+
+impl Effect for LookaheadLimiter {
+    fn name(&self) -> &str {
+        "limiter"
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            // Add sample to lookahead buffer
+            self.lookahead_buffer.push_back(*sample);
+
+            // Find peak in lookahead window
+            let peak = self.lookahead_buffer.iter().map(|&s| s.abs()).fold(0.0, f32::max);
+
+            // When the lookahead peak crosses threshold, target the ceiling.
+            let target_gain = if peak > self.threshold {
+                self.ceiling / peak
+            } else {
+                1.0
+            };
+
+            // Use attack when moving farther away from unity, release when relaxing.
+            let current_distance = (self.limiter_gain - 1.0).abs();
+            let target_distance = (target_gain - 1.0).abs();
+            if target_distance >= current_distance {
+                self.limiter_gain = self.limiter_gain * self.attack_coef + target_gain * (1.0 - self.attack_coef);
+            } else {
+                self.limiter_gain = self.limiter_gain * self.release_coef + target_gain * (1.0 - self.release_coef);
+            }
+
+            // Apply gain reduction to the oldest sample in buffer
+            let oldest_sample = self.lookahead_buffer.pop_front().unwrap_or(0.0);
+            *sample = (oldest_sample * self.limiter_gain).clamp(-self.ceiling, self.ceiling);
+        }
+    }
+
+    fn reset(&mut self) {
+        // Pre-fill lookahead buffer
+        self.lookahead_buffer.clear();
+        self.lookahead_buffer.resize(self.lookahead_samples, 0.0);
+        self.limiter_gain = 1.0;
+    }
+
+    fn latency_samples(&self) -> usize {
+        self.lookahead_samples
+    }
+}
+
+/// Root Mean Square (RMS) normalization with soft clipping.
+///
+/// The level is measured over each block it is given, so it is meant for
+/// offline use where the block is the whole recording.
+pub struct RmsNormalizer {
+    target_db: f32,
+}
+
+impl RmsNormalizer {
+    pub fn new(target_db: f32) -> Self {
+        Self { target_db }
+    }
+}
+
+impl Effect for RmsNormalizer {
+    fn name(&self) -> &str {
+        "rms"
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        // Calculate current RMS
+        if samples.is_empty() {
+            return;
+        }
+        let rms_current = (samples.iter().map(|&x| x * x).sum::<f32>() / samples.len() as f32).sqrt();
+        if rms_current == 0.0 {
+            // Silence has no level to normalize
+            return;
+        }
+        let rms_current_db = 20.0 * rms_current.log10();
+
+        // Convert target RMS from dB to linear
+        let target_rms = 10.0f32.powf(self.target_db / 20.0);
+
+        // Calculate gain factor
+        let gain_factor = target_rms / rms_current;
+
+        println!("RMS Normalization:");
+        println!("  Current RMS: {:.2} dB", rms_current_db);
+        println!("  Target RMS: {:.2} dB", self.target_db);
+        println!("  Gain factor: {:.2}x", gain_factor);
+
+        // Apply gain with peak limiting
+        for sample in samples.iter_mut() {
+            // Apply gain
+            *sample *= gain_factor;
+
+            // Apply soft clipping to prevent hard clipping
+            if *sample > 0.95 {
+                *sample = 0.95 + (1.0 - 0.95) * (1.0 - (1.0 - (*sample - 0.95) / (1.0 - 0.95)).powi(2));
+            } else if *sample < -0.95 {
+                *sample = -0.95 - (1.0 - 0.95) * (1.0 - (1.0 - (-*sample - 0.95) / (1.0 - 0.95)).powi(2));
+            }
+
+            // Hard limit as a safety measure
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+
+        // Calculate new RMS after normalization
+        let new_rms = (samples.iter().map(|&x| x * x).sum::<f32>() / samples.len() as f32).sqrt();
+        let new_rms_db = 20.0 * new_rms.log10();
+
+        println!("  New RMS after normalization: {:.2} dB", new_rms_db);
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Smoothstep fade-in that continues across blocks.
+pub struct FadeIn {
+    delay: usize,
+    length: usize,
+    position: usize,
+}
+
+impl FadeIn {
+    pub fn new(length: usize) -> Self {
+        Self::with_delay(length, 0)
+    }
+
+    /// Mutes the first `delay` samples, e.g. the latency of the effects before it,
+    /// and fades in from there.
+    pub fn with_delay(length: usize, delay: usize) -> Self {
+        Self { delay, length, position: 0 }
+    }
+}
+
+impl Effect for FadeIn {
+    fn name(&self) -> &str {
+        "fade_in"
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            if self.position >= self.delay + self.length {
+                break;
+            }
+            let gain = (self.position.saturating_sub(self.delay) as f32) / (self.length as f32);
+            // Use a smooth curve for the fade (cubic)
+            let smooth_gain = gain * gain * (3.0 - 2.0 * gain);
+            *sample *= smooth_gain;
+            self.position += 1;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chain, Effect, Gain, LookaheadLimiter, SpectralFilter};
+
+    // Adds a constant, so its position relative to `Gain` matters
+    struct Offset(f32);
+
+    impl Effect for Offset {
+        fn name(&self) -> &str {
+            "offset"
+        }
+
+        fn process(&mut self, samples: &mut [f32]) {
+            for sample in samples.iter_mut() {
+                *sample += self.0;
+            }
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    #[test]
+    fn custom_effects_run_in_chain_order() {
+        let mut chain = Chain::new().with(Offset(1.0)).with(Gain::new(20.0 * 2.0f32.log10()));
+        let mut samples = [0.0, 1.0];
+        chain.process(&mut samples);
+        assert!((samples[0] - 2.0).abs() < 1e-5 && (samples[1] - 4.0).abs() < 1e-5);
+
+        chain.effects_mut().swap(0, 1);
+        assert_eq!(chain.names(), ["gain", "offset"]);
+        let mut samples = [0.0, 1.0];
+        chain.process(&mut samples);
+        assert!((samples[0] - 1.0).abs() < 1e-5 && (samples[1] - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn offline_processing_removes_latency() {
+        let mut chain = Chain::new()
+            .with(SpectralFilter::new(48_000.0, 0.0, 24_000.0))
+            .with(LookaheadLimiter::new(48_000.0, 0.0, 0.0, 5.0, 50.0, 5.0));
+        assert_eq!(chain.latency_samples(), 4095 + 240);
+
+        let mut samples = vec![0.0; 10_000];
+        samples[3_000] = 0.5;
+        chain.process_offline(&mut samples);
+
+        let loudest = samples.iter().enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .map(|(i, _)| i);
+        assert_eq!(loudest, Some(3_000));
+        assert!((samples[3_000] - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn flush_returns_exactly_the_latency() {
+        let mut chain = Chain::new()
+            .with(SpectralFilter::new(48_000.0, 75.0, 20_000.0))
+            .with(Offset(0.0))
+            .with(LookaheadLimiter::new(48_000.0, -12.0, -2.0, 5.0, 50.0, 1.0));
+
+        let mut samples = vec![0.1; 1_000];
+        chain.process(&mut samples);
+        assert_eq!(chain.flush().len(), chain.latency_samples());
+    }
+}
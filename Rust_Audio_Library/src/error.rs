@@ -0,0 +1,147 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Error type returned by every public entry point in the library.
+///
+/// Callers can match on the variant to tell a missing device apart from a
+/// corrupt file or a codec failure instead of inspecting message strings.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RusticAudioError {
+    /// No input/output device is available, or the device refused to open a stream.
+    DeviceUnavailable(String),
+    /// The device or file uses a sample format this library cannot handle.
+    UnsupportedSampleFormat(String),
+    /// A WAV file could not be parsed or written.
+    WavParse(String),
+    /// An Ogg Opus stream could not be parsed.
+    OggParse(String),
+    /// The Opus codec rejected its settings or failed to encode/decode a packet.
+    Codec(String),
+    /// An argument or setting is outside the range this library accepts.
+    InvalidArgument(String),
+    /// A preset could not be parsed, has an unsupported version or holds an out-of-range value.
+    InvalidPreset(String),
+    /// Untrusted input exceeds a `ParseLimits` bound, such as its duration or packet size.
+    LimitExceeded(String),
+    /// An encoded stream would not fit the requested size, even at the lowest bitrate.
+    SizeBudgetExceeded { max_bytes: usize, smallest: usize },
+    /// A recording was captured, but processing or encoding it failed. The
+    /// capture is kept at `original_wav`; `source` is what went wrong.
+    ProcessingFailed { original_wav: PathBuf, source: Box<RusticAudioError> },
+    /// Another recording or playback is already in progress.
+    Busy,
+    /// The requested operation needs a recording or playback that is not running.
+    NotRunning,
+    /// A worker thread panicked before it could report a result.
+    ThreadPanicked,
+    /// Underlying file system error.
+    Io(std::io::Error),
+}
+
+pub type Result<T, E = RusticAudioError> = std::result::Result<T, E>;
+
+impl fmt::Display for RusticAudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeviceUnavailable(msg) => write!(f, "audio device unavailable: {}", msg),
+            Self::UnsupportedSampleFormat(msg) => write!(f, "unsupported sample format: {}", msg),
+            Self::WavParse(msg) => write!(f, "invalid WAV data: {}", msg),
+            Self::OggParse(msg) => write!(f, "invalid Ogg Opus data: {}", msg),
+            Self::Codec(msg) => write!(f, "Opus codec error: {}", msg),
+            Self::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            Self::InvalidPreset(msg) => write!(f, "invalid preset: {}", msg),
+            Self::LimitExceeded(msg) => write!(f, "input exceeds a limit: {}", msg),
+            Self::SizeBudgetExceeded { max_bytes, smallest } => write!(
+                f, "size budget of {} bytes cannot be met; the smallest encoding is {} bytes", max_bytes, smallest
+            ),
+            Self::ProcessingFailed { original_wav, source } => write!(
+                f, "the recording was kept at {}, but processing it failed: {}", original_wav.display(), source
+            ),
+            Self::Busy => f.write_str("another operation is already in progress"),
+            Self::NotRunning => f.write_str("no matching operation is in progress"),
+            Self::ThreadPanicked => f.write_str("worker thread panicked"),
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RusticAudioError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::ProcessingFailed { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RusticAudioError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<hound::Error> for RusticAudioError {
+    fn from(err: hound::Error) -> Self {
+        match err {
+            hound::Error::IoError(err) => Self::Io(err),
+            hound::Error::Unsupported => Self::UnsupportedSampleFormat(err.to_string()),
+            other => Self::WavParse(other.to_string()),
+        }
+    }
+}
+
+impl From<ogg::OggReadError> for RusticAudioError {
+    fn from(err: ogg::OggReadError) -> Self {
+        match err {
+            ogg::OggReadError::ReadError(err) => Self::Io(err),
+            other => Self::OggParse(other.to_string()),
+        }
+    }
+}
+
+macro_rules! device_error {
+    ($($err:ty),*) => {
+        $(
+            impl From<$err> for RusticAudioError {
+                fn from(err: $err) -> Self {
+                    Self::DeviceUnavailable(err.to_string())
+                }
+            }
+        )*
+    };
+}
+
+device_error!(
+    cpal::DefaultStreamConfigError,
+    cpal::DevicesError,
+    cpal::SupportedStreamConfigsError,
+    cpal::BuildStreamError,
+    cpal::PlayStreamError,
+    cpal::PauseStreamError
+);
+
+#[cfg(test)]
+mod tests {
+    use super::RusticAudioError;
+
+    #[test]
+    fn hound_io_errors_map_to_io() {
+        let err: RusticAudioError = hound::Error::IoError(std::io::Error::other("gone")).into();
+        assert!(matches!(err, RusticAudioError::Io(_)));
+    }
+
+    #[test]
+    fn hound_format_errors_map_to_wav_parse() {
+        let err: RusticAudioError = hound::Error::FormatError("no RIFF tag found").into();
+        assert!(matches!(err, RusticAudioError::WavParse(_)));
+    }
+
+    #[test]
+    fn missing_input_file_is_an_error_not_a_panic() {
+        let mut processor = crate::AudioProcessor::default();
+        let result = processor.process_file("does_not_exist.wav", "unused.wav");
+        assert!(matches!(result, Err(RusticAudioError::Io(_))));
+    }
+}
@@ -1,20 +1,54 @@
+mod error;
+mod device;
 mod record;
+mod session;
 mod playback;
 mod dsp;
+mod downmix;
+mod effect;
+mod streaming;
 mod opus_encoder;
+mod opus_decoder;
 mod opus_playback;
+mod player;
+mod opus_info;
+mod opus_packet;
+mod opus_tags;
+mod padding;
+mod resample;
+mod ring_buffer;
+mod preset;
+mod validate;
 
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::sync::Mutex;
-use crate::record::record_audio;
-use crate::playback::playback_audio;
-use crate::opus_playback::playback_opus;
+use std::time::Duration;
+use crate::record::RecordingLimit;
+use crate::opus_info::read_opus_info_with_limits;
+use crate::session::RecordingPipeline;
 
 // Keep these re-exports for public use
+pub use crate::error::{Result, RusticAudioError};
+pub use crate::device::{list_devices, DeviceConfigRange, DeviceDirection, DeviceInfo, DeviceSettings, HostInfo};
 pub use crate::dsp::AudioProcessor;
-pub use crate::opus_encoder::{OpusEncoder, OpusEncodingMode};
+pub use crate::record::{RecordingEvent, RecordingStats};
+pub use crate::session::{RecordingArtifacts, RecordingSession};
+pub use crate::downmix::Downmix;
+pub use crate::effect::{
+    AmplitudeGate, Chain, Effect, FadeIn, Gain, LookaheadLimiter, RmsNormalizer, SpectralFilter, SpectralGate,
+};
+pub use crate::streaming::StreamingProcessor;
+pub use crate::opus_encoder::{OpusApplication, OpusBandwidth, OpusEncoder, OpusEncodingMode, OpusFrameDuration, OpusSignal};
+pub use crate::opus_decoder::{decode_opus_bytes, decode_opus_to_samples, decode_opus_to_wav, DecodedAudio, OpusPacketDecoder};
+pub use crate::opus_info::{read_opus_info, FrameDuration, OpusFileInfo};
+pub use crate::opus_tags::{read_opus_tags, DspChainInfo, OpusTags, DSP_CHAIN, R128_TRACK_GAIN};
+pub use crate::player::{Player, PlayerEvent, PlayerState};
+pub use crate::padding::{pad_opus_file, pad_to_size, pad_to_size_class, strip_padding, DEFAULT_SIZE_CLASSES};
+pub use crate::resample::{resample, Resampler, ResamplerQuality};
+pub use crate::preset::{Preset, PresetFormat, PRESET_VERSION};
+pub use crate::validate::{read_wav_bytes, validate_opus, ParseLimits};
 
 #[derive(Clone)]
 pub struct AudioFileInfo {
@@ -24,6 +58,13 @@ pub struct AudioFileInfo {
     pub unprocessed_opus_size: u64,
     pub processed_opus_size: u64,
     pub last_message: String,
+    /// Captured and dropped samples of the last recording.
+    pub recording_stats: RecordingStats,
+    /// Files the last recording produced; `None` until one succeeds.
+    pub last_recording: Option<RecordingArtifacts>,
+    /// The capture of the last recording if processing or encoding it failed,
+    /// kept so the audio is not lost.
+    pub unprocessed_recording: Option<std::path::PathBuf>,
 }
 
 /// Main audio processing and recording library
@@ -32,14 +73,15 @@ pub struct AudioFileInfo {
 /// with various DSP effects and Opus compression.
 pub struct RusticAudio {
     is_recording: Arc<AtomicBool>,
-    is_playing: Arc<AtomicBool>,
-    is_playing_original: Arc<AtomicBool>,
-    is_playing_unprocessed_opus: Arc<AtomicBool>,
     recording_thread: Option<thread::JoinHandle<()>>,
-    playback_thread: Option<thread::JoinHandle<()>>,
-    playback_original_thread: Option<thread::JoinHandle<()>>,
-    playback_unprocessed_opus_thread: Option<thread::JoinHandle<()>>,
+    player: Option<Player>,
+    input_device: DeviceSettings,
+    output_device: DeviceSettings,
+    recording_limit: RecordingLimit,
     audio_info: Arc<Mutex<AudioFileInfo>>,
+    opus_size_budget: Option<usize>,
+    opus_size_classes: Option<Vec<usize>>,
+    tag_dsp_chain: bool,
     pub processor: AudioProcessor,
     pub opus_encoder: OpusEncoder,
 }
@@ -48,13 +90,11 @@ impl Default for RusticAudio {
     fn default() -> Self {
         Self {
             is_recording: Arc::new(AtomicBool::new(false)),
-            is_playing: Arc::new(AtomicBool::new(false)),
-            is_playing_original: Arc::new(AtomicBool::new(false)),
-            is_playing_unprocessed_opus: Arc::new(AtomicBool::new(false)),
             recording_thread: None,
-            playback_thread: None,
-            playback_original_thread: None,
-            playback_unprocessed_opus_thread: None,
+            player: None,
+            input_device: DeviceSettings::default(),
+            output_device: DeviceSettings::default(),
+            recording_limit: RecordingLimit::default(),
             audio_info: Arc::new(Mutex::new(AudioFileInfo {
                 file_size: 0,
                 duration: 0.0,
@@ -62,7 +102,13 @@ impl Default for RusticAudio {
                 unprocessed_opus_size: 0,
                 processed_opus_size: 0,
                 last_message: String::new(),
+                recording_stats: RecordingStats::default(),
+                last_recording: None,
+                unprocessed_recording: None,
             })),
+            opus_size_budget: None,
+            opus_size_classes: None,
+            tag_dsp_chain: false,
             processor: AudioProcessor::new(44100.0),
             opus_encoder: OpusEncoder::new(),
         }
@@ -75,82 +121,108 @@ impl RusticAudio {
         Self::default()
     }
 
-    /// Starts recording audio to the specified file path
-    /// 
+    /// Starts recording to `output_path`
+    ///
+    /// Once `stop_recording` returns, `output_path` holds the captured mono
+    /// 48 kHz WAV, and `last_recording` lists it with the files derived from it.
+    ///
     /// # Arguments
-    /// * `output_path` - The path where the processed audio will be saved
-    /// 
+    /// * `output_path` - Where the capture is written. Its directory and file stem
+    ///   also name the derived files: `out/take.wav` records to `out/take.wav` and
+    ///   `out/take_original.wav`, `out/take_processed.wav`, `out/take_processed.opus`
+    ///   and `out/take_unprocessed.opus`, replacing any earlier recording with the same name
+    ///
     /// # Returns
     /// * `Ok(())` if recording started successfully
-    /// * `Err(String)` with an error message if recording couldn't be started
-    pub fn start_recording(&mut self, output_path: &str) -> Result<(), String> {
-        if self.is_recording.load(Ordering::Relaxed) || 
-           self.is_playing.load(Ordering::Relaxed) || 
-           self.is_playing_original.load(Ordering::Relaxed) || 
-           self.is_playing_unprocessed_opus.load(Ordering::Relaxed) {
-            return Err("Another operation is already in progress".to_string());
+    /// * `Err(RusticAudioError::Busy)` if another recording or playback is running
+    pub fn start_recording(&mut self, output_path: &str) -> Result<()> {
+        let path = std::path::Path::new(output_path);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => std::path::Path::new("."),
+        };
+        let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("recording");
+        let file_name = path.file_name().and_then(|name| name.to_str())
+            .ok_or_else(|| RusticAudioError::InvalidArgument(format!("{} is not a file name", output_path)))?;
+        let session = RecordingSession::in_dir(dir)?.with_name(name)?.with_output_copy(file_name);
+        self.start_recording_session(session.overwrite(true))
+    }
+
+    /// Starts recording into `session`. Once `stop_recording` returns, the
+    /// files it produced are listed by `last_recording`.
+    ///
+    /// # Returns
+    /// * `Ok(())` if recording started successfully
+    /// * `Err(RusticAudioError::Busy)` if another recording or playback is running
+    /// * `Err(RusticAudioError::InvalidArgument)` if the session's files already exist and it may not overwrite them
+    pub fn start_recording_session(&mut self, session: RecordingSession) -> Result<()> {
+        if self.is_busy() {
+            return Err(RusticAudioError::Busy);
+        }
+        session.check_free()?;
+        // A recording that stopped at its limit is still joined here if nobody called `stop_recording`
+        if let Some(thread) = self.recording_thread.take() {
+            if thread.join().is_err() {
+                return Err(RusticAudioError::ThreadPanicked);
+            }
         }
 
+        let mut processed_encoder = self.opus_encoder.clone();
+        if self.tag_dsp_chain {
+            processed_encoder.tags_mut().set_dsp_chain(&self.processor)?;
+        }
+        let pipeline = RecordingPipeline {
+            processor: self.processor.clone(),
+            opus_encoder: self.opus_encoder.clone(),
+            processed_encoder,
+            size_budget: self.opus_size_budget,
+            size_classes: self.opus_size_classes.clone(),
+            input_device: self.input_device.clone(),
+            limit: self.recording_limit.clone(),
+        };
         let is_recording = Arc::clone(&self.is_recording);
         let audio_info = Arc::clone(&self.audio_info);
-        let processor = self.processor.clone();
-        let opus_encoder = self.opus_encoder.clone();
-        let output_path = output_path.to_string();
-        
+        {
+            let mut info = audio_info.lock().unwrap();
+            info.last_recording = None;
+            info.unprocessed_recording = None;
+        }
+
         self.is_recording.store(true, Ordering::Relaxed);
         self.recording_thread = Some(thread::spawn(move || {
-            if let Ok(_) = record_audio(&output_path, is_recording, processor.clone()) {
-                let mut info = audio_info.lock().unwrap();
-                info.last_message = "Recording completed successfully".to_string();
-                
-                // Copy output.wav to original.wav
-                let original_path = format!("{}_original.wav", output_path.trim_end_matches(".wav"));
-                if let Err(e) = std::fs::copy(&output_path, &original_path) {
-                    info.last_message = format!("Error copying to original file: {:?}", e);
-                    return;
-                }
-                
-                // Update original WAV file size
-                if let Ok(metadata) = std::fs::metadata(&original_path) {
-                    info.original_wav_size = metadata.len();
-                }
-                
-                // Process audio
-                let mut processor_instance = processor;
-                let processed_path = format!("{}_processed.wav", output_path.trim_end_matches(".wav"));
-                if let Err(e) = processor_instance.process_file(&output_path, &processed_path) {
-                    info.last_message = format!("Error processing audio: {:?}", e);
-                    return;
-                }
-                
-                // Encode to Opus
-                let processed_opus_path = format!("{}_processed.opus", output_path.trim_end_matches(".wav"));
-                if let Err(e) = opus_encoder.encode_wav_to_opus(&processed_path, &processed_opus_path) {
-                    info.last_message = format!("Error encoding to Opus: {:?}", e);
-                } else {
-                    // Update file info after successful encoding
-                    match opus_playback::get_opus_info(&processed_opus_path) {
-                        Ok((size, duration)) => {
-                            info.file_size = size;
-                            info.processed_opus_size = size;
-                            info.duration = duration;
-                            info.last_message = "Processing and Opus encoding completed successfully".to_string();
-                        }
-                        Err(e) => {
-                            info.last_message = format!("Error getting Opus file info: {:?}", e);
+            let result = session.record(is_recording, &pipeline);
+            let mut info = audio_info.lock().unwrap();
+            match result {
+                Ok((artifacts, stats)) => {
+                    let file_size = |path: &std::path::Path| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                    info.recording_stats = stats;
+                    info.original_wav_size = file_size(&artifacts.original_wav);
+                    info.unprocessed_opus_size = file_size(&artifacts.unprocessed_opus);
+                    let own_file = |path: &str| read_opus_info_with_limits(path, &ParseLimits::trusted());
+                    match artifacts.processed_opus.to_str().map(own_file) {
+                        Some(Ok(opus_info)) => {
+                            info.file_size = opus_info.file_size;
+                            info.processed_opus_size = opus_info.file_size;
+                            info.duration = opus_info.duration;
+                            info.last_message = if stats.has_gaps() {
+                                format!("Processing and Opus encoding completed; the recording lost {} samples in {} overruns",
+                                        stats.dropped_samples, stats.overruns)
+                            } else {
+                                "Processing and Opus encoding completed successfully".to_string()
+                            };
                         }
+                        Some(Err(e)) => info.last_message = format!("Error getting Opus file info: {:?}", e),
+                        None => info.last_message = "Error getting Opus file info: path is not UTF-8".to_string(),
                     }
+                    info.last_recording = Some(artifacts);
                 }
-                
-                // Also encode original to opus for comparison
-                let unprocessed_opus_path = format!("{}_unprocessed.opus", output_path.trim_end_matches(".wav"));
-                if let Err(e) = opus_encoder.encode_wav_to_opus(&original_path, &unprocessed_opus_path) {
-                    info.last_message = format!("Error encoding unprocessed audio: {:?}", e);
-                } else {
-                    // Update unprocessed opus file size
-                    if let Ok(metadata) = std::fs::metadata(&unprocessed_opus_path) {
-                        info.unprocessed_opus_size = metadata.len();
-                    }
+                Err(RusticAudioError::ProcessingFailed { original_wav, source }) => {
+                    info.original_wav_size = std::fs::metadata(&original_wav).map(|m| m.len()).unwrap_or(0);
+                    info.last_message = format!("Error processing recording, kept at {}: {:?}", original_wav.display(), source);
+                    info.unprocessed_recording = Some(original_wav);
+                }
+                Err(e) => {
+                    info.last_message = format!("Error recording audio: {:?}", e);
                 }
             }
         }));
@@ -158,9 +230,11 @@ impl RusticAudio {
         Ok(())
     }
 
-    pub fn stop_recording(&mut self) -> Result<(), String> {
-        if !self.is_recording.load(Ordering::Relaxed) {
-            return Err("Not currently recording".to_string());
+    /// Stops the recording and waits until its files are written. A recording
+    /// that already stopped at its maximum duration is waited for the same way.
+    pub fn stop_recording(&mut self) -> Result<()> {
+        if self.recording_thread.is_none() {
+            return Err(RusticAudioError::NotRunning);
         }
         
         self.is_recording.store(false, Ordering::Relaxed);
@@ -168,160 +242,194 @@ impl RusticAudio {
         // Wait for recording thread to finish
         if let Some(thread) = self.recording_thread.take() {
             if thread.join().is_err() {
-                return Err("Failed to join recording thread".to_string());
+                return Err(RusticAudioError::ThreadPanicked);
             }
         }
         
         Ok(())
     }
 
-    pub fn play_original_wav(&mut self, file_path: &str) -> Result<(), String> {
-        if self.is_recording.load(Ordering::Relaxed) || 
-           self.is_playing.load(Ordering::Relaxed) || 
-           self.is_playing_original.load(Ordering::Relaxed) || 
-           self.is_playing_unprocessed_opus.load(Ordering::Relaxed) {
-            return Err("Another operation is already in progress".to_string());
+    pub fn play_original_wav(&mut self, file_path: &str) -> Result<()> {
+        self.start_playback(file_path, "Original")
+    }
+
+    pub fn play_processed_wav(&mut self, file_path: &str) -> Result<()> {
+        self.start_playback(file_path, "Processed WAV")
+    }
+
+    pub fn play_unprocessed_opus(&mut self, file_path: &str) -> Result<()> {
+        self.start_playback(file_path, "Unprocessed opus")
+    }
+
+    pub fn play_processed_opus(&mut self, file_path: &str) -> Result<()> {
+        self.start_playback(file_path, "Processed opus")
+    }
+
+    // Plays a file with a fresh `Player`, reporting how it ends in `last_message`
+    fn start_playback(&mut self, file_path: &str, label: &'static str) -> Result<()> {
+        if self.is_busy() {
+            return Err(RusticAudioError::Busy);
         }
-        
-        let is_playing = Arc::clone(&self.is_playing_original);
+
+        let mut player = Player::open(file_path)?;
+        player.set_device(self.output_device.clone());
         let audio_info = Arc::clone(&self.audio_info);
-        let file_path = file_path.to_string();
-        
-        self.is_playing_original.store(true, Ordering::Relaxed);
-        self.playback_original_thread = Some(thread::spawn(move || {
-            match playback_audio(&file_path, is_playing) {
-                Ok(_) => {
-                    let mut info = audio_info.lock().unwrap();
-                    info.last_message = "Original playback completed successfully".to_string();
-                },
-                Err(e) => {
-                    let mut info = audio_info.lock().unwrap();
-                    info.last_message = format!("Error during original playback: {:?}", e);
-                },
-            }
-        }));
-        
+        player.on_event(move |event| {
+            let mut info = audio_info.lock().unwrap();
+            info.last_message = match event {
+                PlayerEvent::Finished => format!("{} playback completed successfully", label),
+                PlayerEvent::Failed(e) => format!("Error during {} playback: {:?}", label.to_lowercase(), e),
+            };
+        });
+        player.play()?;
+        self.player = Some(player);
+
         Ok(())
     }
 
-    pub fn play_processed_wav(&mut self, file_path: &str) -> Result<(), String> {
-        if self.is_recording.load(Ordering::Relaxed) || 
-           self.is_playing.load(Ordering::Relaxed) || 
-           self.is_playing_original.load(Ordering::Relaxed) || 
-           self.is_playing_unprocessed_opus.load(Ordering::Relaxed) {
-            return Err("Another operation is already in progress".to_string());
+    pub fn stop_playback(&mut self) -> Result<()> {
+        match self.player.take() {
+            Some(mut player) => player.stop(),
+            None => Ok(()),
         }
-        
-        let is_playing = Arc::clone(&self.is_playing);
-        let audio_info = Arc::clone(&self.audio_info);
-        let file_path = file_path.to_string();
-        
-        self.is_playing.store(true, Ordering::Relaxed);
-        self.playback_thread = Some(thread::spawn(move || {
-            match playback_audio(&file_path, is_playing) {
-                Ok(_) => {
-                    let mut info = audio_info.lock().unwrap();
-                    info.last_message = "Processed WAV playback completed successfully".to_string();
-                },
-                Err(e) => {
-                    let mut info = audio_info.lock().unwrap();
-                    info.last_message = format!("Error during processed WAV playback: {:?}", e);
-                },
-            }
-        }));
-        
-        Ok(())
     }
 
-    pub fn play_unprocessed_opus(&mut self, file_path: &str) -> Result<(), String> {
-        if self.is_recording.load(Ordering::Relaxed) || 
-           self.is_playing.load(Ordering::Relaxed) || 
-           self.is_playing_original.load(Ordering::Relaxed) || 
-           self.is_playing_unprocessed_opus.load(Ordering::Relaxed) {
-            return Err("Another operation is already in progress".to_string());
+    /// The player behind the current or last playback, for pausing, seeking
+    /// and volume control.
+    pub fn player(&self) -> Option<&Player> {
+        self.player.as_ref()
+    }
+
+    pub fn player_mut(&mut self) -> Option<&mut Player> {
+        self.player.as_mut()
+    }
+
+    /// Selects the microphone to record from by id or name, as `DeviceSettings::device`
+    /// accepts them; `None` uses the default input device.
+    ///
+    /// # Returns
+    /// * `Err(RusticAudioError::DeviceUnavailable)` if no input device matches
+    /// * `Err(RusticAudioError::InvalidArgument)` if the name matches several devices
+    pub fn set_input_device(&mut self, device: Option<&str>) -> Result<()> {
+        if let Some(device) = device {
+            device::find_device(Some(device), DeviceDirection::Input)?;
         }
-        
-        let is_playing = Arc::clone(&self.is_playing_unprocessed_opus);
-        let audio_info = Arc::clone(&self.audio_info);
-        let file_path = file_path.to_string();
-        
-        self.is_playing_unprocessed_opus.store(true, Ordering::Relaxed);
-        self.playback_unprocessed_opus_thread = Some(thread::spawn(move || {
-            match playback_opus(&file_path, is_playing) {
-                Ok(_) => {
-                    let mut info = audio_info.lock().unwrap();
-                    info.last_message = "Unprocessed opus playback completed successfully".to_string();
-                },
-                Err(e) => {
-                    let mut info = audio_info.lock().unwrap();
-                    info.last_message = format!("Error during unprocessed opus playback: {:?}", e);
-                },
-            }
-        }));
-        
+        self.input_device.device = device.map(str::to_string);
         Ok(())
     }
 
-    pub fn play_processed_opus(&mut self, file_path: &str) -> Result<(), String> {
-        if self.is_recording.load(Ordering::Relaxed) || 
-           self.is_playing.load(Ordering::Relaxed) || 
-           self.is_playing_original.load(Ordering::Relaxed) || 
-           self.is_playing_unprocessed_opus.load(Ordering::Relaxed) {
-            return Err("Another operation is already in progress".to_string());
+    pub fn get_input_device(&self) -> Option<&str> {
+        self.input_device.device.as_deref()
+    }
+
+    /// Selects the device to play on by id or name; `None` uses the default output device.
+    ///
+    /// # Returns
+    /// * `Err(RusticAudioError::DeviceUnavailable)` if no output device matches
+    /// * `Err(RusticAudioError::InvalidArgument)` if the name matches several devices
+    pub fn set_output_device(&mut self, device: Option<&str>) -> Result<()> {
+        if let Some(device) = device {
+            device::find_device(Some(device), DeviceDirection::Output)?;
         }
-        
-        let is_playing = Arc::clone(&self.is_playing);
-        let audio_info = Arc::clone(&self.audio_info);
-        let file_path = file_path.to_string();
-        
-        self.is_playing.store(true, Ordering::Relaxed);
-        self.playback_thread = Some(thread::spawn(move || {
-            match playback_opus(&file_path, is_playing) {
-                Ok(_) => {
-                    let mut info = audio_info.lock().unwrap();
-                    info.last_message = "Processed opus playback completed successfully".to_string();
-                },
-                Err(e) => {
-                    let mut info = audio_info.lock().unwrap();
-                    info.last_message = format!("Error during processed opus playback: {:?}", e);
-                },
-            }
-        }));
-        
+        self.output_device.device = device.map(str::to_string);
         Ok(())
     }
 
-    pub fn stop_playback(&mut self) -> Result<(), String> {
-        if self.is_playing.load(Ordering::Relaxed) {
-            self.is_playing.store(false, Ordering::Relaxed);
-            if let Some(thread) = self.playback_thread.take() {
-                if thread.join().is_err() {
-                    return Err("Failed to join playback thread".to_string());
-                }
-            }
+    pub fn get_output_device(&self) -> Option<&str> {
+        self.output_device.device.as_deref()
+    }
+
+    /// Sets the sample rate recording and playback streams open at when the
+    /// device supports it; `None` uses each device's default rate. Recordings
+    /// are still saved at 48 kHz.
+    pub fn set_preferred_sample_rate(&mut self, sample_rate: Option<u32>) -> Result<()> {
+        if sample_rate == Some(0) {
+            return Err(RusticAudioError::InvalidArgument("sample rate must be above 0".to_string()));
         }
-        
-        if self.is_playing_original.load(Ordering::Relaxed) {
-            self.is_playing_original.store(false, Ordering::Relaxed);
-            if let Some(thread) = self.playback_original_thread.take() {
-                if thread.join().is_err() {
-                    return Err("Failed to join original playback thread".to_string());
-                }
-            }
+        self.input_device.sample_rate = sample_rate;
+        self.output_device.sample_rate = sample_rate;
+        Ok(())
+    }
+
+    pub fn get_preferred_sample_rate(&self) -> Option<u32> {
+        self.output_device.sample_rate
+    }
+
+    /// Sets the frames per device callback for recording and playback; `None`
+    /// uses the host's default. Smaller buffers lower latency at the risk of dropouts.
+    pub fn set_buffer_size(&mut self, frames: Option<u32>) -> Result<()> {
+        if frames == Some(0) {
+            return Err(RusticAudioError::InvalidArgument("buffer size must be above 0".to_string()));
         }
-        
-        if self.is_playing_unprocessed_opus.load(Ordering::Relaxed) {
-            self.is_playing_unprocessed_opus.store(false, Ordering::Relaxed);
-            if let Some(thread) = self.playback_unprocessed_opus_thread.take() {
-                if thread.join().is_err() {
-                    return Err("Failed to join unprocessed opus playback thread".to_string());
-                }
-            }
+        self.input_device.buffer_size = frames;
+        self.output_device.buffer_size = frames;
+        Ok(())
+    }
+
+    pub fn get_buffer_size(&self) -> Option<u32> {
+        self.output_device.buffer_size
+    }
+
+    /// Stops recordings by themselves once they reach `max_duration`; `None`
+    /// records until `stop_recording`. Capture ends on the exact sample, then
+    /// the recording is processed and encoded as usual. `is_recording` turns
+    /// false at the limit, and `stop_recording` waits for the files.
+    ///
+    /// # Returns
+    /// * `Err(RusticAudioError::InvalidArgument)` if `max_duration` is zero
+    pub fn set_max_recording_duration(&mut self, max_duration: Option<Duration>) -> Result<()> {
+        if max_duration == Some(Duration::ZERO) {
+            return Err(RusticAudioError::InvalidArgument("maximum recording duration must be above 0".to_string()));
         }
-        
+        self.recording_limit.max_duration = max_duration;
         Ok(())
     }
 
+    pub fn get_max_recording_duration(&self) -> Option<Duration> {
+        self.recording_limit.max_duration
+    }
+
+    /// Sends `RecordingEvent::Warning` once this much time is left before the
+    /// maximum duration; `None` sends no warning.
+    pub fn set_recording_warning(&mut self, warn_before: Option<Duration>) {
+        self.recording_limit.warn_before = warn_before;
+    }
+
+    pub fn get_recording_warning(&self) -> Option<Duration> {
+        self.recording_limit.warn_before
+    }
+
+    /// Sets the handler for the time left in recordings with a maximum
+    /// duration. It runs on the recording thread, replaces any earlier
+    /// handler, and applies to the running recording too.
+    ///
+    /// ```no_run
+    /// use rustic_audio_tool::{RecordingEvent, RusticAudio};
+    /// use std::time::Duration;
+    ///
+    /// let mut audio = RusticAudio::new();
+    /// audio.set_max_recording_duration(Some(Duration::from_secs(30)))?;
+    /// audio.set_recording_warning(Some(Duration::from_secs(5)));
+    /// audio.on_recording_event(|event| match event {
+    ///     RecordingEvent::Remaining(left) => println!("{:.1} s left", left.as_secs_f32()),
+    ///     RecordingEvent::Warning(_) => println!("Wrap it up"),
+    ///     RecordingEvent::LimitReached => println!("Time's up"),
+    /// });
+    /// audio.start_recording("message.wav")?;
+    /// # Ok::<(), rustic_audio_tool::RusticAudioError>(())
+    /// ```
+    pub fn on_recording_event<F>(&self, handler: F)
+    where
+        F: FnMut(RecordingEvent) + Send + 'static,
+    {
+        self.recording_limit.events.set(Box::new(handler));
+    }
+
+    /// Files the last successful recording produced.
+    pub fn last_recording(&self) -> Option<RecordingArtifacts> {
+        self.audio_info.lock().unwrap().last_recording.clone()
+    }
+
     pub fn get_audio_info(&self) -> AudioFileInfo {
         self.audio_info.lock().unwrap().clone()
     }
@@ -342,20 +450,204 @@ impl RusticAudio {
         self.opus_encoder.get_mode()
     }
 
+    /// Sets what the Opus encoder is tuned for; see `OpusApplication`.
+    pub fn set_opus_application(&mut self, application: OpusApplication) {
+        self.opus_encoder.set_application(application);
+    }
+
+    pub fn get_opus_application(&self) -> OpusApplication {
+        self.opus_encoder.get_application()
+    }
+
+    /// Sets the Opus content hint; see `OpusSignal`.
+    pub fn set_opus_signal(&mut self, signal: OpusSignal) {
+        self.opus_encoder.set_signal(signal);
+    }
+
+    pub fn get_opus_signal(&self) -> OpusSignal {
+        self.opus_encoder.get_signal()
+    }
+
+    /// Sets the Opus encoder complexity, 0 to 10.
+    pub fn set_opus_complexity(&mut self, complexity: i32) {
+        self.opus_encoder.set_complexity(complexity);
+    }
+
+    pub fn get_opus_complexity(&self) -> i32 {
+        self.opus_encoder.get_complexity()
+    }
+
+    /// Sets the duration of the audio in each Opus packet, 2.5 to 120 ms.
+    pub fn set_opus_frame_duration(&mut self, duration: OpusFrameDuration) {
+        self.opus_encoder.set_frame_duration(duration);
+    }
+
+    pub fn get_opus_frame_duration(&self) -> OpusFrameDuration {
+        self.opus_encoder.get_frame_duration()
+    }
+
+    /// Turns Opus in-band forward error correction on or off.
+    pub fn set_opus_inband_fec(&mut self, enabled: bool) {
+        self.opus_encoder.set_inband_fec(enabled);
+    }
+
+    pub fn get_opus_inband_fec(&self) -> bool {
+        self.opus_encoder.get_inband_fec()
+    }
+
+    /// Sets the packet loss the Opus encoder plans for, in percent (0-100).
+    pub fn set_opus_packet_loss_perc(&mut self, percent: u8) {
+        self.opus_encoder.set_packet_loss_perc(percent);
+    }
+
+    pub fn get_opus_packet_loss_perc(&self) -> u8 {
+        self.opus_encoder.get_packet_loss_perc()
+    }
+
+    /// Turns Opus discontinuous transmission on or off.
+    pub fn set_opus_dtx(&mut self, enabled: bool) {
+        self.opus_encoder.set_dtx(enabled);
+    }
+
+    pub fn get_opus_dtx(&self) -> bool {
+        self.opus_encoder.get_dtx()
+    }
+
+    #[deprecated(note = "VBR quality was always the encoder complexity; use `set_opus_complexity`")]
     pub fn set_opus_vbr_quality(&mut self, quality: i32) {
-        self.opus_encoder.set_vbr_quality(quality);
+        self.opus_encoder.set_complexity(quality);
     }
 
+    #[deprecated(note = "VBR quality was always the encoder complexity; use `get_opus_complexity`")]
     pub fn get_opus_vbr_quality(&self) -> i32 {
-        self.opus_encoder.get_vbr_quality()
+        self.opus_encoder.get_complexity()
+    }
+
+    pub fn set_opus_sample_rate(&mut self, sample_rate: u32) {
+        self.opus_encoder.set_sample_rate(sample_rate);
+    }
+
+    pub fn get_opus_sample_rate(&self) -> u32 {
+        self.opus_encoder.get_sample_rate()
     }
 
-    pub fn process_file(&mut self, input_path: &str, output_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    /// Limits the Opus audio bandwidth, lowering the encoding rate to match.
+    pub fn set_opus_max_bandwidth(&mut self, bandwidth: OpusBandwidth) {
+        self.opus_encoder.set_max_bandwidth(bandwidth);
+    }
+
+    pub fn get_opus_max_bandwidth(&self) -> OpusBandwidth {
+        self.opus_encoder.get_max_bandwidth()
+    }
+
+    /// Sets the resampler quality used when recordings and encodes change sample rate.
+    pub fn set_resampler_quality(&mut self, quality: ResamplerQuality) {
+        self.opus_encoder.set_resampler_quality(quality);
+    }
+
+    pub fn get_resampler_quality(&self) -> ResamplerQuality {
+        self.opus_encoder.get_resampler_quality()
+    }
+
+    /// Sets how multichannel recordings and WAV files are reduced to mono.
+    ///
+    /// Applies to the recorder, the Opus encoder and, when
+    /// `processor.downmix_enabled` is set, `process_file`.
+    pub fn set_downmix(&mut self, downmix: Downmix) {
+        self.processor.downmix = downmix;
+        self.opus_encoder.set_downmix(downmix);
+    }
+
+    pub fn get_downmix(&self) -> Downmix {
+        self.processor.downmix
+    }
+
+    /// Caps the size of the processed Opus file written by recordings and `encode_to_opus`.
+    ///
+    /// With a budget set, encoding fails with `RusticAudioError::SizeBudgetExceeded`
+    /// rather than produce a larger file. `None` removes the cap.
+    pub fn set_opus_size_budget(&mut self, max_bytes: Option<usize>) {
+        self.opus_size_budget = max_bytes;
+    }
+
+    pub fn get_opus_size_budget(&self) -> Option<usize> {
+        self.opus_size_budget
+    }
+
+    /// Pads the processed Opus file up to the smallest of `size_classes` that holds it.
+    ///
+    /// All messages in one class then have the same byte length, hiding their
+    /// duration. `Some(DEFAULT_SIZE_CLASSES.to_vec())` gives 8/16/32/64 KiB;
+    /// `None` turns padding off. A file larger than every class fails with
+    /// `RusticAudioError::SizeBudgetExceeded`, so combine this with
+    /// `set_opus_size_budget` to keep long recordings inside the largest class.
+    pub fn set_opus_size_classes(&mut self, size_classes: Option<Vec<usize>>) {
+        self.opus_size_classes = size_classes;
+    }
+
+    pub fn get_opus_size_classes(&self) -> Option<&[usize]> {
+        self.opus_size_classes.as_deref()
+    }
+
+    /// Sets the OpusTags comments written into every Opus file, e.g. language or message id.
+    pub fn set_opus_tags(&mut self, tags: OpusTags) {
+        self.opus_encoder.set_tags(tags);
+    }
+
+    pub fn get_opus_tags(&self) -> &OpusTags {
+        self.opus_encoder.get_tags()
+    }
+
+    /// Records the processor's effect chain and settings under `RUSTIC_DSP_CHAIN`
+    /// in the processed Opus file of each recording. The unprocessed file is not tagged.
+    pub fn set_tag_dsp_chain(&mut self, enabled: bool) {
+        self.tag_dsp_chain = enabled;
+    }
+
+    pub fn get_tag_dsp_chain(&self) -> bool {
+        self.tag_dsp_chain
+    }
+
+    /// Validates `preset` and replaces the processor and Opus settings with it.
+    pub fn apply_preset(&mut self, preset: &Preset) -> Result<()> {
+        preset.validate()?;
+        preset.apply(&mut self.processor, &mut self.opus_encoder);
+        Ok(())
+    }
+
+    /// Applies a built-in preset by name, or a preset file (TOML, or JSON by extension).
+    pub fn load_preset(&mut self, name_or_path: &str) -> Result<()> {
+        let preset = Preset::resolve(name_or_path)?;
+        self.apply_preset(&preset)
+    }
+
+    /// Captures the current processor and Opus settings as a preset.
+    pub fn current_preset(&self, name: &str) -> Preset {
+        Preset::from_settings(name, &self.processor, &self.opus_encoder)
+    }
+
+    /// Saves the current settings to `path` (TOML, or JSON by extension).
+    pub fn save_preset(&self, path: &str) -> Result<()> {
+        let name = std::path::Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        self.current_preset(name).save(path)
+    }
+
+    pub fn process_file(&mut self, input_path: &str, output_path: &str) -> Result<()> {
         self.processor.process_file(input_path, output_path)
     }
 
-    pub fn encode_to_opus(&self, input_path: &str, output_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        self.opus_encoder.encode_wav_to_opus(input_path, output_path)
+    pub fn encode_to_opus(&self, input_path: &str, output_path: &str) -> Result<()> {
+        match self.opus_size_budget {
+            Some(max_bytes) => self.opus_encoder.encode_wav_to_opus_with_size_budget(input_path, output_path, max_bytes)?,
+            None => self.opus_encoder.encode_wav_to_opus(input_path, output_path)?,
+        }
+        if let Some(classes) = &self.opus_size_classes {
+            padding::pad_opus_file(output_path, classes)?;
+        }
+        Ok(())
     }
 
     pub fn is_recording(&self) -> bool {
@@ -363,9 +655,14 @@ impl RusticAudio {
     }
 
     pub fn is_playing(&self) -> bool {
-        self.is_playing.load(Ordering::Relaxed) || 
-        self.is_playing_original.load(Ordering::Relaxed) || 
-        self.is_playing_unprocessed_opus.load(Ordering::Relaxed)
+        self.player.as_ref()
+            .is_some_and(|player| matches!(player.state(), PlayerState::Playing | PlayerState::Paused))
+    }
+
+    fn is_busy(&self) -> bool {
+        // A recording that stopped at its limit is busy until it has been processed
+        let processing = self.recording_thread.as_ref().is_some_and(|thread| !thread.is_finished());
+        self.is_recording() || processing || self.is_playing()
     }
 }
 
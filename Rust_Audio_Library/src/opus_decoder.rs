@@ -0,0 +1,662 @@
+use ogg::reading::PacketReader;
+use opus_rs::range_coder::RangeCoder;
+use opus_rs::silk::dec_api::SilkDecoder;
+use opus_rs::silk::decode_frame::{FLAG_DECODE_LBRR, FLAG_DECODE_NORMAL};
+use opus_rs::{OpusDecoder, SilkResampler};
+use std::io::Cursor;
+use crate::error::{Result, RusticAudioError};
+use crate::opus_packet::{frame_payloads, is_celt_only, packet_frames, packet_samples};
+use crate::resample::{resample, ResamplerQuality};
+use crate::validate::{check_opus, read_opus_file, OpusHead, ParseLimits};
+
+const SAMPLE_RATE: u32 = 48_000;
+// Longest SILK frame opus-rs's OpusDecoder decodes in full, 20 ms at 48 kHz
+const FRAME_SIZE: usize = 960;
+
+/// Audio decoded from an Ogg Opus file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedAudio {
+    /// Samples, interleaved when there is more than one channel.
+    pub samples: Vec<f32>,
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+// A stream decoded at the Opus rate of 48 kHz, with what OpusHead says about it
+struct DecodedStream {
+    samples: Vec<f32>,
+    channels: usize,
+    input_rate: u32,
+}
+
+/// Decodes a complete Ogg Opus stream held in memory.
+///
+/// Returns the decoded samples (interleaved when the stream has more than one
+/// channel) together with their sample rate. No audio device or file is used.
+/// The encoder delay given by the OpusHead pre-skip is dropped from the start,
+/// and the end is trimmed to the final granule position, so the result has
+/// exactly the length of the audio that was encoded.
+///
+/// Stereo streams decode only when every packet is CELT-only; stereo SILK and
+/// hybrid packets are a `RusticAudioError::Codec` error (see `OpusPacketDecoder`).
+///
+/// The stream is checked against `ParseLimits::default()` before decoding.
+pub fn decode_opus_bytes(data: &[u8]) -> Result<(Vec<f32>, u32)> {
+    let summary = check_opus(data, &ParseLimits::default())?;
+    let decoded = decode_stream(data, summary.head, summary.end_granule)?;
+    Ok((decoded.samples, SAMPLE_RATE))
+}
+
+/// Decodes the Ogg Opus file at `path` without an audio device.
+///
+/// The audio comes back at the input sample rate recorded in OpusHead, with
+/// the channel count of the stream, the pre-skip removed and the output gain
+/// applied. Streams that do not record an input rate are returned at 48 kHz.
+/// Stereo is supported for CELT-only streams, as in `decode_opus_bytes`.
+pub fn decode_opus_to_samples(path: &str) -> Result<DecodedAudio> {
+    let (stream, summary) = read_opus_file(path, &ParseLimits::default())?;
+    let decoded = decode_stream(&stream, summary.head, summary.end_granule)?;
+    let rate = decoded.input_rate;
+    Ok(resample_stream(decoded, rate))
+}
+
+/// Decodes the Ogg Opus file at `input` to a 16-bit WAV file at `output`.
+///
+/// `target_rate` picks the WAV sample rate; `None` uses the input rate recorded
+/// in OpusHead, like `decode_opus_to_samples`.
+pub fn decode_opus_to_wav(input: &str, output: &str, target_rate: Option<u32>) -> Result<()> {
+    if target_rate == Some(0) {
+        return Err(RusticAudioError::InvalidArgument("sample rate must be greater than zero".to_string()));
+    }
+
+    let (stream, summary) = read_opus_file(input, &ParseLimits::default())?;
+    let decoded = decode_stream(&stream, summary.head, summary.end_granule)?;
+    let rate = target_rate.unwrap_or(decoded.input_rate);
+    let audio = resample_stream(decoded, rate);
+
+    let spec = hound::WavSpec {
+        channels: audio.channels,
+        sample_rate: audio.sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(output, spec)?;
+    for &sample in &audio.samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * 32767.0) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+/// Decodes raw Opus packets, one at a time, to 48 kHz samples.
+///
+/// Packets may hold any number of frames. Frames without data, which an
+/// encoder sends in discontinuous transmission (DTX), decode to silence. A
+/// lost packet is replaced with `decode_lost`, which recovers it from the
+/// in-band FEC data of the packet after it when there is any.
+///
+/// Stereo is only supported for CELT-only packets: opus-rs decodes the mid
+/// channel of SILK and hybrid frames alone, so stereo packets in those modes
+/// are a `RusticAudioError::Codec` error rather than mid copied into both
+/// channels.
+pub struct OpusPacketDecoder {
+    channels: usize,
+    decoder: OpusDecoder,
+    // Follows the SILK layer of every SILK and hybrid frame, only so that FEC
+    // data can be decoded against the current state
+    silk: SilkDecoder,
+    resampler: SilkResampler,
+    resampler_rate: i32,
+    // Samples per channel of the last packet; a lost packet is taken to be as long
+    last_samples: usize,
+}
+
+impl OpusPacketDecoder {
+    /// Creates a decoder for a stream of 1 or 2 channels.
+    pub fn new(channels: u16) -> Result<Self> {
+        let decoder = OpusDecoder::new(SAMPLE_RATE as i32, channels as usize)
+            .map_err(|e| RusticAudioError::Codec(e.to_string()))?;
+        let mut silk = SilkDecoder::new();
+        silk.init(16_000, channels as i32);
+        silk.channel_state[0].fs_api_hz = SAMPLE_RATE as i32;
+
+        Ok(Self {
+            channels: channels as usize,
+            decoder,
+            silk,
+            resampler: SilkResampler::default(),
+            resampler_rate: 0,
+            last_samples: 0,
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    /// Decodes one packet to interleaved samples, as many as its TOC byte says it holds.
+    pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<f32>> {
+        let invalid = || RusticAudioError::Codec("invalid Opus packet".to_string());
+        let (frame_samples, _) = packet_frames(packet).ok_or_else(invalid)?;
+        let payloads = frame_payloads(packet).ok_or_else(invalid)?;
+        if payloads.is_empty() {
+            return Err(invalid());
+        }
+        let stereo = packet[0] & 0x04 != 0;
+        if (stereo as usize + 1) != self.channels {
+            return Err(RusticAudioError::Codec(format!(
+                "{} packet in a {} channel stream", if stereo { "stereo" } else { "mono" }, self.channels
+            )));
+        }
+        let config = packet[0] >> 3;
+        // Without the side channel and the mid/side unmixing of RFC 6716
+        // section 4.2.8 both channels would just be the mid signal
+        if stereo && config < 16 {
+            return Err(RusticAudioError::Codec(
+                "stereo SILK and hybrid packets are not supported, only stereo CELT".to_string(),
+            ));
+        }
+        // Each frame is decoded on its own, as a single frame packet
+        let toc = packet[0] & !0x03;
+        let mut output = vec![0f32; frame_samples * payloads.len() * self.channels];
+        for (payload, frame) in payloads.iter().zip(output.chunks_mut(frame_samples * self.channels)) {
+            if payload.is_empty() {
+                continue;
+            }
+            // opus-rs's OpusDecoder decodes only the first 20 ms of a 40 or
+            // 60 ms SILK frame, so those go through the SILK decoder alone
+            if config < 12 && frame_samples > FRAME_SIZE {
+                self.decode_silk(toc, payload, FLAG_DECODE_NORMAL, frame)?;
+                continue;
+            }
+            if config < 16 {
+                let mut silk_layer = vec![0f32; frame.len()];
+                self.decode_silk(toc, payload, FLAG_DECODE_NORMAL, &mut silk_layer)?;
+            }
+            let mut single = Vec::with_capacity(payload.len() + 1);
+            single.push(toc);
+            single.extend_from_slice(payload);
+            self.decoder.decode(&single, frame_samples, frame)
+                .map_err(|e| RusticAudioError::Codec(e.to_string()))?;
+        }
+
+        self.last_samples = output.len() / self.channels;
+        Ok(output)
+    }
+
+    /// Produces the samples of a packet that never arrived.
+    ///
+    /// `next` is the packet that follows the lost one, if it has arrived. When it
+    /// carries in-band FEC data (SILK or hybrid packets from an encoder with FEC
+    /// on and a packet loss percentage above zero) the end of the lost packet is
+    /// rebuilt from it; everything else is silence. Pass `next` to `decode`
+    /// afterwards as usual. The lost packet is taken to be as long as the one
+    /// before it.
+    pub fn decode_lost(&mut self, next: Option<&[u8]>) -> Result<Vec<f32>> {
+        let samples = match self.last_samples {
+            0 => next.and_then(packet_samples).unwrap_or(0),
+            samples => samples,
+        };
+        let mut output = vec![0f32; samples * self.channels];
+
+        let Some(next) = next.filter(|packet| !is_celt_only(packet)) else {
+            return Ok(output);
+        };
+        let (Some((frame_samples, _)), Some(payloads)) = (packet_frames(next), frame_payloads(next)) else {
+            return Ok(output);
+        };
+        if let Some(payload) = payloads.first().filter(|payload| !payload.is_empty()) {
+            if frame_samples <= samples.min(FRAME_SIZE) {
+                let start = (samples - frame_samples) * self.channels;
+                self.decode_silk(next[0] & !0x03, payload, FLAG_DECODE_LBRR, &mut output[start..])?;
+            }
+        }
+        Ok(output)
+    }
+
+    // Decodes the SILK layer of one frame into `output`, resampled to 48 kHz.
+    // SILK codes 40 and 60 ms frames as 20 ms ones; FEC data of a frame with
+    // none decodes to silence
+    fn decode_silk(&mut self, toc: u8, payload: &[u8], flag: i32, output: &mut [f32]) -> Result<()> {
+        if self.channels == 2 {
+            return Err(RusticAudioError::Codec(
+                "stereo SILK and hybrid packets are not supported, only stereo CELT".to_string(),
+            ));
+        }
+
+        let config = (toc >> 3) as usize;
+        let rate = if config < 12 { [8_000, 12_000, 16_000][config / 4] } else { 16_000 };
+        let frame_ms = (output.len() / self.channels / 48) as i32;
+        let sub_frames = (frame_ms / 20).max(1) as usize;
+        let sub_len = (rate * frame_ms.min(20) / 1000) as usize;
+
+        if rate != self.resampler_rate {
+            self.resampler.init(rate, SAMPLE_RATE as i32);
+            self.resampler_rate = rate;
+        }
+
+        let mut range_decoder = RangeCoder::new_decoder(payload);
+        let mut pcm = vec![0i16; sub_len];
+        let mut resampled = vec![0i16; sub_len * SAMPLE_RATE as usize / rate as usize];
+        for (index, out) in output.chunks_mut(resampled.len()).enumerate() {
+            if index >= sub_frames {
+                break;
+            }
+            if self.silk.decode(&mut range_decoder, &mut pcm, flag, index == 0, frame_ms, rate) < 0 {
+                return Err(RusticAudioError::Codec("SILK decoding failed".to_string()));
+            }
+            self.resampler.process(&mut resampled, &pcm, sub_len as i32);
+            for (sample, &pcm) in out.iter_mut().zip(&resampled) {
+                *sample = pcm as f32 / 32768.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Decodes a stream that has already passed `check_opus`
+fn decode_stream(stream: &[u8], head: OpusHead, end: u64) -> Result<DecodedStream> {
+    let mut packet_reader = PacketReader::new(Cursor::new(stream));
+    let channels = head.channels as usize;
+    let pre_skip = head.pre_skip as u64;
+    let gain_db = head.output_gain_db();
+
+    packet_reader.read_packet()?; // OpusHead
+    packet_reader.read_packet()?; // OpusTags
+
+    let mut decoder = OpusPacketDecoder::new(head.channels as u16)?;
+    let mut samples = Vec::new();
+    // Samples per channel decoded so far, counted like granule positions
+    let mut position = 0u64;
+
+    while let Some(packet) = packet_reader.read_packet()? {
+        // Older streams end with an empty packet on their end-of-stream page
+        if packet.data.is_empty() {
+            continue;
+        }
+
+        let decoded = decoder.decode(&packet.data)?;
+        let len = decoded.len() / channels;
+        let (from, to) = playable_range(position, len, pre_skip, u64::MAX);
+        samples.extend_from_slice(&decoded[from * channels..to * channels]);
+        position += len as u64;
+    }
+
+    let playable = end.saturating_sub(pre_skip);
+    if playable < (samples.len() / channels) as u64 {
+        samples.truncate(playable as usize * channels);
+    }
+
+    if gain_db != 0.0 {
+        let gain = 10f32.powf(gain_db / 20.0);
+        for sample in samples.iter_mut() {
+            *sample *= gain;
+        }
+    }
+
+    Ok(DecodedStream {
+        samples,
+        channels,
+        // Zero means the encoder did not record its input rate
+        input_rate: if head.input_sample_rate == 0 { SAMPLE_RATE } else { head.input_sample_rate },
+    })
+}
+
+// Converts a decoded stream to `rate`, one channel at a time
+fn resample_stream(stream: DecodedStream, rate: u32) -> DecodedAudio {
+    let channels = stream.channels;
+    let samples = if rate == SAMPLE_RATE {
+        stream.samples
+    } else {
+        let resampled: Vec<Vec<f32>> = (0..channels)
+            .map(|channel| {
+                let plane: Vec<f32> = stream.samples.iter().skip(channel).step_by(channels).copied().collect();
+                resample(&plane, SAMPLE_RATE, rate, ResamplerQuality::default())
+            })
+            .collect();
+        (0..resampled[0].len())
+            .flat_map(|i| resampled.iter().map(move |plane| plane[i]))
+            .collect()
+    };
+
+    DecodedAudio {
+        samples,
+        channels: channels as u16,
+        sample_rate: rate,
+    }
+}
+
+/// Part of a decoded packet that is played, as a sample range within the packet.
+///
+/// `position` is the number of samples decoded before the packet and `len` the
+/// number it decoded to. Samples before `pre_skip` and from `end` (the final
+/// granule position) onwards are discarded.
+pub(crate) fn playable_range(position: u64, len: usize, pre_skip: u64, end: u64) -> (usize, usize) {
+    let clamp = |granule: u64| granule.saturating_sub(position).min(len as u64) as usize;
+    let from = clamp(pre_skip);
+    (from, clamp(end).max(from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_opus_bytes, decode_opus_to_samples, decode_opus_to_wav, playable_range, OpusPacketDecoder};
+    use crate::opus_packet::{frame_payloads, packet_frames};
+    use crate::{OpusEncoder, RusticAudioError};
+    use ogg::reading::PacketReader;
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+    use opus_rs::{Application, OpusDecoder, OpusEncoder as CodecEncoder};
+    use opus_rs::range_coder::RangeCoder;
+    use opus_rs::silk::control_codec::silk_control_encoder;
+    use opus_rs::silk::init_encoder::silk_init_encoder;
+    use opus_rs::silk::{silk_encode, SilkEncoderState};
+    use std::io::Cursor;
+
+    #[test]
+    fn in_memory_round_trip_keeps_duration() {
+        let samples: Vec<f32> = (0..48_000)
+            .map(|i| (i as f32 * 440.0 * 2.0 * std::f32::consts::PI / 48_000.0).sin() * 0.5)
+            .collect();
+
+        let encoded = OpusEncoder::new().encode_samples(&samples, 48_000).unwrap();
+        assert_eq!(&encoded[..4], b"OggS");
+
+        let (decoded, rate) = decode_opus_bytes(&encoded).unwrap();
+        assert_eq!(rate, 48_000);
+        assert_eq!(decoded.len(), samples.len());
+    }
+
+    #[test]
+    fn lower_encoder_rates_still_decode_at_48k() {
+        let samples = vec![0.1f32; 16_000];
+        let mut encoder = OpusEncoder::new();
+        encoder.set_sample_rate(16_000);
+
+        let encoded = encoder.encode_samples(&samples, 16_000).unwrap();
+        let (decoded, rate) = decode_opus_bytes(&encoded).unwrap();
+        assert_eq!(rate, 48_000);
+        assert_eq!(decoded.len(), 48_000);
+    }
+
+    #[test]
+    fn round_trip_is_sample_exact() {
+        // Lengths that are not whole frames, including shorter than the lookahead
+        for len in [48_123, 961, 100, 0] {
+            let samples: Vec<f32> = (0..len).map(|i| ((i * 7919) % 200) as f32 / 1000.0 - 0.1).collect();
+            let encoded = OpusEncoder::new().encode_samples(&samples, 48_000).unwrap();
+            assert_eq!(decode_opus_bytes(&encoded).unwrap().0.len(), len);
+        }
+
+        let mut encoder = OpusEncoder::new();
+        encoder.set_sample_rate(16_000);
+        let encoded = encoder.encode_samples(&vec![0.1f32; 16_007], 16_000).unwrap();
+        assert_eq!(decode_opus_bytes(&encoded).unwrap().0.len(), 48_021);
+    }
+
+    #[test]
+    fn pre_skip_aligns_output_with_input() {
+        // A rising chirp, so only the right alignment matches
+        let samples: Vec<f32> = (0..48_000)
+            .map(|i| {
+                let t = i as f64 / 48_000.0;
+                ((200.0 + 1500.0 * t) * t * 2.0 * std::f64::consts::PI).sin() as f32 * 0.5
+            })
+            .collect();
+
+        // CELT-only at 48 kHz and SILK at 16 kHz have different lookaheads
+        for (rate, bitrate) in [(48_000, 64_000), (16_000, 24_000)] {
+            let mut encoder = OpusEncoder::new();
+            encoder.set_sample_rate(rate);
+            encoder.set_bitrate(bitrate);
+            let (decoded, _) = decode_opus_bytes(&encoder.encode_samples(&samples, 48_000).unwrap()).unwrap();
+
+            let correlation = |lag: isize| -> f32 {
+                (1_000..47_000).map(|i| decoded[(i + lag) as usize] * samples[i as usize]).sum()
+            };
+            let best = (-400..400).max_by(|&a, &b| correlation(a).total_cmp(&correlation(b))).unwrap();
+            assert!(best.abs() <= 16, "{} Hz output is off by {} samples", rate, best);
+        }
+    }
+
+    #[test]
+    fn playable_range_trims_both_ends() {
+        assert_eq!(playable_range(0, 960, 312, u64::MAX), (312, 960));
+        assert_eq!(playable_range(960, 960, 312, 1_500), (0, 540));
+        assert_eq!(playable_range(1_920, 960, 312, 1_500), (0, 0));
+        assert_eq!(playable_range(0, 960, 1_000, 1_500), (960, 960));
+    }
+
+    #[test]
+    fn files_decode_at_the_input_rate_without_a_device() {
+        let dir = std::env::temp_dir();
+        let opus_path = dir.join(format!("rustic_decode_{}.opus", std::process::id()));
+        let wav_path = dir.join(format!("rustic_decode_{}.wav", std::process::id()));
+        let (opus_path, wav_path) = (opus_path.to_str().unwrap(), wav_path.to_str().unwrap());
+
+        let samples: Vec<f32> = (0..16_000)
+            .map(|i| (i as f32 * 300.0 * 2.0 * std::f32::consts::PI / 16_000.0).sin() * 0.5)
+            .collect();
+        let mut encoder = OpusEncoder::new();
+        encoder.set_sample_rate(16_000);
+        encoder.set_bitrate(32_000);
+        std::fs::write(opus_path, encoder.encode_samples(&samples, 16_000).unwrap()).unwrap();
+
+        let decoded = decode_opus_to_samples(opus_path).unwrap();
+        assert_eq!((decoded.channels, decoded.sample_rate), (1, 16_000));
+        assert_eq!(decoded.samples.len(), samples.len());
+        let rms = |s: &[f32]| (s.iter().map(|x| x * x).sum::<f32>() / s.len() as f32).sqrt();
+        assert!((rms(&decoded.samples) / rms(&samples) - 1.0).abs() < 0.1);
+
+        decode_opus_to_wav(opus_path, wav_path, Some(44_100)).unwrap();
+        let reader = hound::WavReader::open(wav_path).unwrap();
+        assert_eq!((reader.spec().sample_rate, reader.spec().channels), (44_100, 1));
+        assert_eq!(reader.len(), 44_100);
+
+        decode_opus_to_wav(opus_path, wav_path, None).unwrap();
+        assert_eq!(hound::WavReader::open(wav_path).unwrap().spec().sample_rate, 16_000);
+        assert!(decode_opus_to_wav(opus_path, wav_path, Some(0)).is_err());
+
+        std::fs::remove_file(opus_path).unwrap();
+        std::fs::remove_file(wav_path).unwrap();
+    }
+
+    #[test]
+    fn rejects_non_opus_bytes() {
+        assert!(decode_opus_bytes(b"definitely not an ogg stream").is_err());
+    }
+
+    fn audio_packets(stream: &[u8]) -> Vec<Vec<u8>> {
+        let mut reader = PacketReader::new(Cursor::new(stream));
+        reader.read_packet().unwrap();
+        reader.read_packet().unwrap();
+        std::iter::from_fn(|| reader.read_packet().unwrap()).map(|packet| packet.data).collect()
+    }
+
+    // Replaces packet `lost` with `decode_lost`, given the packet after it
+    fn recover(packets: &[Vec<u8>], lost: usize) -> Vec<f32> {
+        let mut decoder = OpusPacketDecoder::new(1).unwrap();
+        for packet in &packets[..lost] {
+            decoder.decode(packet).unwrap();
+        }
+        decoder.decode_lost(Some(&packets[lost + 1])).unwrap()
+    }
+
+    #[test]
+    fn fec_rebuilds_a_lost_packet() {
+        // A voiced, speech-like signal at a SILK rate
+        let samples: Vec<f32> = (0..48_000)
+            .map(|i| {
+                let t = i as f32 / 48_000.0;
+                (1..6).map(|h| (t * 140.0 * h as f32 * 2.0 * std::f32::consts::PI).sin() / h as f32).sum::<f32>() * 0.2
+            })
+            .collect();
+
+        let mut encoder = OpusEncoder::new();
+        encoder.set_sample_rate(16_000);
+        encoder.set_inband_fec(true);
+        encoder.set_packet_loss_perc(20);
+        let packets = audio_packets(&encoder.encode_samples(&samples, 48_000).unwrap());
+        let mut reference = OpusPacketDecoder::new(1).unwrap();
+        let expected: Vec<Vec<f32>> = packets.iter().map(|packet| reference.decode(packet).unwrap()).collect();
+
+        // Quiet frames carry no FEC data, so most losses, not all, are rebuilt
+        let rms = |x: &[f32]| (x.iter().map(|s| s * s).sum::<f32>() / x.len() as f32).sqrt();
+        let rebuilt = (5..45)
+            .filter(|&lost| {
+                let recovered = recover(&packets, lost);
+                assert_eq!(recovered.len(), expected[lost].len());
+                let level = rms(&recovered) / rms(&expected[lost]);
+                (0.1..10.0).contains(&level)
+            })
+            .count();
+        assert!(rebuilt >= 20, "{} of 40 packets rebuilt", rebuilt);
+
+        // The packet after the loss decodes as usual
+        let mut decoder = OpusPacketDecoder::new(1).unwrap();
+        decoder.decode(&packets[0]).unwrap();
+        decoder.decode_lost(Some(&packets[2])).unwrap();
+        assert_eq!(decoder.decode(&packets[2]).unwrap().len(), 960);
+
+        // Without FEC data the gap is silence, once the resampler has flushed the
+        // end of the packet before
+        encoder.set_inband_fec(false);
+        let packets = audio_packets(&encoder.encode_samples(&samples, 48_000).unwrap());
+        assert!(recover(&packets, 20)[240..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn packets_without_frame_data_decode_to_silence() {
+        let mut decoder = OpusPacketDecoder::new(1).unwrap();
+        // SILK wideband 20 ms and CELT 2.5 ms TOC bytes alone, the second with three frames
+        assert_eq!(decoder.decode(&[9 << 3]).unwrap(), vec![0.0; 960]);
+        assert_eq!(decoder.decode(&[(16 << 3) | 3, 3]).unwrap(), vec![0.0; 360]);
+        assert!(decoder.decode(&[(9 << 3) | 0x04]).is_err());
+        assert!(decoder.decode(&[]).is_err());
+        // A lost packet is as long as the one before it
+        assert_eq!(decoder.decode_lost(None).unwrap().len(), 360);
+    }
+
+    #[test]
+    fn frames_decode_as_opus_rs_decodes_them() {
+        // SILK at 16 kHz followed by CELT at 48 kHz, so the stream switches modes
+        let samples: Vec<f32> = (0..24_000)
+            .map(|i| (i as f32 * 220.0 * 2.0 * std::f32::consts::PI / 48_000.0).sin() * 0.4)
+            .collect();
+        let mut packets = Vec::new();
+        for rate in [16_000, 48_000] {
+            let mut encoder = OpusEncoder::new();
+            encoder.set_sample_rate(rate);
+            packets.extend(audio_packets(&encoder.encode_samples(&samples, 48_000).unwrap()));
+        }
+
+        let mut decoder = OpusPacketDecoder::new(1).unwrap();
+        let mut reference = OpusDecoder::new(48_000, 1).unwrap();
+        for packet in &packets {
+            let (frame_samples, _) = packet_frames(packet).unwrap();
+            let mut expected = Vec::new();
+            for payload in frame_payloads(packet).unwrap() {
+                let mut frame = vec![0f32; frame_samples];
+                let single: Vec<u8> = std::iter::once(packet[0] & !0x03).chain(payload.iter().copied()).collect();
+                reference.decode(&single, frame_samples, &mut frame).unwrap();
+                expected.extend(frame);
+            }
+            assert_eq!(decoder.decode(packet).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn long_silk_frames_decode_in_full() {
+        // A 300 Hz tone coded as single 60 ms narrowband SILK frames, the
+        // way libopus writes them at low bitrates
+        let mut silk = SilkEncoderState::default();
+        silk_init_encoder(&mut silk, 0);
+        silk_control_encoder(&mut silk, 8, 60, 12_000, 10);
+        silk.s_cmn.n_channels = 1;
+        let mut range_coder = RangeCoder::new_encoder(1);
+        let packets: Vec<Vec<u8>> = (0..10)
+            .map(|index| {
+                let input: Vec<i16> = (index * 480..(index + 1) * 480)
+                    .map(|i| ((i as f32 * 300.0 * 2.0 * std::f32::consts::PI / 8_000.0).sin() * 10_000.0) as i16)
+                    .collect();
+                range_coder.reset_for_encode(1275);
+                let mut bytes = 0;
+                assert_eq!(silk_encode(&mut silk, &input, input.len(), &mut range_coder, &mut bytes, 12_000, 1275 * 8, 0, 1), 0);
+                range_coder.done();
+                let len = ((range_coder.tell() + 7) >> 3) as usize;
+                // Narrowband SILK, 60 ms, one frame
+                std::iter::once(3 << 3).chain(range_coder.buf[..len].iter().copied()).collect()
+            })
+            .collect();
+
+        let mut decoder = OpusPacketDecoder::new(1).unwrap();
+        let mut decoded = Vec::new();
+        for packet in &packets {
+            assert_eq!(packet_frames(packet), Some((2880, 1)));
+            let samples = decoder.decode(packet).unwrap();
+            assert_eq!(samples.len(), 2880);
+            decoded.extend(samples);
+        }
+
+        // Every 20 ms of each frame carries the tone, not just the first
+        let rms = |samples: &[f32]| (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+        let level = 10_000.0 / 32_768.0 / 2f32.sqrt();
+        for sub_frame in decoded[2880..].chunks(960) {
+            assert!((rms(sub_frame) / level - 1.0).abs() < 0.25, "rms {}", rms(sub_frame));
+        }
+    }
+
+    // One second of a 300 Hz tone on the left and 1 kHz on the right, encoded
+    // as 20 ms stereo packets at `rate`
+    fn stereo_packets(rate: u32, application: Application) -> Vec<Vec<u8>> {
+        let frame = rate as usize / 50;
+        let mut encoder = CodecEncoder::new(rate as i32, 2, application).unwrap();
+        encoder.bitrate_bps = 32_000;
+        let mut output = vec![0u8; 1275];
+        (0..50)
+            .map(|index| {
+                let input: Vec<f32> = (index * frame..(index + 1) * frame)
+                    .flat_map(|i| {
+                        let t = i as f32 / rate as f32 * 2.0 * std::f32::consts::PI;
+                        [(t * 300.0).sin() * 0.5, (t * 1_000.0).sin() * 0.5]
+                    })
+                    .collect();
+                let len = encoder.encode(&input, frame, &mut output).unwrap();
+                output[..len].to_vec()
+            })
+            .collect()
+    }
+
+    fn stereo_stream(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut stream = Vec::new();
+        let mut writer = PacketWriter::new(&mut stream);
+        let mut head = b"OpusHead".to_vec();
+        head.extend_from_slice(&[1, 2, 0, 0]);
+        head.extend_from_slice(&16_000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        writer.write_packet(head, 1, PacketWriteEndInfo::EndPage, 0).unwrap();
+        writer.write_packet(crate::OpusTags::new().to_packet(), 1, PacketWriteEndInfo::EndPage, 0).unwrap();
+        for (index, packet) in packets.iter().enumerate() {
+            let end = if index == packets.len() - 1 { PacketWriteEndInfo::EndStream } else { PacketWriteEndInfo::NormalPacket };
+            writer.write_packet(packet.clone(), 1, end, (index as u64 + 1) * 960).unwrap();
+        }
+        drop(writer);
+        stream
+    }
+
+    #[test]
+    fn stereo_silk_is_an_error_not_fake_stereo() {
+        let packets = stereo_packets(16_000, Application::Voip);
+        assert!(packets.iter().all(|packet| packet[0] >> 3 < 12 && packet[0] & 0x04 != 0));
+
+        let mut decoder = OpusPacketDecoder::new(2).unwrap();
+        assert!(matches!(decoder.decode(&packets[0]), Err(RusticAudioError::Codec(_))));
+        assert!(matches!(decoder.decode_lost(Some(&packets[1])), Err(RusticAudioError::Codec(_))));
+        assert!(matches!(decode_opus_bytes(&stereo_stream(&packets)), Err(RusticAudioError::Codec(_))));
+
+        // Stereo CELT keeps both channels
+        let packets = stereo_packets(48_000, Application::RestrictedLowDelay);
+        let mut decoder = OpusPacketDecoder::new(2).unwrap();
+        let decoded: Vec<f32> = packets.iter().flat_map(|packet| decoder.decode(packet).unwrap()).collect();
+        let difference: f32 = decoded.chunks(2).map(|frame| (frame[0] - frame[1]).abs()).sum();
+        assert!(difference / (decoded.len() / 2) as f32 > 0.1, "channels differ by {}", difference);
+    }
+}
@@ -1,19 +1,217 @@
 use ogg::{PacketWriter, writing::PacketWriteEndInfo};
 use opus_rs::{Application, OpusEncoder as CodecEncoder};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
+use crate::downmix::Downmix;
+use crate::error::{Result, RusticAudioError};
+use crate::opus_packet::{frame_payloads, is_celt_only, join_frames, packet_samples};
+use crate::opus_tags::OpusTags;
+use crate::resample::{resample, ResamplerQuality};
+use crate::validate::{read_wav_file, ParseLimits};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+const MIN_BITRATE: i32 = 6000;
+const MAX_BITRATE: i32 = 510000;
+
+// Encoder lookahead in 48 kHz samples. opus-rs has no lookahead query, so these
+// are the delays of its two paths: SILK and hybrid frames carry the 2.5 ms CELT
+// overlap plus 4 ms of delay compensation (312 samples, as libopus reports),
+// while CELT-only frames carry just the 2.5 ms overlap.
+const LOOKAHEAD: u64 = 312;
+const CELT_LOOKAHEAD: u64 = 120;
+
+// libopus moves music to CELT-only from this bitrate; VoIP raises it by 8 kbps
+const MUSIC_CELT_BITRATE: i32 = 10000;
+const VOIP_MODE_OFFSET: i32 = 8000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum OpusEncodingMode {
+    /// Every packet has the same size.
     Cbr,
+    /// Packet sizes follow the signal.
     Vbr,
+    /// Packet sizes follow the signal, but no packet is larger than a CBR
+    /// packet at the same bitrate, so the rate never peaks above the target.
+    ConstrainedVbr,
+}
+
+/// What the encoder is tuned for, as in libopus's `OPUS_APPLICATION_*`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpusApplication {
+    /// Speech intelligibility: high-pass filters the input and favours SILK.
+    Voip,
+    /// Fidelity to the input, for music and mixed content.
+    #[default]
+    Audio,
+    /// Lowest delay: CELT-only at every bitrate.
+    RestrictedLowDelay,
+}
+
+/// Hint about the content being encoded, as in libopus's `OPUS_SET_SIGNAL`.
+///
+/// opus-rs has no signal control, so the hint is applied to the coding mode
+/// the way libopus's thresholds would: music switches to CELT-only from
+/// 10 kbps (18 kbps with `Voip`), while voice keeps the SILK and hybrid modes
+/// the codec picks for speech bitrates.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpusSignal {
+    /// Let the codec decide from the bitrate.
+    #[default]
+    Auto,
+    Voice,
+    Music,
+}
+
+/// Duration of the audio in each Opus packet.
+///
+/// Up to 20 ms, every packet holds a single frame. Longer durations put two to
+/// six 20 ms frames in one packet, which saves the TOC byte, the Ogg lacing and
+/// part of the page overhead of the packets they replace; at 6-12 kbps that is
+/// a noticeable share of the file. Durations under 10 ms are CELT-only and
+/// need a 48 kHz encoding rate.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
+pub enum OpusFrameDuration {
+    #[serde(rename = "2.5ms")]
+    Ms2_5,
+    #[serde(rename = "5ms")]
+    Ms5,
+    #[serde(rename = "10ms")]
+    Ms10,
+    #[default]
+    #[serde(rename = "20ms")]
+    Ms20,
+    #[serde(rename = "40ms")]
+    Ms40,
+    #[serde(rename = "60ms")]
+    Ms60,
+    #[serde(rename = "80ms")]
+    Ms80,
+    #[serde(rename = "100ms")]
+    Ms100,
+    #[serde(rename = "120ms")]
+    Ms120,
+}
+
+impl OpusFrameDuration {
+    pub const ALL: [OpusFrameDuration; 9] = [
+        OpusFrameDuration::Ms2_5,
+        OpusFrameDuration::Ms5,
+        OpusFrameDuration::Ms10,
+        OpusFrameDuration::Ms20,
+        OpusFrameDuration::Ms40,
+        OpusFrameDuration::Ms60,
+        OpusFrameDuration::Ms80,
+        OpusFrameDuration::Ms100,
+        OpusFrameDuration::Ms120,
+    ];
+
+    /// Packet duration in 48 kHz samples, the unit granule positions count in.
+    pub fn samples_48k(self) -> usize {
+        match self {
+            OpusFrameDuration::Ms2_5 => 120,
+            OpusFrameDuration::Ms5 => 240,
+            OpusFrameDuration::Ms10 => 480,
+            OpusFrameDuration::Ms20 => 960,
+            OpusFrameDuration::Ms40 => 1920,
+            OpusFrameDuration::Ms60 => 2880,
+            OpusFrameDuration::Ms80 => 3840,
+            OpusFrameDuration::Ms100 => 4800,
+            OpusFrameDuration::Ms120 => 5760,
+        }
+    }
+
+    pub fn milliseconds(self) -> f32 {
+        self.samples_48k() as f32 / 48.0
+    }
+
+    /// The duration of `milliseconds` ms, if it is one Opus allows.
+    pub fn from_milliseconds(milliseconds: f32) -> Option<Self> {
+        Self::ALL.into_iter().find(|duration| duration.milliseconds() == milliseconds)
+    }
+
+    // Length of each frame the codec encodes, in 48 kHz samples; longer
+    // packets are made of 20 ms frames
+    fn codec_frame_48k(self) -> usize {
+        self.samples_48k().min(960)
+    }
+}
+
+/// Highest audio bandwidth the encoder may use.
+///
+/// Opus picks the bandwidth from the rate it encodes at, so a limit below the
+/// configured sample rate lowers the encoding rate to match: narrowband encodes
+/// at 8 kHz, mediumband at 12 kHz, wideband at 16 kHz, superwideband at 24 kHz
+/// and fullband at 48 kHz.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpusBandwidth {
+    /// 4 kHz audio bandwidth.
+    Narrowband,
+    /// 6 kHz audio bandwidth.
+    Mediumband,
+    /// 8 kHz audio bandwidth.
+    Wideband,
+    /// 12 kHz audio bandwidth.
+    Superwideband,
+    /// 20 kHz audio bandwidth.
+    #[default]
+    Fullband,
 }
 
-#[derive(Clone)]
+impl OpusBandwidth {
+    /// Highest audio frequency kept at this bandwidth.
+    pub fn cutoff_hz(self) -> u32 {
+        match self {
+            OpusBandwidth::Narrowband => 4000,
+            OpusBandwidth::Mediumband => 6000,
+            OpusBandwidth::Wideband => 8000,
+            OpusBandwidth::Superwideband => 12000,
+            OpusBandwidth::Fullband => 20000,
+        }
+    }
+
+    /// Lowest Opus sample rate that carries this bandwidth.
+    pub fn sample_rate(self) -> u32 {
+        match self {
+            OpusBandwidth::Narrowband => 8000,
+            OpusBandwidth::Mediumband => 12000,
+            OpusBandwidth::Wideband => 16000,
+            OpusBandwidth::Superwideband => 24000,
+            OpusBandwidth::Fullband => 48000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct OpusEncoder {
     bitrate: i32,
     mode: OpusEncodingMode,
-    vbr_quality: i32,
+    application: OpusApplication,
+    signal: OpusSignal,
+    // Presets from before complexity was its own setting call it VBR quality
+    #[serde(alias = "vbr_quality")]
+    complexity: i32,
+    sample_rate: u32,
+    max_bandwidth: OpusBandwidth,
+    frame_duration: OpusFrameDuration,
+    inband_fec: bool,
+    packet_loss_perc: u8,
+    dtx: bool,
+    resampler_quality: ResamplerQuality,
+    downmix: Downmix,
+    // Per-message metadata rather than a setting, so presets leave it out
+    #[serde(skip)]
+    tags: OpusTags,
+}
+
+impl Default for OpusEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OpusEncoder {
@@ -21,11 +219,88 @@ impl OpusEncoder {
         Self {
             bitrate: 12000, // Default 12kbps
             mode: OpusEncodingMode::Cbr,
-            vbr_quality: 5,
+            application: OpusApplication::Audio,
+            signal: OpusSignal::Auto,
+            complexity: 5,
+            sample_rate: 48000,
+            max_bandwidth: OpusBandwidth::Fullband,
+            frame_duration: OpusFrameDuration::Ms20,
+            inband_fec: false,
+            packet_loss_perc: 0,
+            dtx: false,
+            resampler_quality: ResamplerQuality::default(),
+            downmix: Downmix::default(),
+            tags: OpusTags::new(),
         }
     }
 
-    // Add setter for bitrate
+    /// Sets the rate the audio is resampled to before encoding.
+    ///
+    /// Opus accepts 8, 12, 16, 24 or 48 kHz; other values are rejected when encoding.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Limits the audio bandwidth; see `OpusBandwidth`.
+    pub fn set_max_bandwidth(&mut self, bandwidth: OpusBandwidth) {
+        self.max_bandwidth = bandwidth;
+    }
+
+    pub fn get_max_bandwidth(&self) -> OpusBandwidth {
+        self.max_bandwidth
+    }
+
+    /// Sets the duration of the audio in each packet; see `OpusFrameDuration`.
+    pub fn set_frame_duration(&mut self, duration: OpusFrameDuration) {
+        self.frame_duration = duration;
+    }
+
+    pub fn get_frame_duration(&self) -> OpusFrameDuration {
+        self.frame_duration
+    }
+
+    /// Rate the codec actually runs at: the sample rate, lowered to the bandwidth limit.
+    ///
+    /// This is the rate written to the OpusHead input sample rate field.
+    pub fn encoding_sample_rate(&self) -> u32 {
+        self.sample_rate.min(self.max_bandwidth.sample_rate())
+    }
+
+    /// Sets the quality of the band-limited resampler used when the input rate differs.
+    pub fn set_resampler_quality(&mut self, quality: ResamplerQuality) {
+        self.resampler_quality = quality;
+    }
+
+    pub fn get_resampler_quality(&self) -> ResamplerQuality {
+        self.resampler_quality
+    }
+
+    /// Sets how multichannel WAV input is reduced to the mono signal that gets encoded.
+    pub fn set_downmix(&mut self, downmix: Downmix) {
+        self.downmix = downmix;
+    }
+
+    pub fn get_downmix(&self) -> Downmix {
+        self.downmix
+    }
+
+    /// Sets the OpusTags comments written into every stream this encoder produces.
+    pub fn set_tags(&mut self, tags: OpusTags) {
+        self.tags = tags;
+    }
+
+    pub fn get_tags(&self) -> &OpusTags {
+        &self.tags
+    }
+
+    pub fn tags_mut(&mut self) -> &mut OpusTags {
+        &mut self.tags
+    }
+
     pub fn set_bitrate(&mut self, bitrate: i32) {
         self.bitrate = bitrate;
     }
@@ -43,87 +318,323 @@ impl OpusEncoder {
         self.mode
     }
 
+    /// Sets what the encoder is tuned for; see `OpusApplication`.
+    pub fn set_application(&mut self, application: OpusApplication) {
+        self.application = application;
+    }
+
+    pub fn get_application(&self) -> OpusApplication {
+        self.application
+    }
+
+    /// Sets the content hint; see `OpusSignal`.
+    pub fn set_signal(&mut self, signal: OpusSignal) {
+        self.signal = signal;
+    }
+
+    pub fn get_signal(&self) -> OpusSignal {
+        self.signal
+    }
+
+    /// Sets the encoder complexity from 0 (fastest) to 10 (best quality per bit).
+    pub fn set_complexity(&mut self, complexity: i32) {
+        self.complexity = complexity.clamp(0, 10);
+    }
+
+    pub fn get_complexity(&self) -> i32 {
+        self.complexity
+    }
+
+    /// Adds in-band forward error correction: each SILK or hybrid packet also
+    /// carries a low-bitrate copy of the previous one, which
+    /// `OpusPacketDecoder::decode_lost` uses to rebuild a lost packet.
+    ///
+    /// The encoder only spends bits on it while the expected packet loss is above zero.
+    pub fn set_inband_fec(&mut self, enabled: bool) {
+        self.inband_fec = enabled;
+    }
+
+    pub fn get_inband_fec(&self) -> bool {
+        self.inband_fec
+    }
+
+    /// Sets the packet loss the link is expected to have, in percent (0-100).
+    ///
+    /// Higher values make the codec more robust to loss at some cost in quality.
+    pub fn set_packet_loss_perc(&mut self, percent: u8) {
+        self.packet_loss_perc = percent.min(100);
+    }
+
+    pub fn get_packet_loss_perc(&self) -> u8 {
+        self.packet_loss_perc
+    }
+
+    /// Enables discontinuous transmission.
+    ///
+    /// After 200 ms of silence, frames are sent as one-byte packets that hold
+    /// only the TOC byte, with a full packet every 400 ms to keep the decoder's
+    /// background noise current. The frame right before the signal returns is
+    /// always sent in full, so the decoder resumes in step with the encoder.
+    /// Granule positions still advance by the whole frame, so the timing of the
+    /// speech after a pause is kept.
+    pub fn set_dtx(&mut self, enabled: bool) {
+        self.dtx = enabled;
+    }
+
+    pub fn get_dtx(&self) -> bool {
+        self.dtx
+    }
+
+    #[deprecated(note = "VBR quality was always the encoder complexity; use `set_complexity`")]
     pub fn set_vbr_quality(&mut self, quality: i32) {
-        self.vbr_quality = quality.clamp(0, 10);
+        self.complexity = quality.clamp(0, 10);
     }
 
+    #[deprecated(note = "VBR quality was always the encoder complexity; use `get_complexity`")]
     pub fn get_vbr_quality(&self) -> i32 {
-        self.vbr_quality
+        self.complexity
     }
 
-    pub fn encode_wav_to_opus(&self, input_path: &str, output_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // Read the WAV file
-        let mut reader = hound::WavReader::open(input_path)?;
-        let spec = reader.spec();
-        
-        // Convert to 48kHz mono if needed
-        let samples: Vec<f32> = if spec.sample_format == hound::SampleFormat::Float {
-            reader.samples::<f32>().map(|s| s.unwrap()).collect()
-        } else {
-            reader.samples::<i16>().map(|s| s.unwrap() as f32 / 32768.0).collect()
-        };
-        
-        // Convert to mono if stereo
-        let mono_samples: Vec<f32> = if spec.channels == 2 {
-            samples.chunks(2)
-                .map(|chunk| chunk[0]) // Take left channel
-                .collect()
-        } else {
-            samples
+    /// Checks that the current settings are ones Opus can encode with.
+    pub fn validate_settings(&self) -> Result<()> {
+        if !matches!(self.sample_rate, 8000 | 12000 | 16000 | 24000 | 48000) {
+            return Err(RusticAudioError::InvalidArgument(format!(
+                "Opus sample rate {} Hz must be 8, 12, 16, 24 or 48 kHz", self.sample_rate
+            )));
+        }
+
+        if !(MIN_BITRATE..=MAX_BITRATE).contains(&self.bitrate) {
+            return Err(RusticAudioError::InvalidArgument(format!(
+                "Opus bitrate {} bps must be between 6 kbps and 510 kbps", self.bitrate
+            )));
+        }
+
+        // opus-rs codes CELT frames at 48 kHz frame sizes only
+        if self.frame_duration < OpusFrameDuration::Ms10 && self.encoding_sample_rate() != 48000 {
+            return Err(RusticAudioError::InvalidArgument(format!(
+                "{} ms frames are CELT-only and need a 48 kHz encoding rate, not {} Hz",
+                self.frame_duration.milliseconds(), self.encoding_sample_rate()
+            )));
+        }
+
+        if self.packet_loss_perc > 100 {
+            return Err(RusticAudioError::InvalidArgument(format!(
+                "packet loss {}% must be between 0 and 100", self.packet_loss_perc
+            )));
+        }
+
+        if !(0..=10).contains(&self.complexity) {
+            return Err(RusticAudioError::InvalidArgument(format!(
+                "complexity {} must be between 0 and 10", self.complexity
+            )));
+        }
+
+        Ok(())
+    }
+
+    // The codec application that carries out the application and signal hint
+    fn codec_application(&self) -> Application {
+        let music_threshold = match self.application {
+            OpusApplication::Voip => MUSIC_CELT_BITRATE + VOIP_MODE_OFFSET,
+            _ => MUSIC_CELT_BITRATE,
         };
-        
-        // Resample to 48kHz if needed
-        let resampled_samples = if spec.sample_rate != 48000 {
-            let input_duration = mono_samples.len() as f32 / spec.sample_rate as f32;
-            let output_len = (input_duration * 48000.0) as usize;
-            let scale = (mono_samples.len() - 1) as f32 / (output_len - 1) as f32;
-            
-            let mut output = Vec::with_capacity(output_len);
-            for i in 0..output_len {
-                let pos = i as f32 * scale;
-                let index = pos as usize;
-                let frac = pos - index as f32;
-                
-                let sample = if index + 1 < mono_samples.len() {
-                    mono_samples[index] * (1.0 - frac) + mono_samples[index + 1] * frac
-                } else {
-                    mono_samples[index]
-                };
-                
-                output.push(sample);
+        // CELT has no mediumband mode, so 12 kHz music stays in SILK
+        let celt_music = self.signal == OpusSignal::Music
+            && self.bitrate >= music_threshold
+            && self.encoding_sample_rate() != 12000;
+
+        // Only CELT codes frames shorter than 10 ms
+        let short_frames = self.frame_duration < OpusFrameDuration::Ms10;
+
+        match self.application {
+            OpusApplication::RestrictedLowDelay => Application::RestrictedLowDelay,
+            _ if celt_music || short_frames => Application::RestrictedLowDelay,
+            OpusApplication::Voip => Application::Voip,
+            OpusApplication::Audio => Application::Audio,
+        }
+    }
+
+    // Samples per codec frame at the encoder sample rate
+    fn frame_size(&self) -> usize {
+        self.frame_duration.codec_frame_48k() * self.encoding_sample_rate() as usize / 48000
+    }
+
+    pub fn encode_wav_to_opus(&self, input_path: &str, output_path: &str) -> Result<()> {
+        let (mono_samples, sample_rate) = read_wav_mono(input_path, self.downmix)?;
+        let encoded = self.encode_samples(&mono_samples, sample_rate)?;
+        write_file(output_path, &encoded)
+    }
+
+    /// Like `encode_wav_to_opus`, but the written file never exceeds `max_bytes`.
+    ///
+    /// See `encode_with_size_budget`. Nothing is written if the budget cannot be met.
+    pub fn encode_wav_to_opus_with_size_budget(&self, input_path: &str, output_path: &str, max_bytes: usize) -> Result<()> {
+        let (mono_samples, sample_rate) = read_wav_mono(input_path, self.downmix)?;
+        let encoded = self.encode_with_size_budget(&mono_samples, sample_rate, max_bytes)?;
+        write_file(output_path, &encoded)
+    }
+
+    /// Encodes mono samples into an Ogg Opus stream of at most `max_bytes`.
+    ///
+    /// The bitrate is planned from the duration and the Ogg container overhead;
+    /// the configured bitrate is kept when it fits. If the result still comes
+    /// out too large (VBR can overshoot) it is re-encoded in CBR at a lower
+    /// bitrate, down to 6 kbps. Returns `RusticAudioError::SizeBudgetExceeded`
+    /// when even that is too large.
+    pub fn encode_with_size_budget(&self, samples: &[f32], sample_rate: u32, max_bytes: usize) -> Result<Vec<u8>> {
+        if sample_rate == 0 {
+            return Err(RusticAudioError::InvalidArgument("sample rate must be greater than zero".to_string()));
+        }
+        self.validate_settings()?;
+
+        let duration = samples.len() as f64 / sample_rate as f64;
+        // Input plus the encoder lookahead, in packets of the configured duration
+        let packet_samples = self.frame_duration.samples_48k() as f64;
+        let packets = ((duration * 48000.0 + LOOKAHEAD as f64) / packet_samples).ceil() as usize;
+        let planned_packet_bytes = max_bytes / packets;
+        let overhead = container_overhead(packets, planned_packet_bytes, self.tags.to_packet().len());
+
+        let payload_budget = max_bytes.saturating_sub(overhead);
+        let planned = (payload_budget as f64 * 8.0 / duration.max(0.02)) as i32;
+
+        let mut attempt = self.clone();
+        attempt.bitrate = planned.min(self.bitrate).clamp(MIN_BITRATE, MAX_BITRATE);
+        println!("Size budget: {} bytes, planned bitrate {} bps, container overhead ~{} bytes",
+                 max_bytes, attempt.bitrate, overhead);
+
+        loop {
+            let encoded = attempt.encode_samples(samples, sample_rate)?;
+            if encoded.len() <= max_bytes {
+                return Ok(encoded);
             }
-            output
-        } else {
-            mono_samples
-        };
-        
-        let mut encoder = CodecEncoder::new(48_000, 1, Application::Audio)
-            .map_err(std::io::Error::other)?;
+
+            if attempt.bitrate == MIN_BITRATE && attempt.mode == OpusEncodingMode::Cbr {
+                return Err(RusticAudioError::SizeBudgetExceeded {
+                    max_bytes,
+                    smallest: encoded.len(),
+                });
+            }
+
+            // Scale the audio payload down by how far over budget it came out, with a margin
+            let payload = encoded.len().saturating_sub(overhead).max(1);
+            let scaled = (attempt.bitrate as f64 * payload_budget as f64 / payload as f64 * 0.95) as i32;
+            attempt.bitrate = scaled.min(attempt.bitrate - 500).max(MIN_BITRATE);
+            attempt.mode = OpusEncodingMode::Cbr;
+            println!("Encoded {} bytes, over budget; retrying at {} bps CBR", encoded.len(), attempt.bitrate);
+        }
+    }
+
+    /// Encodes mono samples at `sample_rate` into a complete Ogg Opus stream held in memory.
+    ///
+    /// Nothing is written to disk; the returned bytes are identical to what
+    /// `encode_wav_to_opus` would write for the same audio.
+    pub fn encode_samples(&self, samples: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
+        if sample_rate == 0 {
+            return Err(RusticAudioError::InvalidArgument("sample rate must be greater than zero".to_string()));
+        }
+        self.validate_settings()?;
+
+        // Resample to the encoder rate if needed
+        let encoding_rate = self.encoding_sample_rate();
+        let resampled_samples = resample(samples, sample_rate, encoding_rate, self.resampler_quality);
+
+        let frame_size = self.frame_size();
+        let mut encoder = CodecEncoder::new(encoding_rate as i32, 1, self.codec_application())
+            .map_err(|e| RusticAudioError::Codec(e.to_string()))?;
         encoder.bitrate_bps = self.bitrate;
         encoder.use_cbr = matches!(self.mode, OpusEncodingMode::Cbr);
-        encoder.complexity = self.vbr_quality;
+        encoder.complexity = self.complexity;
+        encoder.use_inband_fec = self.inband_fec;
+        encoder.packet_loss_perc = self.packet_loss_perc as i32;
         
         println!("Converting to Opus:");
         println!("  Mode: {:?}", self.mode_name());
+        println!("  Application: {:?}, signal: {:?}", self.application, self.signal);
         println!("  Bitrate target: {} bps", self.bitrate);
-        println!("  VBR quality: {}", self.vbr_quality);
-        println!("  Frame size: 960 samples (20ms at 48kHz)");
-        println!("  Total frames: {}", resampled_samples.len() / 960);
+        println!("  Complexity: {}", self.complexity);
+        println!("  FEC: {}, expected loss: {}%, DTX: {}", self.inband_fec, self.packet_loss_perc, self.dtx);
+        println!("  Max bandwidth: {:?} ({} Hz)", self.max_bandwidth, self.max_bandwidth.cutoff_hz());
+        println!("  Frame size: {} samples ({} ms at {} Hz), {} ms packets",
+                 frame_size, self.frame_duration.codec_frame_48k() as f32 / 48.0, encoding_rate,
+                 self.frame_duration.milliseconds());
+
+        // Encode past the end of the input by the lookahead so its last samples come out
+        let granule_scale = (48000 / encoding_rate) as u64;
+        let lookahead = (LOOKAHEAD as usize).div_ceil(granule_scale as usize);
+        let frames = (resampled_samples.len() + lookahead).div_ceil(frame_size);
+        println!("  Total frames: {}", frames);
+
+        let mut input_buffer = vec![0.0f32; frame_size];
+        // Constrained VBR caps every packet at the CBR packet size
+        let max_packet = match self.mode {
+            OpusEncodingMode::ConstrainedVbr => {
+                (self.bitrate as usize * frame_size / encoding_rate as usize).div_ceil(8).clamp(2, 1275)
+            }
+            _ => 1275,
+        };
+        let mut encoded_data = vec![0u8; max_packet];
+        // Each frame as a single frame packet, joined into longer packets below
+        let mut frame_packets = Vec::with_capacity(frames);
+        let mut dtx = Dtx::new(self.frame_duration.codec_frame_48k());
+        // Full packet of the last frame DTX left out
+        let mut skipped = None;
+
+        for frame in 0..frames {
+            let start = (frame * frame_size).min(resampled_samples.len());
+            let end = (start + frame_size).min(resampled_samples.len());
+            input_buffer.clear();
+            input_buffer.extend_from_slice(&resampled_samples[start..end]);
+            input_buffer.resize(frame_size, 0.0);
 
-        let file = BufWriter::new(File::create(output_path)?);
+            let encoded_len = encoder
+                .encode(&input_buffer, frame_size, &mut encoded_data)
+                .map_err(|e| RusticAudioError::Codec(e.to_string()))?;
+            let packet = encoded_data[..encoded_len].to_vec();
+            // The frame is still encoded in DTX, so the codec state follows the signal
+            if self.dtx && dtx.skip(&input_buffer) {
+                // Code 0 with an empty frame, in the same configuration
+                frame_packets.push(vec![packet[0] & !0x03]);
+                skipped = Some(packet);
+            } else {
+                // Once the signal returns, the frame before it is sent in full, so
+                // the decoder has the history the next frame is coded against
+                let resumed = skipped.take().filter(|_| !dtx.in_silence());
+                if let (Some(full), Some(last)) = (resumed, frame_packets.last_mut()) {
+                    *last = full;
+                }
+                frame_packets.push(packet);
+            }
+        }
+
+        // Decoders skip the delay at the start and trim the stream to the input
+        // length using the granule position of the last page
+        let pre_skip = if frame_packets.iter().all(|packet| is_celt_only(packet)) {
+            CELT_LOOKAHEAD
+        } else {
+            LOOKAHEAD
+        };
+        let end_granule = pre_skip + resampled_samples.len() as u64 * granule_scale;
+        // Padding frames that hold nothing before the end granule are not needed
+        let codec_frame_48k = self.frame_duration.codec_frame_48k() as u64;
+        while frame_packets.len() > 1 && (frame_packets.len() as u64 - 1) * codec_frame_48k >= end_granule {
+            frame_packets.pop();
+        }
+        let packets = self.join_packets(frame_packets);
+
+        let mut encoded_stream = Vec::new();
         let serial = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as u32;
-        let mut packet_writer = PacketWriter::new(file);
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+        let mut packet_writer = PacketWriter::new(&mut encoded_stream);
 
         // Opus header
         let mut id_header = Vec::new();
         id_header.extend_from_slice(b"OpusHead");
         id_header.push(1);  // Version
         id_header.push(1);  // Channel count
-        id_header.extend_from_slice(&(0u16).to_le_bytes());  // Pre-skip
-        id_header.extend_from_slice(&(48000u32).to_le_bytes());  // Input sample rate
+        id_header.extend_from_slice(&(pre_skip as u16).to_le_bytes());  // Pre-skip
+        id_header.extend_from_slice(&encoding_rate.to_le_bytes());  // Input sample rate
         id_header.extend_from_slice(&[0, 0]);  // Output gain
         id_header.push(0);  // Channel mapping family
 
@@ -135,64 +646,405 @@ impl OpusEncoder {
         )?;
 
         // Comment header
-        let mut comment_header = Vec::new();
-        comment_header.extend_from_slice(b"OpusTags");
-        let vendor = b"rustic_audio";
-        comment_header.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
-        comment_header.extend_from_slice(vendor);
-        comment_header.extend_from_slice(&[0, 0, 0, 0]);
-
         packet_writer.write_packet(
-            comment_header,
+            self.tags.to_packet(),
             serial,
             PacketWriteEndInfo::EndPage,
             0
         )?;
 
-        let frame_size = 960;  // 20ms at 48kHz
-        let mut input_buffer = vec![0.0f32; frame_size];
-        let mut encoded_data = vec![0u8; 1275];
-        let mut granulepos = 0i64;
+        let last = packets.len() - 1;
+        let mut granule = 0u64;
+        for (index, packet) in packets.into_iter().enumerate() {
+            // Granule positions always count 48kHz samples, whatever the input rate
+            granule += packet_samples(&packet).unwrap_or(0) as u64;
+            let (end_info, granulepos) = if index == last {
+                (PacketWriteEndInfo::EndStream, end_granule)
+            } else {
+                (PacketWriteEndInfo::NormalPacket, granule)
+            };
+            packet_writer.write_packet(packet, serial, end_info, granulepos)?;
+        }
 
-        for chunk in resampled_samples.chunks(frame_size) {
-            input_buffer.clear();
-            input_buffer.extend(chunk);
-            if input_buffer.len() < frame_size {
-                input_buffer.resize(frame_size, 0.0);
-            }
+        drop(packet_writer);
 
-            let encoded_len = encoder
-                .encode(&input_buffer, frame_size, &mut encoded_data)
-                .map_err(std::io::Error::other)?;
-            let encoded_packet = &encoded_data[..encoded_len];
+        let final_duration = (end_granule - pre_skip) as f32 / 48000.0;
+        println!("Final Opus duration: {} seconds", final_duration);
 
-            granulepos += frame_size as i64;
+        Ok(encoded_stream)
+    }
 
-            packet_writer.write_packet(
-                encoded_packet.to_vec(),
-                serial,
-                PacketWriteEndInfo::NormalPacket,
-                granulepos as u64
-            )?;
+    // Groups single frame packets into packets of the configured duration.
+    // Frames only share a packet when they share a TOC configuration, so a
+    // change of coding mode or bandwidth starts a new, shorter packet.
+    fn join_packets(&self, frame_packets: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let per_packet = self.frame_duration.samples_48k() / self.frame_duration.codec_frame_48k();
+        if per_packet == 1 {
+            return frame_packets;
         }
 
-        packet_writer.write_packet(
-            Vec::<u8>::new(),
-            serial,
-            PacketWriteEndInfo::EndStream,
-            granulepos as u64
-        )?;
+        let mut groups: Vec<Vec<Vec<u8>>> = Vec::new();
+        for packet in frame_packets {
+            match groups.last_mut() {
+                Some(group) if group.len() < per_packet && group[0][0] >> 2 == packet[0] >> 2 => group.push(packet),
+                _ => groups.push(vec![packet]),
+            }
+        }
 
-        let final_duration = granulepos as f32 / 48000.0;
-        println!("Final Opus duration: {} seconds", final_duration);
+        let mut packets: Vec<Vec<u8>> = groups
+            .iter()
+            .map(|group| {
+                let payloads: Vec<&[u8]> = group
+                    .iter()
+                    .map(|packet| frame_payloads(packet).and_then(|frames| frames.first().copied()).unwrap_or(&[]))
+                    .collect();
+                join_frames(group[0][0], &payloads, None)
+            })
+            .collect();
 
-        Ok(())
+        // Joining drops the padding of CBR frames, so CBR pads every packet
+        // of whole frames back up to one size
+        if self.mode == OpusEncodingMode::Cbr {
+            let full = |packet: &Vec<u8>| frame_payloads(packet).is_some_and(|frames| frames.iter().all(|f| !f.is_empty()));
+            let size = packets.iter().filter(|packet| full(packet)).map(Vec::len).max().unwrap_or(0);
+            for (packet, group) in packets.iter_mut().zip(&groups) {
+                if full(packet) && packet.len() < size {
+                    let payloads = frame_payloads(packet).unwrap_or_default();
+                    *packet = join_frames(group[0][0], &payloads, Some(size));
+                }
+            }
+        }
+        packets
     }
 
     fn mode_name(&self) -> &'static str {
         match self.mode {
             OpusEncodingMode::Cbr => "CBR",
             OpusEncodingMode::Vbr => "VBR",
+            OpusEncodingMode::ConstrainedVbr => "Constrained VBR",
         }
     }
-}
\ No newline at end of file
+}
+
+// Silence below which DTX may stop sending frames, in dBFS
+const DTX_SILENCE_DB: f32 = -60.0;
+// Silence that must pass before DTX starts, and how often a full frame is still sent
+const DTX_HANGOVER_MS: usize = 200;
+const DTX_REFRESH_MS: usize = 400;
+
+// Decides which frames discontinuous transmission leaves out. Durations are
+// counted in 48 kHz samples, so 2.5 ms frames add up exactly
+struct Dtx {
+    frame: usize,
+    silent: usize,
+    skipped: usize,
+}
+
+impl Dtx {
+    fn new(frame_48k: usize) -> Self {
+        Self { frame: frame_48k, silent: 0, skipped: 0 }
+    }
+
+    fn skip(&mut self, frame: &[f32]) -> bool {
+        let power = frame.iter().map(|s| s * s).sum::<f32>() / frame.len().max(1) as f32;
+        let silent = 10.0 * power.max(1e-12).log10() < DTX_SILENCE_DB;
+        if !silent {
+            self.silent = 0;
+            self.skipped = 0;
+            return false;
+        }
+
+        self.silent += self.frame;
+        if self.silent <= DTX_HANGOVER_MS * 48 {
+            return false;
+        }
+        if self.skipped + self.frame > DTX_REFRESH_MS * 48 {
+            self.skipped = 0;
+            return false;
+        }
+        self.skipped += self.frame;
+        true
+    }
+
+    // Whether the last frame was silent, whether or not it was skipped
+    fn in_silence(&self) -> bool {
+        self.silent > 0
+    }
+}
+
+// Reads a WAV file as mono samples and its sample rate
+fn read_wav_mono(input_path: &str, downmix: Downmix) -> Result<(Vec<f32>, u32)> {
+    let (samples, spec) = read_wav_file(input_path, &ParseLimits::trusted())?;
+    let mono_samples = downmix.apply(&samples, spec.channels as usize)?;
+
+    Ok((mono_samples, spec.sample_rate))
+}
+
+fn write_file(output_path: &str, encoded: &[u8]) -> Result<()> {
+    let mut file = BufWriter::new(File::create(output_path)?);
+    file.write_all(encoded)?;
+    file.flush()?;
+    Ok(())
+}
+
+// Bytes the Ogg container adds around `packets` audio packets of about `packet_bytes` each
+fn container_overhead(packets: usize, packet_bytes: usize, tags_bytes: usize) -> usize {
+    // OpusHead and OpusTags pages: 27-byte page header, lacing bytes, the packet
+    let headers = (27 + 1 + 19) + (27 + tags_bytes / 255 + 1 + tags_bytes);
+    // Every packet needs one lacing byte per 255 bytes
+    let segments = packets * (packet_bytes / 255 + 1);
+    // A page holds at most 255 segments
+    let pages = segments.div_ceil(255);
+    headers + pages * 27 + segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OpusApplication, OpusBandwidth, OpusEncoder, OpusEncodingMode, OpusFrameDuration, OpusSignal};
+    use crate::opus_packet::{is_celt_only, packet_samples};
+    use crate::RusticAudioError;
+    use ogg::reading::PacketReader;
+    use std::io::Cursor;
+
+    // OpusHead input sample rate and the widest bandwidth of any audio packet
+    fn head_rate_and_bandwidth(stream: &[u8]) -> (u32, OpusBandwidth) {
+        let mut reader = PacketReader::new(Cursor::new(stream));
+        let head = reader.read_packet().unwrap().unwrap().data;
+        reader.read_packet().unwrap();
+
+        let mut widest = OpusBandwidth::Narrowband;
+        while let Some(packet) = reader.read_packet().unwrap() {
+            // Bandwidth by TOC configuration number, RFC 6716 section 3.1
+            let bandwidth = match packet.data.first().map(|toc| toc >> 3) {
+                None => continue,
+                Some(0..=3 | 16..=19) => OpusBandwidth::Narrowband,
+                Some(4..=7) => OpusBandwidth::Mediumband,
+                Some(8..=11 | 20..=23) => OpusBandwidth::Wideband,
+                Some(12..=13 | 24..=27) => OpusBandwidth::Superwideband,
+                Some(_) => OpusBandwidth::Fullband,
+            };
+            widest = widest.max(bandwidth);
+        }
+        (u32::from_le_bytes([head[12], head[13], head[14], head[15]]), widest)
+    }
+
+    // Noisy, voice-like signal that makes VBR work hard
+    fn busy_signal(len: usize) -> Vec<f32> {
+        let mut seed = 0x2545_f491u32;
+        (0..len)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = (seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5;
+                let tone = (i as f32 * 180.0 * 2.0 * std::f32::consts::PI / 48_000.0).sin();
+                tone * 0.3 + noise * 0.4
+            })
+            .collect()
+    }
+
+    #[test]
+    fn thirty_second_message_fits_45_kb() {
+        let samples = busy_signal(48_000 * 30);
+        let mut encoder = OpusEncoder::new();
+        encoder.set_mode(OpusEncodingMode::Vbr);
+        encoder.set_complexity(10);
+        encoder.set_bitrate(16000);
+
+        let encoded = encoder.encode_with_size_budget(&samples, 48_000, 45_000).unwrap();
+        assert!(encoded.len() <= 45_000, "{} bytes", encoded.len());
+    }
+
+    #[test]
+    fn bandwidth_limit_lowers_the_encoding_rate() {
+        let samples = busy_signal(48_000);
+
+        let mut encoder = OpusEncoder::new();
+        encoder.set_max_bandwidth(OpusBandwidth::Wideband);
+        let encoded = encoder.encode_samples(&samples, 48_000).unwrap();
+        assert_eq!(head_rate_and_bandwidth(&encoded), (16_000, OpusBandwidth::Wideband));
+
+        let mut encoder = OpusEncoder::new();
+        encoder.set_sample_rate(8_000);
+        let encoded = encoder.encode_samples(&samples, 48_000).unwrap();
+        assert_eq!(head_rate_and_bandwidth(&encoded), (8_000, OpusBandwidth::Narrowband));
+
+        let mut encoder = OpusEncoder::new();
+        encoder.set_sample_rate(16_000);
+        encoder.set_max_bandwidth(OpusBandwidth::Superwideband);
+        assert_eq!(encoder.encoding_sample_rate(), 16_000);
+    }
+
+    fn audio_packets(stream: &[u8]) -> Vec<Vec<u8>> {
+        let mut reader = PacketReader::new(Cursor::new(stream));
+        reader.read_packet().unwrap();
+        reader.read_packet().unwrap();
+        std::iter::from_fn(|| reader.read_packet().unwrap()).map(|packet| packet.data).collect()
+    }
+
+    #[test]
+    fn application_and_signal_pick_the_coding_mode() {
+        let samples = busy_signal(48_000);
+        let celt_share = |encoder: &OpusEncoder| {
+            let packets = audio_packets(&encoder.encode_samples(&samples, 48_000).unwrap());
+            packets.iter().filter(|packet| is_celt_only(packet)).count() as f32 / packets.len() as f32
+        };
+
+        let mut encoder = OpusEncoder::new();
+        encoder.set_application(OpusApplication::Voip);
+        encoder.set_signal(OpusSignal::Voice);
+        assert_eq!(celt_share(&encoder), 0.0);
+
+        // Music at 12 kbps is CELT-only in audio mode but not yet in VoIP mode
+        encoder.set_signal(OpusSignal::Music);
+        assert_eq!(celt_share(&encoder), 0.0);
+        encoder.set_application(OpusApplication::Audio);
+        assert_eq!(celt_share(&encoder), 1.0);
+
+        let mut encoder = OpusEncoder::new();
+        encoder.set_application(OpusApplication::RestrictedLowDelay);
+        let stream = encoder.encode_samples(&samples, 48_000).unwrap();
+        assert_eq!(celt_share(&encoder), 1.0);
+        assert_eq!(crate::OpusFileInfo::from_stream(&stream).unwrap().pre_skip, 120);
+    }
+
+    #[test]
+    fn constrained_vbr_never_exceeds_the_cbr_packet_size() {
+        let samples = busy_signal(48_000 * 2);
+        // SILK-only, where packet sizes follow the signal
+        let mut encoder = OpusEncoder::new();
+        encoder.set_sample_rate(16_000);
+        encoder.set_bitrate(16000);
+        encoder.set_mode(OpusEncodingMode::ConstrainedVbr);
+        let packets = audio_packets(&encoder.encode_samples(&samples, 48_000).unwrap());
+
+        // 16 kbps for 20 ms is 40 bytes
+        assert!(packets.iter().all(|packet| packet.len() <= 40));
+        let sizes: std::collections::BTreeSet<usize> = packets.iter().map(Vec::len).collect();
+        assert!(sizes.len() > 1, "constrained VBR still varies the packet size");
+    }
+
+    #[test]
+    fn complexity_is_checked() {
+        let mut encoder = OpusEncoder::new();
+        encoder.set_complexity(42);
+        assert_eq!(encoder.get_complexity(), 10);
+        encoder.set_complexity(0);
+        assert!(encoder.encode_samples(&busy_signal(4_800), 48_000).is_ok());
+    }
+
+    #[test]
+    fn dtx_makes_pauses_nearly_free() {
+        // A second of speech-like signal, three of silence, then another second
+        let mut samples = busy_signal(48_000);
+        samples.extend(std::iter::repeat_n(0.0, 48_000 * 3));
+        samples.extend(busy_signal(48_000));
+
+        let mut encoder = OpusEncoder::new();
+        encoder.set_sample_rate(16_000);
+        let plain = encoder.encode_samples(&samples, 48_000).unwrap();
+        encoder.set_dtx(true);
+        let dtx = encoder.encode_samples(&samples, 48_000).unwrap();
+
+        // Most of the 150 silent frames shrink to their TOC byte
+        let packets = audio_packets(&dtx);
+        let toc_only = packets.iter().filter(|packet| packet.len() == 1).count();
+        assert!(toc_only >= 120, "{} TOC-only packets", toc_only);
+        assert_eq!(packets.len(), audio_packets(&plain).len());
+        assert!(dtx.len() * 10 < plain.len() * 7, "{} vs {} bytes", dtx.len(), plain.len());
+
+        // Timing is unchanged: every sample is still there after decoding
+        let (decoded, _) = crate::decode_opus_bytes(&dtx).unwrap();
+        assert_eq!(decoded.len(), samples.len());
+        assert!(decoded[48_000 * 2..48_000 * 3].iter().all(|s| s.abs() < 0.01));
+
+        // The encoder ran over the pause too, so the packets after it are the ones
+        // a plain encode makes. The frame before them is sent in full, so the
+        // decoder is in the same state for them and the speech decodes exactly
+        // as it does without DTX
+        let (reference, _) = crate::decode_opus_bytes(&plain).unwrap();
+        assert!(decoded[48_000 * 4..] == reference[48_000 * 4..]);
+    }
+
+    #[test]
+    fn fec_and_loss_settings_are_checked() {
+        let mut encoder = OpusEncoder::new();
+        encoder.set_inband_fec(true);
+        encoder.set_packet_loss_perc(250);
+        assert!(encoder.get_inband_fec());
+        assert_eq!(encoder.get_packet_loss_perc(), 100);
+
+        // Settings loaded from a preset skip the setter
+        encoder.packet_loss_perc = 101;
+        assert!(matches!(
+            encoder.encode_samples(&busy_signal(960), 48_000),
+            Err(RusticAudioError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn zero_input_rate_is_an_invalid_argument() {
+        let encoder = OpusEncoder::new();
+        let samples = busy_signal(960);
+        assert!(matches!(encoder.encode_samples(&samples, 0), Err(RusticAudioError::InvalidArgument(_))));
+        assert!(matches!(
+            encoder.encode_with_size_budget(&samples, 0, 10_000),
+            Err(RusticAudioError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn every_frame_duration_round_trips() {
+        let samples = busy_signal(48_000);
+        for duration in OpusFrameDuration::ALL {
+            for (rate, mode) in [(48_000, OpusEncodingMode::Cbr), (16_000, OpusEncodingMode::Vbr), (8_000, OpusEncodingMode::Cbr)] {
+                let mut encoder = OpusEncoder::new();
+                encoder.set_frame_duration(duration);
+                encoder.set_sample_rate(rate);
+                encoder.set_mode(mode);
+                if duration < OpusFrameDuration::Ms10 && rate != 48_000 {
+                    assert!(matches!(encoder.validate_settings(), Err(RusticAudioError::InvalidArgument(_))));
+                    continue;
+                }
+                let stream = encoder.encode_samples(&samples, 48_000).unwrap();
+
+                let info = crate::OpusFileInfo::from_stream(&stream).unwrap();
+                assert_eq!(info.samples, 48_000, "{:?} at {} Hz", duration, rate);
+                let packets = audio_packets(&stream);
+                let longest = packets.iter().filter_map(|packet| packet_samples(packet)).max();
+                assert_eq!(longest, Some(duration.samples_48k()), "{:?} at {} Hz", duration, rate);
+                if mode == OpusEncodingMode::Cbr && duration > OpusFrameDuration::Ms20 {
+                    let sizes: Vec<usize> = packets[..packets.len() - 1].iter().map(Vec::len).collect();
+                    assert!(sizes.windows(2).all(|pair| pair[0] == pair[1]), "{:?}: {:?}", duration, sizes);
+                }
+
+                let (decoded, _) = crate::decode_opus_bytes(&stream).unwrap();
+                assert_eq!(decoded.len(), samples.len(), "{:?} at {} Hz", duration, rate);
+            }
+        }
+    }
+
+    #[test]
+    fn long_packets_cut_overhead_at_low_bitrates() {
+        let samples = busy_signal(48_000 * 10);
+        let mut encoder = OpusEncoder::new();
+        encoder.set_sample_rate(16_000);
+        encoder.set_mode(OpusEncodingMode::Vbr);
+        encoder.set_bitrate(8_000);
+        let short = encoder.encode_samples(&samples, 48_000).unwrap();
+        encoder.set_frame_duration(OpusFrameDuration::Ms120);
+        let long = encoder.encode_samples(&samples, 48_000).unwrap();
+        assert!(long.len() * 100 < short.len() * 97, "{} vs {} bytes", long.len(), short.len());
+
+        assert_eq!(OpusFrameDuration::from_milliseconds(2.5), Some(OpusFrameDuration::Ms2_5));
+        assert_eq!(OpusFrameDuration::from_milliseconds(30.0), None);
+    }
+
+    #[test]
+    fn impossible_budget_is_a_typed_error() {
+        let samples = busy_signal(48_000 * 5);
+        let result = OpusEncoder::new().encode_with_size_budget(&samples, 48_000, 1_000);
+        assert!(matches!(
+            result,
+            Err(RusticAudioError::SizeBudgetExceeded { max_bytes: 1_000, smallest }) if smallest > 1_000
+        ));
+    }
+}
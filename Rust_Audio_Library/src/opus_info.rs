@@ -0,0 +1,249 @@
+use ogg::reading::PacketReader;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Cursor;
+use crate::error::{Result, RusticAudioError};
+use crate::opus_packet::packet_frames;
+use crate::opus_tags::OpusTags;
+use crate::validate::{check_opus, read_opus_file, ParseLimits};
+
+// Ogg page header size before the lacing table
+const PAGE_HEADER_BYTES: usize = 27;
+
+/// What an Ogg Opus file holds, read from its headers, pages and packet TOC bytes.
+///
+/// Nothing is decoded, and nothing is assumed about frame sizes, so files from
+/// any encoder are measured exactly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpusFileInfo {
+    pub file_size: u64,
+    pub channels: u8,
+    /// Sample rate of the audio before encoding, from OpusHead. Zero if the encoder did not record it.
+    pub input_sample_rate: u32,
+    /// Samples at 48 kHz a decoder discards from the start.
+    pub pre_skip: u16,
+    /// Gain decoders apply to the output, in dB.
+    pub output_gain_db: f32,
+    /// Playable length in seconds: the final granule position minus the pre-skip.
+    pub duration: f64,
+    /// Playable length in samples at 48 kHz.
+    pub samples: u64,
+    /// Number of frames of each duration; a multi-frame packet counts every frame.
+    pub frame_sizes: BTreeMap<FrameDuration, usize>,
+    pub packet_count: usize,
+    /// Opus payload bitrate over all packets, pre-skip and trimmed samples included, in bits per second.
+    pub average_bitrate: f64,
+    /// Bitrate of the largest packet for its duration, in bits per second.
+    pub peak_bitrate: f64,
+    pub page_count: usize,
+    /// Bytes that are not Opus audio: page headers, lacing and the header packets.
+    pub container_overhead: u64,
+    pub tags: OpusTags,
+}
+
+/// Duration of one Opus frame, ordered from 2.5 ms to 60 ms.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct FrameDuration {
+    samples_48k: u32,
+}
+
+impl FrameDuration {
+    pub fn milliseconds(&self) -> f32 {
+        self.samples_48k as f32 / 48.0
+    }
+
+    /// Length of the frame in 48 kHz samples.
+    pub fn samples(&self) -> u32 {
+        self.samples_48k
+    }
+}
+
+impl fmt::Display for FrameDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ms", self.milliseconds())
+    }
+}
+
+impl OpusFileInfo {
+    /// Inspects an Ogg Opus stream held in memory.
+    ///
+    /// The stream is checked against `ParseLimits::default()` first.
+    pub fn from_stream(stream: &[u8]) -> Result<Self> {
+        Self::inspect(stream, &ParseLimits::default())
+    }
+
+    fn inspect(stream: &[u8], limits: &ParseLimits) -> Result<Self> {
+        let head = check_opus(stream, limits)?.head;
+        let mut packet_reader = PacketReader::new(Cursor::new(stream));
+
+        packet_reader.read_packet()?; // OpusHead
+        let tags = packet_reader.read_packet()?
+            .ok_or_else(|| RusticAudioError::OggParse("missing OpusTags packet".to_string()))?;
+        let tags = OpusTags::from_packet(&tags.data)?;
+
+        let pre_skip = head.pre_skip;
+        let mut frame_sizes = BTreeMap::new();
+        let mut packet_count = 0;
+        let mut audio_bytes = 0u64;
+        let mut decoded_samples = 0u64;
+        let mut peak_bitrate = 0f64;
+        let mut end_granule = None;
+
+        while let Some(packet) = packet_reader.read_packet()? {
+            end_granule = Some(packet.absgp_page());
+            // Empty packets, such as an end-of-stream marker, hold no audio
+            let Some((frame_samples, frames)) = packet_frames(&packet.data) else {
+                continue;
+            };
+
+            let duration = FrameDuration { samples_48k: frame_samples as u32 };
+            *frame_sizes.entry(duration).or_insert(0) += frames;
+
+            let samples = (frame_samples * frames) as u64;
+            if samples > 0 {
+                let bitrate = packet.data.len() as f64 * 8.0 * 48_000.0 / samples as f64;
+                peak_bitrate = peak_bitrate.max(bitrate);
+            }
+
+            packet_count += 1;
+            audio_bytes += packet.data.len() as u64;
+            decoded_samples += samples;
+        }
+
+        // The final granule counts the pre-skip and excludes trimmed end samples;
+        // without one, fall back to what the packets decode to
+        let end = end_granule.unwrap_or(decoded_samples);
+        let samples = end.saturating_sub(pre_skip as u64);
+        let duration = samples as f64 / 48_000.0;
+
+        Ok(Self {
+            file_size: stream.len() as u64,
+            channels: head.channels,
+            input_sample_rate: head.input_sample_rate,
+            pre_skip,
+            output_gain_db: head.output_gain_db(),
+            duration,
+            samples,
+            frame_sizes,
+            packet_count,
+            average_bitrate: if decoded_samples > 0 {
+                audio_bytes as f64 * 8.0 * 48_000.0 / decoded_samples as f64
+            } else {
+                0.0
+            },
+            peak_bitrate,
+            page_count: count_pages(stream)?,
+            container_overhead: stream.len() as u64 - audio_bytes,
+            tags,
+        })
+    }
+}
+
+/// Inspects the Ogg Opus file at `path`; see `OpusFileInfo`.
+pub fn read_opus_info(path: &str) -> Result<OpusFileInfo> {
+    read_opus_info_with_limits(path, &ParseLimits::default())
+}
+
+// For files the library wrote itself, which may exceed the default limits
+pub(crate) fn read_opus_info_with_limits(path: &str, limits: &ParseLimits) -> Result<OpusFileInfo> {
+    let (stream, _) = read_opus_file(path, limits)?;
+    OpusFileInfo::inspect(&stream, limits)
+}
+
+// Walks the Ogg page headers; the packet reader does not expose page boundaries
+fn count_pages(stream: &[u8]) -> Result<usize> {
+    let mut offset = 0;
+    let mut pages = 0;
+    while offset < stream.len() {
+        let header = stream.get(offset..offset + PAGE_HEADER_BYTES)
+            .filter(|header| header.starts_with(b"OggS"))
+            .ok_or_else(|| RusticAudioError::OggParse(format!("no Ogg page at byte {}", offset)))?;
+        let segments = header[26] as usize;
+        let lacing = stream.get(offset + PAGE_HEADER_BYTES..offset + PAGE_HEADER_BYTES + segments)
+            .ok_or_else(|| RusticAudioError::OggParse("truncated Ogg page header".to_string()))?;
+        let body: usize = lacing.iter().map(|&len| len as usize).sum();
+
+        offset += PAGE_HEADER_BYTES + segments + body;
+        pages += 1;
+    }
+    Ok(pages)
+}
+
+impl fmt::Display for OpusFileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "File size:          {} bytes", self.file_size)?;
+        writeln!(f, "Channels:           {}", self.channels)?;
+        writeln!(f, "Input sample rate:  {} Hz", self.input_sample_rate)?;
+        writeln!(f, "Pre-skip:           {} samples", self.pre_skip)?;
+        writeln!(f, "Output gain:        {:.2} dB", self.output_gain_db)?;
+        writeln!(f, "Duration:           {:.3} s ({} samples at 48 kHz)", self.duration, self.samples)?;
+        writeln!(f, "Packets:            {}", self.packet_count)?;
+        for (duration, count) in &self.frame_sizes {
+            writeln!(f, "  {:>7} frames:   {}", duration.to_string(), count)?;
+        }
+        writeln!(f, "Average bitrate:    {:.0} bps", self.average_bitrate)?;
+        writeln!(f, "Peak bitrate:       {:.0} bps", self.peak_bitrate)?;
+        writeln!(f, "Pages:              {}", self.page_count)?;
+        write!(f, "Container overhead: {} bytes", self.container_overhead)?;
+        if !self.tags.vendor().is_empty() {
+            write!(f, "\nVendor:             {}", self.tags.vendor())?;
+        }
+        for (key, value) in self.tags.comments() {
+            write!(f, "\n  {}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_pages, OpusFileInfo};
+    use crate::{pad_to_size, OpusEncoder, OpusTags};
+
+    #[test]
+    fn measures_a_stream_exactly() {
+        let mut tags = OpusTags::new();
+        tags.set("LANGUAGE", "en").unwrap();
+        let mut encoder = OpusEncoder::new();
+        encoder.set_tags(tags.clone());
+        encoder.set_bitrate(24_000);
+        let stream = encoder.encode_samples(&vec![0.2; 50_000], 48_000).unwrap();
+
+        let info = OpusFileInfo::from_stream(&stream).unwrap();
+        assert_eq!(info.channels, 1);
+        assert_eq!(info.input_sample_rate, 48_000);
+        assert_eq!(info.samples, 50_000);
+        assert!((info.duration - 50_000.0 / 48_000.0).abs() < 1e-9);
+        assert_eq!(info.tags, tags);
+
+        // Input plus lookahead, in 20 ms frames
+        let frames = (50_000 + info.pre_skip as usize).div_ceil(960);
+        assert_eq!(info.packet_count, frames);
+        assert_eq!(info.frame_sizes.len(), 1);
+        let (duration, count) = info.frame_sizes.iter().next().unwrap();
+        assert_eq!((duration.milliseconds(), *count), (20.0, frames));
+
+        assert!(info.average_bitrate > 0.0 && info.peak_bitrate >= info.average_bitrate);
+        assert_eq!(info.page_count, 3);
+        assert!(info.container_overhead > 0 && info.container_overhead < info.file_size);
+    }
+
+    #[test]
+    fn padding_counts_as_overhead_not_audio() {
+        let stream = OpusEncoder::new().encode_samples(&vec![0.1; 9_600], 48_000).unwrap();
+        let padded = pad_to_size(&stream, stream.len() + 1_000).unwrap();
+
+        let plain = OpusFileInfo::from_stream(&stream).unwrap();
+        let info = OpusFileInfo::from_stream(&padded).unwrap();
+        assert_eq!(info.container_overhead, plain.container_overhead + 1_000);
+        assert_eq!(info.average_bitrate, plain.average_bitrate);
+        assert_eq!(info.page_count, count_pages(&padded).unwrap());
+    }
+
+    #[test]
+    fn garbage_after_a_page_is_an_error() {
+        let mut stream = OpusEncoder::new().encode_samples(&vec![0.1; 960], 48_000).unwrap();
+        stream.extend_from_slice(b"junk");
+        assert!(OpusFileInfo::from_stream(&stream).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+// Helpers for reading the framing of raw Opus packets (RFC 6716, section 3.1)
+
+/// Number of 48 kHz samples an Opus packet decodes to, read from its TOC byte.
+///
+/// Returns `None` for an empty packet or a code 3 packet without its frame count byte.
+pub(crate) fn packet_samples(packet: &[u8]) -> Option<usize> {
+    let (frame_samples, frames) = packet_frames(packet)?;
+    Some(frame_samples * frames)
+}
+
+/// Samples per frame at 48 kHz and number of frames of an Opus packet.
+pub(crate) fn packet_frames(packet: &[u8]) -> Option<(usize, usize)> {
+    let toc = *packet.first()?;
+    let config = (toc >> 3) as usize;
+
+    let frame_samples = match config {
+        // SILK-only: 10, 20, 40, 60 ms
+        0..=11 => [480, 960, 1920, 2880][config % 4],
+        // Hybrid: 10, 20 ms
+        12..=15 => [480, 960][config % 2],
+        // CELT-only: 2.5, 5, 10, 20 ms
+        _ => [120, 240, 480, 960][config % 4],
+    };
+
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*packet.get(1)? & 0x3f) as usize,
+    };
+
+    Some((frame_samples, frames))
+}
+
+/// Compressed data of each frame in an Opus packet (RFC 6716, section 3.2).
+///
+/// Returns `None` for packets whose framing is invalid. A frame may be empty,
+/// which marks it as lost or in discontinuous transmission.
+pub(crate) fn frame_payloads(packet: &[u8]) -> Option<Vec<&[u8]>> {
+    let toc = *packet.first()?;
+    let data = &packet[1..];
+
+    match toc & 0x03 {
+        0 => Some(vec![data]),
+        1 => {
+            if !data.len().is_multiple_of(2) {
+                return None;
+            }
+            let (first, second) = data.split_at(data.len() / 2);
+            Some(vec![first, second])
+        }
+        2 => {
+            let (first_len, used) = frame_length(data)?;
+            let rest = data.get(used..)?;
+            (first_len <= rest.len()).then(|| {
+                let (first, second) = rest.split_at(first_len);
+                vec![first, second]
+            })
+        }
+        _ => {
+            let (&count, mut data) = data.split_first()?;
+            let frames = (count & 0x3f) as usize;
+            if frames == 0 {
+                return None;
+            }
+
+            if count & 0x40 != 0 {
+                // Padding length: each 255 adds 254 and continues
+                let mut padding = 0;
+                loop {
+                    let (&byte, rest) = data.split_first()?;
+                    data = rest;
+                    padding += if byte == 255 { 254 } else { byte as usize };
+                    if byte != 255 {
+                        break;
+                    }
+                }
+                data = &data[..data.len().checked_sub(padding)?];
+            }
+
+            if count & 0x80 == 0 {
+                // Constant bitrate: every frame has the same length
+                if data.len() % frames != 0 {
+                    return None;
+                }
+                let len = data.len() / frames;
+                return Some((0..frames).map(|i| &data[i * len..(i + 1) * len]).collect());
+            }
+
+            // Variable bitrate: the length of every frame but the last precedes the data
+            let mut lengths = Vec::with_capacity(frames);
+            for _ in 1..frames {
+                let (len, used) = frame_length(data)?;
+                data = &data[used..];
+                lengths.push(len);
+            }
+            let mut payloads = Vec::with_capacity(frames);
+            for len in lengths {
+                if len > data.len() {
+                    return None;
+                }
+                let (frame, rest) = data.split_at(len);
+                payloads.push(frame);
+                data = rest;
+            }
+            payloads.push(data);
+            Some(payloads)
+        }
+    }
+}
+
+// One or two byte frame length and the number of bytes it took
+fn frame_length(data: &[u8]) -> Option<(usize, usize)> {
+    let first = *data.first()? as usize;
+    if first < 252 {
+        return Some((first, 1));
+    }
+    let second = *data.get(1)? as usize;
+    Some((first + 4 * second, 2))
+}
+
+/// Builds a code 3 packet holding `payloads` as frames of the configuration in `toc`.
+///
+/// Frames of equal length are stored as CBR, others with their lengths. With
+/// `size`, a shorter packet is padded up to that many bytes.
+pub(crate) fn join_frames(toc: u8, payloads: &[&[u8]], size: Option<usize>) -> Vec<u8> {
+    let cbr = payloads.windows(2).all(|pair| pair[0].len() == pair[1].len());
+    let mut lengths = Vec::new();
+    if !cbr {
+        for payload in &payloads[..payloads.len() - 1] {
+            let len = payload.len();
+            if len < 252 {
+                lengths.push(len as u8);
+            } else {
+                let first = 252 + (len & 3);
+                lengths.extend_from_slice(&[first as u8, ((len - first) / 4) as u8]);
+            }
+        }
+    }
+
+    let frames_len: usize = payloads.iter().map(|payload| payload.len()).sum();
+    let unpadded = 2 + lengths.len() + frames_len;
+    let mut packet = vec![(toc & !0x03) | 3, payloads.len() as u8 | if cbr { 0 } else { 0x80 }];
+
+    let mut padding = 0;
+    if let Some(size) = size.filter(|&size| size > unpadded) {
+        packet[1] |= 0x40;
+        // Bytes the padding takes, its own length bytes included; each 255
+        // stands for 254 bytes of padding and another length byte
+        let mut extra = size - unpadded;
+        while extra > 255 {
+            packet.push(255);
+            padding += 254;
+            extra -= 255;
+        }
+        packet.push((extra - 1) as u8);
+        padding += extra - 1;
+    }
+
+    packet.extend_from_slice(&lengths);
+    for payload in payloads {
+        packet.extend_from_slice(payload);
+    }
+    packet.resize(packet.len() + padding, 0);
+    packet
+}
+
+/// Whether a packet was coded in the CELT-only mode, read from its TOC byte.
+pub(crate) fn is_celt_only(packet: &[u8]) -> bool {
+    packet.first().is_some_and(|&toc| toc >> 3 >= 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frame_payloads, is_celt_only, join_frames, packet_frames, packet_samples};
+
+    #[test]
+    fn reads_duration_from_toc() {
+        // CELT fullband 20 ms, one frame
+        assert_eq!(packet_samples(&[31 << 3, 0xff]), Some(960));
+        // SILK narrowband 60 ms, two frames
+        assert_eq!(packet_samples(&[(3 << 3) | 1]), Some(5760));
+        // CELT 2.5 ms, code 3 with five frames
+        assert_eq!(packet_samples(&[(16 << 3) | 3, 5]), Some(600));
+        assert_eq!(packet_samples(&[(16 << 3) | 3]), None);
+        assert_eq!(packet_samples(&[]), None);
+        assert_eq!(packet_frames(&[(16 << 3) | 3, 5]), Some((120, 5)));
+
+        assert!(is_celt_only(&[31 << 3]));
+        assert!(!is_celt_only(&[15 << 3]));
+        assert!(!is_celt_only(&[]));
+    }
+
+    #[test]
+    fn splits_packets_into_frames() {
+        let toc = 1 << 3;
+        assert_eq!(frame_payloads(&[toc, 1, 2, 3]), Some(vec![&[1u8, 2, 3][..]]));
+        // A TOC byte alone is one empty frame
+        assert_eq!(frame_payloads(&[toc]), Some(vec![&[][..]]));
+        assert_eq!(frame_payloads(&[toc | 1, 1, 2, 3, 4]), Some(vec![&[1u8, 2][..], &[3, 4][..]]));
+        assert_eq!(frame_payloads(&[toc | 1, 1, 2, 3]), None);
+        assert_eq!(frame_payloads(&[toc | 2, 1, 9, 3, 4]), Some(vec![&[9u8][..], &[3, 4][..]]));
+        assert_eq!(frame_payloads(&[toc | 2, 5, 9]), None);
+
+        // Code 3, constant bitrate, three frames of one byte plus two bytes of padding
+        assert_eq!(
+            frame_payloads(&[toc | 3, 0x40 | 3, 2, 7, 8, 9, 0, 0]),
+            Some(vec![&[7u8][..], &[8][..], &[9][..]])
+        );
+        // Code 3, variable bitrate: lengths 2 and 1, last frame takes the rest
+        assert_eq!(
+            frame_payloads(&[toc | 3, 0x80 | 3, 2, 1, 5, 6, 7, 8, 9]),
+            Some(vec![&[5u8, 6][..], &[7][..], &[8, 9][..]])
+        );
+        // Two-byte length: 252 + 4 * 1 = 256 bytes, more than the packet holds
+        assert_eq!(frame_payloads(&[toc | 3, 0x80 | 2, 252, 1, 0]), None);
+        assert_eq!(frame_payloads(&[toc | 3, 0]), None);
+        assert_eq!(frame_payloads(&[]), None);
+    }
+
+    #[test]
+    fn joins_frames_into_one_packet() {
+        let long = vec![7u8; 600];
+        let frames: [&[u8]; 3] = [&[1, 2, 3], &long, &[]];
+        let packet = join_frames(9 << 3, &frames, None);
+        assert_eq!(packet[..2], [(9 << 3) | 3, 0x83]);
+        assert_eq!(packet_samples(&packet), Some(2880));
+        assert_eq!(frame_payloads(&packet).unwrap(), frames);
+
+        // Equal frames need no lengths, and padding reaches any size
+        let frames: [&[u8]; 2] = [&[5; 40], &[6; 40]];
+        assert_eq!(join_frames(31 << 3, &frames, None).len(), 82);
+        for size in [83, 84, 337, 338, 339, 600] {
+            let padded = join_frames(31 << 3, &frames, Some(size));
+            assert_eq!(padded.len(), size);
+            assert_eq!(frame_payloads(&padded).unwrap(), frames);
+        }
+    }
+}
@@ -1,158 +1,138 @@
 use ogg::reading::PacketReader;
-use opus_rs::OpusDecoder;
-use std::fs::File;
-use std::io::BufReader;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::io::Cursor;
+use crate::error::Result;
+use crate::opus_decoder::{playable_range, OpusPacketDecoder};
+use crate::opus_packet::packet_samples;
+use crate::playback::PlaybackSource;
+use crate::validate::{read_opus_file, ParseLimits};
 
 const SAMPLE_RATE: u32 = 48_000;
-const FRAME_SIZE: usize = 960;
-
-pub fn get_opus_info(file_path: &str) -> Result<(u64, f64), Box<dyn std::error::Error>> {
-    let file = File::open(file_path)?;
-    let file_size = file.metadata()?.len();
-    
-    // Count the number of audio packets to estimate duration
-    let reader = BufReader::new(file);
-    let mut packet_reader = PacketReader::new(reader);
-    
-    // Skip headers
-    packet_reader.read_packet()?; // OpusHead
-    packet_reader.read_packet()?; // OpusTags
-    
-    let mut packet_count = 0;
-    while let Ok(Some(_)) = packet_reader.read_packet() {
-        packet_count += 1;
-    }
-    
-    // Each packet is 20ms of audio
-    let duration = (packet_count as f64) * 0.02;
-    
-    Ok((file_size, duration))
+// Audio decoded ahead of a seek target so the decoder has converged by the
+// time it is reached; RFC 7845 recommends 80 ms
+const SEEK_PREROLL: u64 = 3840;
+
+/// An Ogg Opus file, decoded a packet at a time as it plays.
+pub(crate) struct OpusSource {
+    packets: Vec<Vec<u8>>,
+    // Granule position at the start of each packet
+    starts: Vec<u64>,
+    channels: u16,
+    pre_skip: u64,
+    // Final granule position, where playback stops
+    end: u64,
+    gain: f32,
+    decoder: OpusPacketDecoder,
+    next_packet: usize,
+    // Decoded samples and the index of the next one to play
+    pending: Vec<f32>,
+    pending_index: usize,
+    // Samples before this granule position are decoded but not played: the
+    // encoder delay, or the audio between a seek's preroll and its target
+    skip_until: u64,
 }
 
-pub fn playback_opus(file_path: &str, is_playing_flag: Arc<AtomicBool>) -> Result<(), Box<dyn std::error::Error>> {
-    // Create Opus decoder (48kHz is the default for Opus)
-    let decoder = OpusDecoder::new(SAMPLE_RATE as i32, 1)
-        .map_err(std::io::Error::other)?;
-
-    // Open Opus file
-    let file = BufReader::new(File::open(file_path)?);
-    let packet_reader = PacketReader::new(file);
-
-    // Skip header packets
-    let mut packet_reader = packet_reader;
-    packet_reader.read_packet()?; // OpusHead
-    packet_reader.read_packet()?; // OpusTags
-
-    // Setup audio output
-    let host = cpal::default_host();
-    let device = host.default_output_device()
-        .expect("Failed to get default output device");
-    let config = device.default_output_config()?;
-
-    // Force 48kHz output config
-    let output_config = cpal::StreamConfig {
-        channels: config.channels(),
-        sample_rate: SAMPLE_RATE,
-        buffer_size: cpal::BufferSize::Default,
-    };
-
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => {
-            let decoder = Arc::new(std::sync::Mutex::new(decoder));
-            let packet_reader = Arc::new(std::sync::Mutex::new(packet_reader));
-            let is_playing = Arc::clone(&is_playing_flag);
-            
-            // Create a fixed-size buffer for decoded audio
-            let decoded_buffer = Arc::new(std::sync::Mutex::new(vec![0f32; FRAME_SIZE]));
-            let decoded_samples = Arc::new(std::sync::Mutex::new(0));
-            let buffer_position = Arc::new(std::sync::Mutex::new(0)); // Track position in decoded buffer
-
-            device.build_output_stream(
-                &output_config,
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                    if is_playing.load(Ordering::Relaxed) {
-                        let channels = output_config.channels as usize;
-                        let mut pos = 0;
-
-                        while pos < data.len() {
-                            // Check if we need more samples from the current buffer
-                            let (need_new_packet, _current_pos) = {
-                                let samples = *decoded_samples.lock().unwrap();
-                                let position = *buffer_position.lock().unwrap();
-                                (position >= samples, position)
-                            };
+impl OpusSource {
+    pub(crate) fn open(file_path: &str) -> Result<Self> {
+        // Validation also yields the pre-skip and the final granule position, which
+        // together bound the samples a decoder plays. Played files are local
+        // recordings, so they are not held to the untrusted-input limits
+        let (stream, summary) = read_opus_file(file_path, &ParseLimits::trusted())?;
+        let mut packet_reader = PacketReader::new(Cursor::new(stream));
+        packet_reader.read_packet()?; // OpusHead
+        packet_reader.read_packet()?; // OpusTags
+
+        let mut packets = Vec::new();
+        let mut starts = Vec::new();
+        let mut granule = 0u64;
+        while let Some(packet) = packet_reader.read_packet()? {
+            // Empty packets, such as an end-of-stream marker, hold no audio
+            let Some(samples) = packet_samples(&packet.data) else {
+                continue;
+            };
+            starts.push(granule);
+            granule += samples as u64;
+            packets.push(packet.data);
+        }
+
+        let head = summary.head;
+        Ok(Self {
+            packets,
+            starts,
+            channels: head.channels as u16,
+            pre_skip: head.pre_skip as u64,
+            end: summary.end_granule.min(granule),
+            gain: 10f32.powf(head.output_gain_db() / 20.0),
+            decoder: OpusPacketDecoder::new(head.channels as u16)?,
+            next_packet: 0,
+            pending: Vec::new(),
+            pending_index: 0,
+            skip_until: head.pre_skip as u64,
+        })
+    }
 
-                            if need_new_packet {
-                                // Reset buffer position
-                                *buffer_position.lock().unwrap() = 0;
-                                
-                                if let Ok(mut reader) = packet_reader.lock() {
-                                    if let Ok(Some(packet)) = reader.read_packet() {
-                                        if let Ok(mut decoder) = decoder.lock() {
-                                            if let Ok(mut buffer) = decoded_buffer.lock() {
-                                                if let Ok(n_samples) = decoder.decode(&packet.data, FRAME_SIZE, &mut buffer) {
-                                                    *decoded_samples.lock().unwrap() = n_samples;
-                                                } else {
-                                                    is_playing.store(false, Ordering::Relaxed);
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                    } else {
-                                        is_playing.store(false, Ordering::Relaxed);
-                                        break;
-                                    }
-                                }
-                            } else {
-                                // Copy available samples to output
-                                if let (Ok(buffer), Ok(samples), Ok(mut position)) = 
-                                    (decoded_buffer.lock(), decoded_samples.lock(), buffer_position.lock()) 
-                                {
-                                    let samples_remaining = *samples - *position;
-                                    if samples_remaining > 0 {
-                                        let samples_to_copy = ((data.len() - pos) / channels).min(samples_remaining);
-                                        
-                                        // Copy to all channels
-                                        for i in 0..samples_to_copy {
-                                            let sample = buffer[*position + i];
-                                            for c in 0..channels {
-                                                data[pos + i * channels + c] = sample;
-                                            }
-                                        }
+    // Decodes the next packet into `pending`; false at the end of the stream
+    fn decode_next(&mut self) -> Result<bool> {
+        let Some(packet) = self.packets.get(self.next_packet) else {
+            return Ok(false);
+        };
+        let start = self.starts[self.next_packet];
+        self.next_packet += 1;
+
+        let channels = self.channels as usize;
+        let mut decoded = self.decoder.decode(packet)?;
+        let (from, to) = playable_range(start, decoded.len() / channels, self.skip_until, self.end);
+        decoded.truncate(to * channels);
+        self.pending = decoded;
+        self.pending_index = from * channels;
+        Ok(true)
+    }
+}
 
-                                        pos += samples_to_copy * channels;
-                                        *position += samples_to_copy;
-                                    }
-                                }
-                            }
-                        }
+impl PlaybackSource for OpusSource {
+    fn channels(&self) -> u16 {
+        self.channels
+    }
 
-                        // Fill any remaining space with silence
-                        for sample in data[pos..].iter_mut() {
-                            *sample = 0.0;
-                        }
-                    } else {
-                        // Output silence when not playing
-                        for sample in data.iter_mut() {
-                            *sample = 0.0;
-                        }
-                    }
-                },
-                |err| eprintln!("Playback error: {:?}", err),
-                None,
-            )?
-        },
-        _ => return Err("Unsupported output format".into()),
-    };
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
 
-    stream.play()?;
+    fn len(&self) -> u64 {
+        self.end.saturating_sub(self.pre_skip)
+    }
 
-    while is_playing_flag.load(Ordering::Relaxed) {
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    fn read(&mut self, out: &mut [f32]) -> Result<usize> {
+        let channels = self.channels as usize;
+        let wanted = out.len() / channels * channels;
+        let mut written = 0;
+
+        while written < wanted {
+            if self.pending_index >= self.pending.len() {
+                if !self.decode_next()? {
+                    break;
+                }
+                continue;
+            }
+            let count = (wanted - written).min(self.pending.len() - self.pending_index);
+            let samples = &self.pending[self.pending_index..self.pending_index + count];
+            for (out, &sample) in out[written..written + count].iter_mut().zip(samples) {
+                *out = sample * self.gain;
+            }
+            written += count;
+            self.pending_index += count;
+        }
+        Ok(written / channels)
     }
 
-    Ok(())
-} 
\ No newline at end of file
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        let target = self.pre_skip + frame.min(self.len());
+        // Start from the last packet that begins at least the preroll before the target
+        let preroll_start = target.saturating_sub(SEEK_PREROLL);
+        self.next_packet = self.starts.partition_point(|&start| start <= preroll_start).saturating_sub(1);
+        self.decoder = OpusPacketDecoder::new(self.channels)?;
+        self.pending.clear();
+        self.pending_index = 0;
+        self.skip_until = target;
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use ogg::reading::PacketReader;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use crate::dsp::AudioProcessor;
+use crate::error::{Result, RusticAudioError};
+use crate::validate::{check_opus, read_opus_file, ParseLimits};
+
+const VENDOR: &str = "rustic_audio";
+
+/// Comment holding the ReplayGain-style track gain (RFC 7845, section 5.2.1).
+pub const R128_TRACK_GAIN: &str = "R128_TRACK_GAIN";
+/// Comment holding the DSP chain the audio went through, as compact JSON.
+pub const DSP_CHAIN: &str = "RUSTIC_DSP_CHAIN";
+
+/// DSP chain recorded under `RUSTIC_DSP_CHAIN`.
+///
+/// Holds the effect names in processing order together with every processor
+/// setting they were built from, so the chain can be rebuilt exactly.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DspChainInfo {
+    pub effects: Vec<String>,
+    pub settings: AudioProcessor,
+}
+
+/// Vendor string and `KEY=value` user comments of an OpusTags header.
+///
+/// Keys are case-insensitive and stored in upper case. A key may appear more
+/// than once; `get` and `to_map` return its first value and `comments` keeps
+/// every entry in order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpusTags {
+    vendor: String,
+    comments: Vec<(String, String)>,
+}
+
+impl Default for OpusTags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpusTags {
+    /// Tags with this library as the vendor and no comments.
+    pub fn new() -> Self {
+        Self {
+            vendor: VENDOR.to_string(),
+            comments: Vec::new(),
+        }
+    }
+
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+
+    /// Appends a comment, keeping any earlier values of the same key.
+    ///
+    /// Keys must be non-empty printable ASCII without `=`.
+    pub fn add(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = normalize_key(key)?;
+        self.comments.push((key, value.to_string()));
+        Ok(())
+    }
+
+    /// Sets a comment, replacing every earlier value of the same key.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = normalize_key(key)?;
+        self.comments.retain(|(existing, _)| *existing != key);
+        self.comments.push((key, value.to_string()));
+        Ok(())
+    }
+
+    /// Removes every value of `key`.
+    pub fn remove(&mut self, key: &str) {
+        let key = key.to_ascii_uppercase();
+        self.comments.retain(|(existing, _)| *existing != key);
+    }
+
+    /// First value of `key`, if present.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = key.to_ascii_uppercase();
+        self.comments
+            .iter()
+            .find(|(existing, _)| *existing == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All comments in the order they are stored.
+    pub fn comments(&self) -> &[(String, String)] {
+        &self.comments
+    }
+
+    /// Comments as a map from key to its first value.
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        for (key, value) in &self.comments {
+            map.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        map
+    }
+
+    /// Sets `R128_TRACK_GAIN`, the gain in dB that brings the track to -23 LUFS.
+    ///
+    /// Stored as a Q7.8 fixed-point integer, so it is rounded to 1/256 dB and
+    /// clamped to -128..128 dB.
+    pub fn set_track_gain(&mut self, gain_db: f32) {
+        let q78 = (gain_db * 256.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        self.comments.retain(|(key, _)| key != R128_TRACK_GAIN);
+        self.comments.push((R128_TRACK_GAIN.to_string(), q78.to_string()));
+    }
+
+    /// `R128_TRACK_GAIN` in dB, if present and valid.
+    pub fn track_gain(&self) -> Option<f32> {
+        let q78: i16 = self.get(R128_TRACK_GAIN)?.trim().parse().ok()?;
+        Some(q78 as f32 / 256.0)
+    }
+
+    /// Records the chain of `processor` and all of its settings under `RUSTIC_DSP_CHAIN`.
+    ///
+    /// The value is a compact JSON `DspChainInfo`, e.g.
+    /// `{"effects":["rms","limiter"],"settings":{"sample_rate":48000.0,...}}`.
+    pub fn set_dsp_chain(&mut self, processor: &AudioProcessor) -> Result<()> {
+        let info = DspChainInfo {
+            effects: processor.chain().names().into_iter().map(str::to_string).collect(),
+            settings: processor.clone(),
+        };
+        let description = serde_json::to_string(&info)
+            .map_err(|e| RusticAudioError::InvalidArgument(e.to_string()))?;
+        self.comments.retain(|(key, _)| key != DSP_CHAIN);
+        self.comments.push((DSP_CHAIN.to_string(), description));
+        Ok(())
+    }
+
+    /// The chain stored under `RUSTIC_DSP_CHAIN`, if present and valid.
+    pub fn dsp_chain(&self) -> Option<DspChainInfo> {
+        serde_json::from_str(self.get(DSP_CHAIN)?).ok()
+    }
+
+    /// Serializes the tags as an OpusTags packet.
+    pub fn to_packet(&self) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(b"OpusTags");
+        packet.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        packet.extend_from_slice(self.vendor.as_bytes());
+        packet.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+        for (key, value) in &self.comments {
+            let comment = format!("{}={}", key, value);
+            packet.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            packet.extend_from_slice(comment.as_bytes());
+        }
+        packet
+    }
+
+    /// Parses an OpusTags packet.
+    ///
+    /// Comments without `=` are ignored, as are padding and application data
+    /// after the comment list.
+    pub fn from_packet(packet: &[u8]) -> Result<Self> {
+        // Validates every length before the slicing below
+        comments_end(packet)?;
+
+        let mut offset = 8;
+        let vendor_len = read_len(packet, offset)?;
+        let vendor = String::from_utf8_lossy(&packet[offset + 4..offset + 4 + vendor_len]).into_owned();
+        offset += 4 + vendor_len;
+
+        let count = read_len(packet, offset)?;
+        offset += 4;
+        let mut comments = Vec::new();
+        for _ in 0..count {
+            let len = read_len(packet, offset)?;
+            let comment = String::from_utf8_lossy(&packet[offset + 4..offset + 4 + len]);
+            offset += 4 + len;
+
+            if let Some((key, value)) = comment.split_once('=') {
+                if let Ok(key) = normalize_key(key) {
+                    comments.push((key, value.to_string()));
+                }
+            }
+        }
+        Ok(Self { vendor, comments })
+    }
+
+    /// Reads the tags of an Ogg Opus stream held in memory.
+    ///
+    /// The stream is checked against `ParseLimits::default()` first.
+    pub fn from_stream(stream: &[u8]) -> Result<Self> {
+        check_opus(stream, &ParseLimits::default())?;
+        let mut packet_reader = PacketReader::new(Cursor::new(stream));
+
+        packet_reader.read_packet()?; // OpusHead
+        let tags = packet_reader.read_packet()?
+            .ok_or_else(|| RusticAudioError::OggParse("missing OpusTags packet".to_string()))?;
+        OpusTags::from_packet(&tags.data)
+    }
+}
+
+/// Reads the OpusTags comments of the Ogg Opus file at `path`.
+pub fn read_opus_tags(path: &str) -> Result<OpusTags> {
+    let (stream, _) = read_opus_file(path, &ParseLimits::default())?;
+    OpusTags::from_stream(&stream)
+}
+
+fn normalize_key(key: &str) -> Result<String> {
+    // Vorbis comment field names: printable ASCII except '='
+    if key.is_empty() || !key.bytes().all(|b| (0x20..=0x7d).contains(&b) && b != b'=') {
+        return Err(RusticAudioError::InvalidArgument(format!(
+            "\"{}\" is not a valid comment key", key
+        )));
+    }
+    Ok(key.to_ascii_uppercase())
+}
+
+fn read_len(packet: &[u8], offset: usize) -> Result<usize> {
+    let bytes = packet.get(offset..offset + 4)
+        .ok_or_else(|| RusticAudioError::OggParse("malformed OpusTags packet".to_string()))?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+/// Offset just past the user comment list of an OpusTags packet.
+pub(crate) fn comments_end(tags: &[u8]) -> Result<usize> {
+    let invalid = || RusticAudioError::OggParse("malformed OpusTags packet".to_string());
+    if !tags.starts_with(b"OpusTags") {
+        return Err(invalid());
+    }
+
+    let mut offset = 8;
+    let vendor_len = read_len(tags, offset)?;
+    offset = offset.checked_add(4 + vendor_len).ok_or_else(invalid)?;
+
+    let count = read_len(tags, offset)?;
+    offset += 4;
+    for _ in 0..count {
+        let comment_len = read_len(tags, offset)?;
+        offset = offset.checked_add(4 + comment_len).ok_or_else(invalid)?;
+    }
+
+    if offset > tags.len() {
+        return Err(invalid());
+    }
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OpusTags, R128_TRACK_GAIN};
+    use crate::{AudioProcessor, OpusEncoder};
+
+    #[test]
+    fn comments_round_trip_through_an_encoded_stream() {
+        let mut tags = OpusTags::new();
+        tags.set("language", "de-CH").unwrap();
+        tags.set("MESSAGE_ID", "b7f0=42").unwrap();
+        tags.add("artist", "first").unwrap();
+        tags.add("ARTIST", "second").unwrap();
+        tags.set_track_gain(-3.5);
+        let processor = AudioProcessor {
+            amplitude_gate_enabled: true,
+            amplitude_threshold_db: -37.5,
+            rms_target_db: -16.0,
+            ..AudioProcessor::default()
+        };
+        tags.set_dsp_chain(&processor).unwrap();
+
+        let mut encoder = OpusEncoder::new();
+        encoder.set_tags(tags.clone());
+        let stream = encoder.encode_samples(&vec![0.0; 4_800], 48_000).unwrap();
+
+        let read = OpusTags::from_stream(&stream).unwrap();
+        assert_eq!(read, tags);
+        assert_eq!(read.vendor(), "rustic_audio");
+
+        let map = read.to_map();
+        assert_eq!(map["LANGUAGE"], "de-CH");
+        // Only the first '=' separates key and value
+        assert_eq!(map["MESSAGE_ID"], "b7f0=42");
+        assert_eq!(map["ARTIST"], "first");
+        assert_eq!(read.comments().len(), 6);
+
+        assert_eq!(read.get(R128_TRACK_GAIN), Some("-896"));
+        assert_eq!(read.track_gain(), Some(-3.5));
+        let chain = read.dsp_chain().unwrap();
+        assert_eq!(chain.effects, processor.chain().names());
+        assert_eq!(chain.settings, processor);
+        assert_eq!(chain.settings.amplitude_threshold_db, -37.5);
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        let mut tags = OpusTags::new();
+        assert!(tags.set("", "value").is_err());
+        assert!(tags.set("A=B", "value").is_err());
+        assert!(tags.add("TITLE\u{e9}", "value").is_err());
+        assert!(tags.comments().is_empty());
+    }
+
+    #[test]
+    fn malformed_packets_are_errors() {
+        assert!(OpusTags::from_packet(b"OpusTags").is_err());
+        // Claims one comment of 100 bytes that is not there
+        let mut packet = OpusTags::new().to_packet();
+        packet.truncate(packet.len() - 4);
+        packet.extend_from_slice(&1u32.to_le_bytes());
+        packet.extend_from_slice(&100u32.to_le_bytes());
+        assert!(OpusTags::from_packet(&packet).is_err());
+    }
+}
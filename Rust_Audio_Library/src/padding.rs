@@ -0,0 +1,285 @@
+use ogg::reading::PacketReader;
+use ogg::writing::PacketWriteEndInfo;
+use ogg::PacketWriter;
+use std::io::Cursor;
+use crate::error::{Result, RusticAudioError};
+use crate::opus_packet::packet_samples;
+use crate::opus_tags::comments_end;
+use crate::validate::{check_opus, read_opus_file, ParseLimits};
+
+/// Size classes used when none are configured: 8, 16, 32 and 64 KiB.
+pub const DEFAULT_SIZE_CLASSES: &[usize] = &[8 * 1024, 16 * 1024, 32 * 1024, 64 * 1024];
+
+// Page breaks the padder may add after early audio packets when the OpusTags
+// length alone cannot land on the exact target (lacing and page headers grow in steps)
+const MAX_EXTRA_PAGES: usize = 4;
+
+struct Stream {
+    serial: u32,
+    head: Vec<u8>,
+    tags: Vec<u8>,
+    // Audio packets with the granule position at which each one ends
+    audio: Vec<(Vec<u8>, u64)>,
+}
+
+/// Pads an Ogg Opus stream to the smallest size class that holds it.
+///
+/// Every stream padded into the same class has exactly the same length, so the
+/// file size no longer reveals the message duration. The padding is stored as
+/// discardable data after the OpusTags comments (RFC 7845, section 5.2), so any
+/// Opus decoder plays the file unchanged. Existing padding is replaced.
+///
+/// Returns `RusticAudioError::SizeBudgetExceeded` if the stream is larger than
+/// the largest class.
+pub fn pad_to_size_class(stream: &[u8], size_classes: &[usize]) -> Result<Vec<u8>> {
+    pad_class(stream, size_classes, &ParseLimits::default())
+}
+
+/// Pads an Ogg Opus stream to exactly `target` bytes.
+pub fn pad_to_size(stream: &[u8], target: usize) -> Result<Vec<u8>> {
+    pad_exact(stream, target, &ParseLimits::default())
+}
+
+/// Removes size-class padding, returning a stream that decodes to the same audio.
+///
+/// Only data the Opus specification marks as discardable is removed; comments
+/// and application data flagged for preservation are kept. Pages are rebuilt
+/// with the same serial number and granule positions, so stripping a stream
+/// written by `OpusEncoder` returns the original bytes.
+pub fn strip_padding(stream: &[u8]) -> Result<Vec<u8>> {
+    strip(stream, &ParseLimits::default())
+}
+
+/// Pads the Ogg Opus file at `path` in place and returns its new size.
+///
+/// The file is taken to be the caller's own, such as a recording, so it is
+/// read without `ParseLimits::default()`'s size and duration bounds.
+pub fn pad_opus_file(path: &str, size_classes: &[usize]) -> Result<usize> {
+    let limits = ParseLimits::trusted();
+    let (stream, _) = read_opus_file(path, &limits)?;
+    let padded = pad_class(&stream, size_classes, &limits)?;
+    std::fs::write(path, &padded)?;
+    Ok(padded.len())
+}
+
+fn pad_class(stream: &[u8], size_classes: &[usize], limits: &ParseLimits) -> Result<Vec<u8>> {
+    let unpadded = strip(stream, limits)?;
+
+    let mut classes = size_classes.to_vec();
+    classes.sort_unstable();
+    let largest = classes.last().copied().unwrap_or(0);
+
+    let mut last_error = None;
+    for &class in classes.iter().filter(|&&class| class >= unpadded.len()) {
+        match pad_exact(&unpadded, class, limits) {
+            Ok(padded) => return Ok(padded),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or(RusticAudioError::SizeBudgetExceeded {
+        max_bytes: largest,
+        smallest: unpadded.len(),
+    }))
+}
+
+fn pad_exact(stream: &[u8], target: usize, limits: &ParseLimits) -> Result<Vec<u8>> {
+    let mut parsed = read_stream(stream, limits)?;
+    let comments = comments_end(&parsed.tags)?;
+    if preserves_trailing_data(&parsed.tags, comments) {
+        return Err(RusticAudioError::InvalidArgument(
+            "OpusTags carries application data that must be kept; it cannot be padded".to_string(),
+        ));
+    }
+    parsed.tags.truncate(comments);
+    let comments_len = parsed.tags.len();
+
+    for extra_pages in 0..=MAX_EXTRA_PAGES {
+        parsed.tags.truncate(comments_len);
+        let unpadded = write_stream(&parsed, extra_pages)?;
+        if unpadded.len() == target {
+            return Ok(unpadded);
+        }
+        if unpadded.len() > target {
+            if extra_pages == 0 {
+                return Err(RusticAudioError::SizeBudgetExceeded {
+                    max_bytes: target,
+                    smallest: unpadded.len(),
+                });
+            }
+            break;
+        }
+
+        // Only the OpusTags pages change size with the padding
+        let wanted = target - unpadded.len() + tags_page_bytes(comments_len);
+        if let Some(len) = tags_len_for_page_bytes(wanted, comments_len + 1) {
+            // A zero first byte marks the data as discardable
+            parsed.tags.resize(len, 0);
+            let padded = write_stream(&parsed, extra_pages)?;
+            if padded.len() == target {
+                return Ok(padded);
+            }
+        }
+    }
+
+    Err(RusticAudioError::InvalidArgument(format!(
+        "this stream cannot be padded to exactly {} bytes", target
+    )))
+}
+
+fn strip(stream: &[u8], limits: &ParseLimits) -> Result<Vec<u8>> {
+    let mut parsed = read_stream(stream, limits)?;
+    let comments = comments_end(&parsed.tags)?;
+    if !preserves_trailing_data(&parsed.tags, comments) {
+        parsed.tags.truncate(comments);
+    }
+    write_stream(&parsed, 0)
+}
+
+fn read_stream(stream: &[u8], limits: &ParseLimits) -> Result<Stream> {
+    check_opus(stream, limits)?;
+    let mut packet_reader = PacketReader::new(Cursor::new(stream));
+
+    let head = packet_reader.read_packet()?
+        .ok_or_else(|| RusticAudioError::OggParse("missing OpusHead packet".to_string()))?;
+    let serial = head.stream_serial();
+
+    let tags = packet_reader.read_packet()?
+        .ok_or_else(|| RusticAudioError::OggParse("missing OpusTags packet".to_string()))?;
+
+    // Packets of one page, waiting for the page's granule position
+    let mut page: Vec<Vec<u8>> = Vec::new();
+    let mut audio = Vec::new();
+    let mut previous_granule = 0;
+    while let Some(packet) = packet_reader.read_packet()? {
+        if packet.stream_serial() != serial {
+            return Err(RusticAudioError::OggParse("multiplexed Ogg streams are not supported".to_string()));
+        }
+
+        let last_in_page = packet.last_in_page();
+        let page_granule = packet.absgp_page();
+        page.push(packet.data);
+
+        if last_in_page {
+            // Packets count forward from the previous page; the page granule belongs
+            // to the last one and may be smaller on the final page, which trims the end
+            let mut granule = previous_granule;
+            let count = page.len();
+            for (index, data) in page.drain(..).enumerate() {
+                granule += packet_samples(&data).unwrap_or(0) as u64;
+                let end = if index + 1 == count { page_granule } else { granule };
+                audio.push((data, end));
+            }
+            previous_granule = page_granule;
+        }
+    }
+
+    Ok(Stream { serial, head: head.data, tags: tags.data, audio })
+}
+
+// Writes the stream with the writer's own page layout, ending a page after
+// each of the first `extra_pages` audio packets
+fn write_stream(stream: &Stream, extra_pages: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut packet_writer = PacketWriter::new(&mut output);
+
+    packet_writer.write_packet(stream.head.clone(), stream.serial, PacketWriteEndInfo::EndPage, 0)?;
+    packet_writer.write_packet(stream.tags.clone(), stream.serial, PacketWriteEndInfo::EndPage, 0)?;
+
+    let last = stream.audio.len().saturating_sub(1);
+    for (index, (data, granule)) in stream.audio.iter().enumerate() {
+        let end_info = if index == last {
+            PacketWriteEndInfo::EndStream
+        } else if index < extra_pages {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        packet_writer.write_packet(data.clone(), stream.serial, end_info, *granule)?;
+    }
+
+    drop(packet_writer);
+    Ok(output)
+}
+
+// Trailing OpusTags data with the low bit of its first byte set must be kept
+fn preserves_trailing_data(tags: &[u8], comments_end: usize) -> bool {
+    tags.get(comments_end).is_some_and(|&first| first & 1 == 1)
+}
+
+// Bytes taken by the header-only pages holding a packet of `len` bytes
+fn tags_page_bytes(len: usize) -> usize {
+    let segments = len / 255 + 1;
+    len + segments + 27 * segments.div_ceil(255)
+}
+
+// Smallest packet length of at least `min_len` whose pages take exactly `page_bytes`
+fn tags_len_for_page_bytes(page_bytes: usize, min_len: usize) -> Option<usize> {
+    if tags_page_bytes(min_len) > page_bytes {
+        return None;
+    }
+
+    let (mut low, mut high) = (min_len, page_bytes);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if tags_page_bytes(mid) < page_bytes {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    (tags_page_bytes(low) == page_bytes).then_some(low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pad_to_size, pad_to_size_class, strip_padding, DEFAULT_SIZE_CLASSES};
+    use crate::{decode_opus_bytes, OpusEncoder, OpusEncodingMode, RusticAudioError};
+
+    fn encode(seconds: f32) -> Vec<u8> {
+        let samples: Vec<f32> = (0..(48_000.0 * seconds) as usize)
+            .map(|i| (i as f32 * 330.0 * 2.0 * std::f32::consts::PI / 48_000.0).sin() * 0.4)
+            .collect();
+        let mut encoder = OpusEncoder::new();
+        encoder.set_mode(OpusEncodingMode::Vbr);
+        encoder.encode_samples(&samples, 48_000).unwrap()
+    }
+
+    #[test]
+    fn padded_messages_share_a_size_and_decode_unchanged() {
+        let short = encode(1.0);
+        let longer = encode(3.0);
+
+        let padded_short = pad_to_size_class(&short, DEFAULT_SIZE_CLASSES).unwrap();
+        let padded_longer = pad_to_size_class(&longer, DEFAULT_SIZE_CLASSES).unwrap();
+        assert_eq!(padded_short.len(), 8 * 1024);
+        assert_eq!(padded_longer.len(), 8 * 1024);
+
+        assert_eq!(decode_opus_bytes(&padded_short).unwrap(), decode_opus_bytes(&short).unwrap());
+        assert_eq!(strip_padding(&padded_short).unwrap(), short);
+        assert_eq!(strip_padding(&padded_longer).unwrap(), longer);
+    }
+
+    #[test]
+    fn every_target_size_is_reachable() {
+        let stream = encode(0.5);
+        // Covers lacing steps of the OpusTags packet and its spill onto a second page
+        let second_page = stream.len() + 65_025 + 255;
+        for target in (stream.len()..stream.len() + 600).chain(second_page - 60..second_page + 60) {
+            let padded = pad_to_size(&stream, target).unwrap();
+            assert_eq!(padded.len(), target);
+            assert_eq!(strip_padding(&padded).unwrap(), stream);
+        }
+    }
+
+    #[test]
+    fn oversized_stream_is_a_typed_error() {
+        let stream = encode(1.0);
+        let result = pad_to_size_class(&stream, &[1024]);
+        assert!(matches!(
+            result,
+            Err(RusticAudioError::SizeBudgetExceeded { max_bytes: 1024, smallest }) if smallest == stream.len()
+        ));
+    }
+}
@@ -1,96 +1,270 @@
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use hound;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::sync::Mutex;
-use std::error::Error;
-
-pub fn playback_audio(file_path: &str, is_playing_flag: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
-    let mut reader = hound::WavReader::open(file_path)?;
-    let spec = reader.spec();
-    
-    println!("Playing audio: channels={}, sample_rate={}, bits={}, format={:?}",
-             spec.channels, spec.sample_rate, spec.bits_per_sample, spec.sample_format);
-    
-    let host = cpal::default_host();
-    let device = host.default_output_device().expect("No output device available");
-    
-    // Use default config instead of matching the file's sample rate
-    let config = device.default_output_config()?;
-    let _sample_format = config.sample_format();
-    let config = config.config();
-    
-    // Read all samples into memory
-    let samples: Vec<f32> = if spec.sample_format == hound::SampleFormat::Float {
-        reader.samples::<f32>().map(|s| s.unwrap()).collect()
-    } else {
-        reader.samples::<i16>().map(|s| s.unwrap() as f32 / 32768.0).collect()
-    };
-    
-    // Create Arc before moving into closure
-    let samples_arc = Arc::new(samples);
-    let samples_for_stream = Arc::clone(&samples_arc);
-    let sample_index = Arc::new(Mutex::new(0usize));
-    let sample_index_for_stream = Arc::clone(&sample_index);
-    let is_playing_for_stream = Arc::clone(&is_playing_flag);
-    
-    let stream = device.build_output_stream(
-        &config,
-        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            let mut index = sample_index_for_stream.lock().unwrap();
-            let samples = &*samples_for_stream;
-            
-            for frame in data.chunks_mut(config.channels as usize) {
-                if !is_playing_for_stream.load(Ordering::Relaxed) || *index >= samples.len() {
-                    // Fill with silence and stop
-                    for sample in frame.iter_mut() {
-                        *sample = 0.0;
-                    }
-                    
-                    if *index >= samples.len() {
-                        is_playing_for_stream.store(false, Ordering::Relaxed);
-                    }
-                    
+use crate::error::Result;
+use crate::resample::{Resampler, ResamplerQuality};
+use crate::validate::{read_wav_file, ParseLimits};
+
+// Frames read from the source at a time to refill a `RateConverter`
+const FILL_FRAMES: usize = 1024;
+
+/// Audio a `Player` reads from: a file decoded as it plays, frame by frame.
+///
+/// A frame is one sample for every channel, interleaved.
+pub(crate) trait PlaybackSource: Send {
+    fn channels(&self) -> u16;
+
+    fn sample_rate(&self) -> u32;
+
+    /// Length in frames.
+    fn len(&self) -> u64;
+
+    /// Fills `out` with the frames from the current position on and returns
+    /// how many it wrote; fewer than fit means the end was reached.
+    fn read(&mut self, out: &mut [f32]) -> Result<usize>;
+
+    /// Moves the position to `frame`, clamped to the length.
+    fn seek(&mut self, frame: u64) -> Result<()>;
+}
+
+/// A WAV file held in memory, as 32-bit float samples.
+pub(crate) struct WavSource {
+    samples: Vec<f32>,
+    channels: u16,
+    sample_rate: u32,
+    // Index of the next sample to play
+    index: usize,
+}
+
+impl WavSource {
+    pub(crate) fn open(file_path: &str) -> Result<Self> {
+        let (samples, spec) = read_wav_file(file_path, &ParseLimits::trusted())?;
+        println!("Playing audio: channels={}, sample_rate={}, bits={}, format={:?}",
+                 spec.channels, spec.sample_rate, spec.bits_per_sample, spec.sample_format);
+
+        Ok(Self {
+            samples,
+            channels: spec.channels,
+            sample_rate: spec.sample_rate,
+            index: 0,
+        })
+    }
+}
+
+impl PlaybackSource for WavSource {
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn len(&self) -> u64 {
+        (self.samples.len() / self.channels as usize) as u64
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> Result<usize> {
+        let channels = self.channels as usize;
+        // Whole frames only, in case the file ends part way through one
+        let available = (self.samples.len() / channels * channels).saturating_sub(self.index);
+        let count = (out.len() / channels * channels).min(available);
+        out[..count].copy_from_slice(&self.samples[self.index..self.index + count]);
+        self.index += count;
+        Ok(count / channels)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        self.index = frame.min(self.len()) as usize * self.channels as usize;
+        Ok(())
+    }
+}
+
+/// Reads a source at another sample rate, resampling every channel with the
+/// `Resampler` kernel as frames are read.
+///
+/// Output matches `Resampler::process` on the whole file, seeks included: a
+/// seek restarts the source far enough back to fill the kernel.
+pub(crate) struct RateConverter {
+    resampler: Resampler,
+    channels: usize,
+    // Source frames from `start` on, interleaved
+    buffer: Vec<f32>,
+    start: u64,
+    // Set once the source has nothing past the buffer
+    ended: bool,
+    // Next output frame
+    next: u64,
+}
+
+impl RateConverter {
+    pub(crate) fn new(channels: u16, from_rate: u32, to_rate: u32, quality: ResamplerQuality) -> Self {
+        Self {
+            resampler: Resampler::new(from_rate, to_rate, quality),
+            channels: channels as usize,
+            buffer: Vec::new(),
+            start: 0,
+            ended: false,
+            next: 0,
+        }
+    }
+
+    /// Length in output frames.
+    pub(crate) fn len(&self, source: &dyn PlaybackSource) -> u64 {
+        self.resampler.output_len(source.len() as usize) as u64
+    }
+
+    pub(crate) fn read(&mut self, source: &mut dyn PlaybackSource, out: &mut [f32]) -> Result<usize> {
+        let (up, down) = self.resampler.ratio();
+        let half_taps = self.resampler.half_taps() as i64;
+        let len = self.len(source);
+        let channels = self.channels;
+        let mut written = 0;
+
+        for frame in out.chunks_exact_mut(channels) {
+            if self.next >= len {
+                break;
+            }
+            let position = self.next * down as u64;
+            let index = (position / up as u64) as i64;
+
+            // Tap k weights source frame first + k; frames outside the source are silence
+            let first = index + 1 - half_taps;
+            self.fill(source, (index + half_taps) as u64)?;
+            self.discard_before(first.max(0) as u64);
+            let coefficients = self.resampler.coefficients((position % up as u64) as usize);
+
+            let buffered = (self.buffer.len() / channels) as i64;
+            let start = self.start as i64;
+            frame.fill(0.0);
+            for (k, &coefficient) in coefficients.iter().enumerate() {
+                let at = first + k as i64 - start;
+                if at < 0 || at >= buffered {
                     continue;
                 }
-                
-                // Copy samples to output
-                for (i, sample) in frame.iter_mut().enumerate() {
-                    let channel_index = i % spec.channels as usize;
-                    let sample_pos = *index + channel_index;
-                    
-                    if sample_pos < samples.len() {
-                        *sample = samples[sample_pos];
-                    } else {
-                        *sample = 0.0;
-                    }
+                let input = &self.buffer[at as usize * channels..(at as usize + 1) * channels];
+                for (sample, &input) in frame.iter_mut().zip(input) {
+                    *sample += input * coefficient;
                 }
-                
-                *index += spec.channels as usize;
             }
-        },
-        |err| eprintln!("Playback error: {:?}", err),
-        None,
-    )?;
-    
-    stream.play()?;
-    
-    // Use the original Arc references here
-    let samples_len = samples_arc.len();
-    
-    while is_playing_flag.load(Ordering::Relaxed) {
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        
-        // Print playback progress
-        let index = *sample_index.lock().unwrap();
-        let progress = if samples_len > 0 {
-            (index as f32 / samples_len as f32) * 100.0
-        } else {
-            0.0
-        };
-        
-        println!("Playback progress: {:.1}%", progress);
-    }
-    
-    Ok(())
+
+            self.next += 1;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    pub(crate) fn seek(&mut self, source: &mut dyn PlaybackSource, frame: u64) -> Result<()> {
+        let (up, down) = self.resampler.ratio();
+        self.next = frame.min(self.len(source));
+        let index = self.next * down as u64 / up as u64;
+        self.start = (index + 1).saturating_sub(self.resampler.half_taps() as u64);
+        source.seek(self.start)?;
+        self.buffer.clear();
+        self.ended = false;
+        Ok(())
+    }
+
+    // Reads source frames until frame `last` is buffered or the source ends
+    fn fill(&mut self, source: &mut dyn PlaybackSource, last: u64) -> Result<()> {
+        while !self.ended && self.start + (self.buffer.len() / self.channels) as u64 <= last {
+            let old_len = self.buffer.len();
+            self.buffer.resize(old_len + FILL_FRAMES * self.channels, 0.0);
+            let read = source.read(&mut self.buffer[old_len..])?;
+            self.buffer.truncate(old_len + read * self.channels);
+            self.ended = read < FILL_FRAMES;
+        }
+        Ok(())
+    }
+
+    // Drops frames the kernel has moved past, a block at a time so the
+    // buffer is not shifted on every output frame
+    fn discard_before(&mut self, frame: u64) {
+        let stale = frame.saturating_sub(self.start) as usize;
+        if stale >= FILL_FRAMES {
+            self.buffer.drain(..stale * self.channels);
+            self.start = frame;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An in-memory source with a WAV file's sample layout
+    fn source(samples: Vec<f32>, channels: u16, sample_rate: u32) -> WavSource {
+        WavSource { samples, channels, sample_rate, index: 0 }
+    }
+
+    fn read_all(converter: &mut RateConverter, source: &mut WavSource, channels: usize) -> Vec<f32> {
+        let mut output = Vec::new();
+        let mut block = vec![0.0; 300 * channels];
+        loop {
+            let read = converter.read(source, &mut block).unwrap();
+            output.extend_from_slice(&block[..read * channels]);
+            if read < 300 {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn wav_files_of_every_depth_decode_to_the_same_levels() {
+        let dir = std::env::temp_dir().join(format!("rustic_playback_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let levels = [0.5f32, -0.25, 0.0];
+
+        for (format, bits) in [
+            (hound::SampleFormat::Int, 8),
+            (hound::SampleFormat::Int, 16),
+            (hound::SampleFormat::Int, 24),
+            (hound::SampleFormat::Int, 32),
+            (hound::SampleFormat::Float, 32),
+        ] {
+            let path = dir.join(format!("{:?}{}.wav", format, bits));
+            let spec = hound::WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: bits, sample_format: format };
+            let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+            for &level in &levels {
+                let full_scale = (1i64 << (bits - 1)) as f32;
+                match (format, bits) {
+                    (hound::SampleFormat::Float, _) => writer.write_sample(level).unwrap(),
+                    (_, 8) => writer.write_sample((level * full_scale) as i8).unwrap(),
+                    (_, 16) => writer.write_sample((level * full_scale) as i16).unwrap(),
+                    _ => writer.write_sample((level * full_scale) as i32).unwrap(),
+                }
+            }
+            writer.finalize().unwrap();
+
+            let mut wav = WavSource::open(path.to_str().unwrap()).unwrap();
+            let mut out = [1.0; 3];
+            assert_eq!(wav.read(&mut out).unwrap(), 3);
+            assert_eq!(out, levels, "{:?} {}-bit", format, bits);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn converted_playback_matches_resampling_the_whole_file() {
+        let left: Vec<f32> = (0..5000).map(|i| (i as f32 * 0.03).sin()).collect();
+        let right: Vec<f32> = (0..5000).map(|i| (i as f32 * 0.11).cos() * 0.5).collect();
+        let interleaved: Vec<f32> = left.iter().zip(&right).flat_map(|(&l, &r)| [l, r]).collect();
+
+        for (from, to) in [(48_000, 44_100), (22_050, 48_000)] {
+            let resampler = Resampler::new(from, to, ResamplerQuality::Medium);
+            let (left, right) = (resampler.process(&left), resampler.process(&right));
+
+            let mut wav = source(interleaved.clone(), 2, from);
+            let mut converter = RateConverter::new(2, from, to, ResamplerQuality::Medium);
+            let output = read_all(&mut converter, &mut wav, 2);
+            assert_eq!(output.len(), left.len() * 2);
+            for (i, frame) in output.chunks(2).enumerate() {
+                assert!((frame[0] - left[i]).abs() < 1e-5 && (frame[1] - right[i]).abs() < 1e-5, "frame {}", i);
+            }
+
+            // A seek lands on the same samples
+            converter.seek(&mut wav, 3000).unwrap();
+            let output = read_all(&mut converter, &mut wav, 2);
+            assert_eq!(output.len(), (left.len() - 3000) * 2);
+            assert!((output[0] - left[3000]).abs() < 1e-5 && (output[1] - right[3000]).abs() < 1e-5);
+        }
+    }
 }
@@ -0,0 +1,726 @@
+use cpal::traits::{DeviceTrait, StreamTrait};
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use crate::device::{open_device, DeviceDirection, DeviceSettings};
+use crate::error::{Result, RusticAudioError};
+use crate::opus_playback::OpusSource;
+use crate::playback::{PlaybackSource, RateConverter, WavSource};
+use crate::resample::ResamplerQuality;
+
+// How often the output thread hands queued events to the handler and checks
+// whether playback is over
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Where a `Player` is in its file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayerState {
+    /// Not started, stopped, or stopped by an error; `play` starts from the current position.
+    Stopped,
+    Playing,
+    /// The output stays open, playing silence, until `resume`.
+    Paused,
+    /// Played to the end; `play` starts over from the beginning.
+    Finished,
+}
+
+/// Something that happened during playback, passed to the handler set with `Player::on_event`.
+#[derive(Debug)]
+pub enum PlayerEvent {
+    /// Playback reached the end of the file.
+    Finished,
+    /// Playback stopped because the device or the decoder failed.
+    Failed(RusticAudioError),
+}
+
+type EventHandler = Box<dyn FnMut(PlayerEvent) + Send>;
+
+// State shared by the `Player`, its output thread and the audio callback
+struct Shared {
+    inner: Mutex<Inner>,
+    // Linear gain, stored as f32 bits so the callback reads it without locking
+    volume: AtomicU32,
+    stop: AtomicBool,
+    handler: Mutex<Option<EventHandler>>,
+}
+
+struct Inner {
+    source: Box<dyn PlaybackSource>,
+    // Converts the source to the device rate when the two differ
+    converter: Option<RateConverter>,
+    // Rate playback runs at: the device's once a stream has opened
+    output_rate: u32,
+    state: PlayerState,
+    // Frames played at `output_rate` since the start of the file
+    position: u64,
+    // Frames read from the source, before channel mapping
+    scratch: Vec<f32>,
+    // Events raised in the audio callback, waiting for the output thread
+    events: Vec<PlayerEvent>,
+}
+
+/// Plays a WAV or Ogg Opus file on the default output device, or the one set with `set_device`.
+///
+/// Playback runs on a background thread; every method returns immediately.
+/// Audio is resampled to the device's rate and mapped to its channels, so a
+/// file plays at its own pitch and speed on any device.
+/// Opus files are decoded as they play, so seeking decodes only the packets
+/// around the new position.
+///
+/// ```no_run
+/// use rustic_audio_tool::{Player, PlayerEvent};
+/// use std::time::Duration;
+///
+/// let mut player = Player::open("recording.opus")?;
+/// player.on_event(|event| {
+///     if let PlayerEvent::Failed(err) = event {
+///         eprintln!("playback failed: {}", err);
+///     }
+/// });
+/// player.set_volume(0.5);
+/// player.play()?;
+/// player.seek(Duration::from_secs(10))?;
+/// println!("{:?} of {:?}", player.position(), player.duration());
+/// # Ok::<(), rustic_audio_tool::RusticAudioError>(())
+/// ```
+pub struct Player {
+    shared: Arc<Shared>,
+    output: Option<thread::JoinHandle<()>>,
+    device: DeviceSettings,
+    channels: u16,
+    sample_rate: u32,
+    duration: Duration,
+}
+
+impl Player {
+    /// Opens a file for playback, telling Ogg Opus from WAV by its first bytes.
+    ///
+    /// # Returns
+    /// * `Ok(Player)` in the `Stopped` state at the start of the file
+    /// * `Err(RusticAudioError)` if the file cannot be read or is not valid WAV or Ogg Opus
+    pub fn open(file_path: &str) -> Result<Self> {
+        let mut magic = Vec::with_capacity(4);
+        File::open(file_path)?.take(4).read_to_end(&mut magic)?;
+        let source: Box<dyn PlaybackSource> = if magic == b"OggS" {
+            Box::new(OpusSource::open(file_path)?)
+        } else {
+            Box::new(WavSource::open(file_path)?)
+        };
+        Ok(Self::from_source(source))
+    }
+
+    fn from_source(source: Box<dyn PlaybackSource>) -> Self {
+        let (channels, sample_rate) = (source.channels(), source.sample_rate());
+        let duration = Duration::from_secs_f64(source.len() as f64 / sample_rate as f64);
+        Self {
+            shared: Arc::new(Shared {
+                inner: Mutex::new(Inner {
+                    source,
+                    converter: None,
+                    output_rate: sample_rate,
+                    state: PlayerState::Stopped,
+                    position: 0,
+                    scratch: Vec::new(),
+                    events: Vec::new(),
+                }),
+                volume: AtomicU32::new(1f32.to_bits()),
+                stop: AtomicBool::new(false),
+                handler: Mutex::new(None),
+            }),
+            output: None,
+            device: DeviceSettings::default(),
+            channels,
+            sample_rate,
+            duration,
+        }
+    }
+
+    /// Starts playback, or resumes it when paused. After `Finished`, playback
+    /// starts over from the beginning.
+    ///
+    /// # Returns
+    /// * `Ok(())` once the output stream is running
+    /// * `Err(RusticAudioError)` if no output device is available or it refuses the stream
+    pub fn play(&mut self) -> Result<()> {
+        {
+            let mut inner = self.shared.lock();
+            if inner.state == PlayerState::Finished {
+                inner.seek(0)?;
+            }
+            inner.state = PlayerState::Playing;
+        }
+
+        // The output thread exits on its own once playback finishes or fails
+        if self.output.as_ref().is_some_and(|output| !output.is_finished()) {
+            return Ok(());
+        }
+        self.join_output()?;
+        self.start_output().inspect_err(|_| self.shared.lock().state = PlayerState::Stopped)
+    }
+
+    /// Pauses playback, keeping the position. Does nothing unless playing.
+    pub fn pause(&self) {
+        let mut inner = self.shared.lock();
+        if inner.state == PlayerState::Playing {
+            inner.state = PlayerState::Paused;
+        }
+    }
+
+    /// Resumes paused playback. Does nothing unless paused.
+    pub fn resume(&self) {
+        let mut inner = self.shared.lock();
+        if inner.state == PlayerState::Paused {
+            inner.state = PlayerState::Playing;
+        }
+    }
+
+    /// Stops playback, closes the output stream and rewinds to the beginning.
+    ///
+    /// # Returns
+    /// * `Err(RusticAudioError::ThreadPanicked)` if the output thread panicked
+    pub fn stop(&mut self) -> Result<()> {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        let joined = self.join_output();
+
+        let mut inner = self.shared.lock();
+        inner.state = PlayerState::Stopped;
+        inner.seek(0)?;
+        joined
+    }
+
+    /// Moves playback to `position`, clamped to the duration. Playing or
+    /// paused playback carries on from there; after `Finished` the player is
+    /// `Stopped` at the new position.
+    pub fn seek(&self, position: Duration) -> Result<()> {
+        let mut inner = self.shared.lock();
+        let frame = (position.as_secs_f64() * inner.output_rate as f64).round() as u64;
+        inner.seek(frame)?;
+        if inner.state == PlayerState::Finished {
+            inner.state = PlayerState::Stopped;
+        }
+        Ok(())
+    }
+
+    /// How far into the file playback is.
+    pub fn position(&self) -> Duration {
+        let inner = self.shared.lock();
+        Duration::from_secs_f64(inner.position as f64 / inner.output_rate as f64)
+    }
+
+    /// Length of the file.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Sets the playback gain: 1.0 plays the file at its own level, 0.0 mutes it.
+    /// Negative and non-finite values mute.
+    pub fn set_volume(&self, volume: f32) {
+        let volume = if volume.is_finite() { volume.max(0.0) } else { 0.0 };
+        self.shared.volume.store(volume.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.shared.volume.load(Ordering::Relaxed))
+    }
+
+    pub fn state(&self) -> PlayerState {
+        self.shared.lock().state
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Chooses the output device, sample rate and buffer size. Takes effect
+    /// the next time the output opens: on `play` after `stop`, after the end
+    /// or an error, or before the first `play`.
+    pub fn set_device(&mut self, settings: DeviceSettings) {
+        self.device = settings;
+    }
+
+    pub fn device(&self) -> &DeviceSettings {
+        &self.device
+    }
+
+    /// Sample rate of the file, which may differ from the device's.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Sets the handler for playback events, replacing any earlier one.
+    ///
+    /// The handler runs on the output thread, never on the audio callback, so
+    /// it may block briefly; it must not call back into this `Player`.
+    pub fn on_event<F>(&self, handler: F)
+    where
+        F: FnMut(PlayerEvent) + Send + 'static,
+    {
+        *self.shared.handler.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(handler));
+    }
+
+    fn start_output(&mut self) -> Result<()> {
+        self.shared.stop.store(false, Ordering::Relaxed);
+        let shared = Arc::clone(&self.shared);
+        let device = self.device.clone();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let output = thread::spawn(move || run_output(shared, device, ready_tx));
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => {
+                self.output = Some(output);
+                Ok(())
+            }
+            Ok(Err(err)) => {
+                let _ = output.join();
+                Err(err)
+            }
+            Err(_) => {
+                let _ = output.join();
+                Err(RusticAudioError::ThreadPanicked)
+            }
+        }
+    }
+
+    fn join_output(&mut self) -> Result<()> {
+        match self.output.take() {
+            Some(output) => output.join().map_err(|_| RusticAudioError::ThreadPanicked),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        let _ = self.join_output();
+    }
+}
+
+impl Inner {
+    fn len(&self) -> u64 {
+        match &self.converter {
+            Some(converter) => converter.len(self.source.as_ref()),
+            None => self.source.len(),
+        }
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> Result<usize> {
+        match &mut self.converter {
+            Some(converter) => converter.read(self.source.as_mut(), out),
+            None => self.source.read(out),
+        }
+    }
+
+    // Moves to `frame` at the output rate, clamped to the length
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        let frame = frame.min(self.len());
+        match &mut self.converter {
+            Some(converter) => converter.seek(self.source.as_mut(), frame)?,
+            None => self.source.seek(frame)?,
+        }
+        self.position = frame;
+        Ok(())
+    }
+
+    // Switches playback to a device running at `rate`, keeping the position in time
+    fn set_output_rate(&mut self, rate: u32) -> Result<()> {
+        if rate == self.output_rate {
+            return Ok(());
+        }
+        let seconds = self.position as f64 / self.output_rate as f64;
+        let source_rate = self.source.sample_rate();
+        self.converter = (rate != source_rate).then(|| {
+            RateConverter::new(self.source.channels(), source_rate, rate, ResamplerQuality::default())
+        });
+        self.output_rate = rate;
+        self.seek((seconds * rate as f64).round() as u64)
+    }
+
+    // Fills `frame` from one source frame. Equal layouts copy; a mono source
+    // plays on every output channel and a mono device gets the average of the
+    // source channels. Otherwise channels map in order, leaving any extra
+    // output channels silent.
+    fn map_channels(input: &[f32], frame: &mut [f32], volume: f32) {
+        if input.len() == 1 {
+            frame.fill(input[0] * volume);
+        } else if frame.len() == 1 {
+            frame[0] = input.iter().sum::<f32>() / input.len() as f32 * volume;
+        } else {
+            for (sample, &input) in frame.iter_mut().zip(input) {
+                *sample = input * volume;
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Fills one device buffer of `channels` interleaved channels
+    fn render(&self, out: &mut [f32], channels: usize) {
+        out.fill(0.0);
+        let mut inner = self.lock();
+        if inner.state != PlayerState::Playing {
+            return;
+        }
+
+        let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
+        let frames = out.len() / channels;
+        let source_channels = inner.source.channels() as usize;
+        let mut scratch = std::mem::take(&mut inner.scratch);
+        scratch.resize(frames * source_channels, 0.0);
+
+        let result = inner.read(&mut scratch);
+        if let Ok(read) = result {
+            for (frame, input) in out.chunks_mut(channels).zip(scratch.chunks(source_channels)).take(read) {
+                Inner::map_channels(input, frame, volume);
+            }
+        }
+        inner.scratch = scratch;
+
+        match result {
+            Ok(read) => {
+                inner.position += read as u64;
+                if read < frames {
+                    inner.state = PlayerState::Finished;
+                    inner.events.push(PlayerEvent::Finished);
+                }
+            }
+            Err(err) => {
+                inner.state = PlayerState::Stopped;
+                inner.events.push(PlayerEvent::Failed(err));
+            }
+        }
+    }
+
+    fn fail(&self, err: RusticAudioError) {
+        let mut inner = self.lock();
+        inner.state = PlayerState::Stopped;
+        inner.events.push(PlayerEvent::Failed(err));
+    }
+
+    fn dispatch_events(&self) {
+        let events = std::mem::take(&mut self.lock().events);
+        if events.is_empty() {
+            return;
+        }
+        let mut handler = self.handler.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(handler) = handler.as_mut() {
+            events.into_iter().for_each(handler);
+        }
+    }
+
+    // The output thread keeps running while paused; it ends once stopped,
+    // finished or failed
+    fn is_over(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+            || matches!(self.lock().state, PlayerState::Stopped | PlayerState::Finished)
+    }
+}
+
+// Owns the output stream for as long as playback lasts, reporting whether it
+// opened through `ready`
+fn run_output(shared: Arc<Shared>, device: DeviceSettings, ready: mpsc::Sender<Result<()>>) {
+    let stream = match open_stream(&shared, &device) {
+        Ok(stream) => stream,
+        Err(err) => {
+            let _ = ready.send(Err(err));
+            return;
+        }
+    };
+    let _ = ready.send(Ok(()));
+
+    while !shared.is_over() {
+        thread::sleep(POLL_INTERVAL);
+        shared.dispatch_events();
+    }
+    drop(stream);
+    shared.dispatch_events();
+}
+
+fn open_stream(shared: &Arc<Shared>, settings: &DeviceSettings) -> Result<cpal::Stream> {
+    let (device, config, sample_format) = open_device(settings, DeviceDirection::Output)?;
+    shared.lock().set_output_rate(config.sample_rate)?;
+
+    use cpal::SampleFormat::*;
+    let stream = match sample_format {
+        F32 => build_stream::<f32>(&device, &config, shared)?,
+        F64 => build_stream::<f64>(&device, &config, shared)?,
+        I8 => build_stream::<i8>(&device, &config, shared)?,
+        I16 => build_stream::<i16>(&device, &config, shared)?,
+        I24 => build_stream::<cpal::I24>(&device, &config, shared)?,
+        I32 => build_stream::<i32>(&device, &config, shared)?,
+        I64 => build_stream::<i64>(&device, &config, shared)?,
+        U8 => build_stream::<u8>(&device, &config, shared)?,
+        U16 => build_stream::<u16>(&device, &config, shared)?,
+        U24 => build_stream::<cpal::U24>(&device, &config, shared)?,
+        U32 => build_stream::<u32>(&device, &config, shared)?,
+        U64 => build_stream::<u64>(&device, &config, shared)?,
+        // DSD bitstreams carry no PCM samples to convert to
+        other => {
+            return Err(RusticAudioError::UnsupportedSampleFormat(format!("{:?} output", other)));
+        }
+    };
+    stream.play()?;
+    Ok(stream)
+}
+
+// Builds an output stream in the device's sample format. Audio is rendered in
+// f32 and converted per callback, reusing one buffer.
+fn build_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, shared: &Arc<Shared>) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let render = Arc::clone(shared);
+    let on_error = Arc::clone(shared);
+    let mut buffer = Vec::new();
+
+    Ok(device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            buffer.resize(data.len(), 0.0);
+            render.render(&mut buffer, channels);
+            convert_samples(&buffer, data);
+        },
+        move |err| on_error.fail(RusticAudioError::DeviceUnavailable(err.to_string())),
+        None,
+    )?)
+}
+
+// Converts f32 samples to the device format, clipping at full scale so a
+// volume above 1.0 cannot wrap around in integer formats
+fn convert_samples<T: cpal::FromSample<f32>>(input: &[f32], output: &mut [T]) {
+    for (out, &sample) in output.iter_mut().zip(input) {
+        *out = T::from_sample_(sample.clamp(-1.0, 1.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opus_encoder::OpusEncoder;
+    use std::sync::mpsc::Receiver;
+
+    // Counts up from 1, one step per frame; channel c holds c + 1 times the count
+    struct Ramp {
+        channels: u16,
+        frames: u64,
+        index: u64,
+    }
+
+    impl PlaybackSource for Ramp {
+        fn channels(&self) -> u16 {
+            self.channels
+        }
+
+        fn sample_rate(&self) -> u32 {
+            1000
+        }
+
+        fn len(&self) -> u64 {
+            self.frames
+        }
+
+        fn read(&mut self, out: &mut [f32]) -> Result<usize> {
+            let channels = self.channels as usize;
+            let count = ((out.len() / channels) as u64).min(self.frames - self.index) as usize;
+            for frame in out.chunks_mut(channels).take(count) {
+                self.index += 1;
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    *sample = (self.index * (channel as u64 + 1)) as f32;
+                }
+            }
+            Ok(count)
+        }
+
+        fn seek(&mut self, frame: u64) -> Result<()> {
+            self.index = frame.min(self.frames);
+            Ok(())
+        }
+    }
+
+    fn ramp_player(frames: u64) -> (Player, Receiver<PlayerEvent>) {
+        ramp_player_with_channels(frames, 1)
+    }
+
+    fn ramp_player_with_channels(frames: u64, channels: u16) -> (Player, Receiver<PlayerEvent>) {
+        let player = Player::from_source(Box::new(Ramp { channels, frames, index: 0 }));
+        let (tx, rx) = mpsc::channel();
+        player.on_event(move |event| tx.send(event).unwrap());
+        // Stand in for `play`, which needs an output device
+        player.shared.lock().state = PlayerState::Playing;
+        (player, rx)
+    }
+
+    #[test]
+    fn renders_to_every_output_channel() {
+        let (player, _events) = ramp_player(100);
+        let mut out = [0.0; 8];
+        player.shared.render(&mut out, 2);
+
+        assert_eq!(out, [1.0, 1.0, 2.0, 2.0, 3.0, 3.0, 4.0, 4.0]);
+        assert_eq!(player.position(), Duration::from_millis(4));
+        assert_eq!(player.duration(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn channels_are_mapped_to_the_device_layout() {
+        let (player, _events) = ramp_player_with_channels(100, 2);
+        let mut mono = [0.0; 2];
+        player.shared.render(&mut mono, 1);
+        assert_eq!(mono, [1.5, 3.0]);
+
+        let mut quad = [9.0; 8];
+        player.shared.render(&mut quad, 4);
+        assert_eq!(quad, [3.0, 6.0, 0.0, 0.0, 4.0, 8.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn a_device_at_another_rate_keeps_time() {
+        let (player, _events) = ramp_player(1000);
+        player.seek(Duration::from_millis(250)).unwrap();
+        player.shared.lock().set_output_rate(2000).unwrap();
+        assert_eq!(player.position(), Duration::from_millis(250));
+        assert_eq!(player.duration(), Duration::from_secs(1));
+
+        // Twice the frames per second, following the ramp from where it was
+        let mut out = vec![0.0; 200];
+        player.shared.render(&mut out, 1);
+        assert_eq!(player.position(), Duration::from_millis(350));
+        assert!((out[100] - 301.0).abs() < 1.0, "{}", out[100]);
+        assert!((out[101] - 301.5).abs() < 1.0, "{}", out[101]);
+
+        let mut rest = vec![0.0; 2000];
+        player.shared.render(&mut rest, 1);
+        assert_eq!(player.position(), Duration::from_secs(1));
+        assert_eq!(player.state(), PlayerState::Finished);
+    }
+
+    #[test]
+    fn samples_convert_to_every_integer_format() {
+        let input = [0.0, 0.5, -1.0, 2.0];
+        let mut i16s = [0i16; 4];
+        convert_samples(&input, &mut i16s);
+        assert_eq!(i16s, [0, 16384, i16::MIN, i16::MAX]);
+
+        let mut u16s = [0u16; 4];
+        convert_samples(&input, &mut u16s);
+        assert_eq!(u16s, [32768, 49152, 0, u16::MAX]);
+
+        let mut i32s = [0i32; 4];
+        convert_samples(&input, &mut i32s);
+        assert_eq!(i32s[..3], [0, 1 << 30, i32::MIN]);
+        assert!(i32s[3] > i32::MAX - 256);
+
+        let mut u8s = [0u8; 4];
+        convert_samples(&input, &mut u8s);
+        assert_eq!(u8s, [128, 192, 0, u8::MAX]);
+    }
+
+    #[test]
+    fn pause_plays_silence_and_keeps_the_position() {
+        let (player, _events) = ramp_player(100);
+        let mut out = [0.0; 4];
+        player.shared.render(&mut out, 1);
+        player.pause();
+        player.shared.render(&mut out, 1);
+        assert_eq!(out, [0.0; 4]);
+        assert_eq!(player.state(), PlayerState::Paused);
+
+        player.resume();
+        player.shared.render(&mut out, 1);
+        assert_eq!(out, [5.0, 6.0, 7.0, 8.0]);
+    }
+
+    #[test]
+    fn seek_and_volume_apply_to_the_next_buffer() {
+        let (player, _events) = ramp_player(100);
+        player.seek(Duration::from_millis(50)).unwrap();
+        player.set_volume(0.5);
+        let mut out = [0.0; 2];
+        player.shared.render(&mut out, 1);
+
+        assert_eq!(out, [25.5, 26.0]);
+        assert_eq!(player.position(), Duration::from_millis(52));
+
+        player.set_volume(-1.0);
+        assert_eq!(player.volume(), 0.0);
+        player.seek(Duration::from_secs(5)).unwrap();
+        assert_eq!(player.position(), player.duration());
+    }
+
+    #[test]
+    fn reaching_the_end_raises_finished() {
+        let (player, events) = ramp_player(3);
+        let mut out = [9.0; 4];
+        player.shared.render(&mut out, 1);
+        player.shared.dispatch_events();
+
+        assert_eq!(out, [1.0, 2.0, 3.0, 0.0]);
+        assert_eq!(player.state(), PlayerState::Finished);
+        assert!(matches!(events.try_recv(), Ok(PlayerEvent::Finished)));
+        assert!(player.shared.is_over());
+
+        // Seeking back leaves the player ready to play from there
+        player.seek(Duration::ZERO).unwrap();
+        assert_eq!(player.state(), PlayerState::Stopped);
+    }
+
+    #[test]
+    fn device_errors_raise_failed() {
+        let (player, events) = ramp_player(100);
+        player.shared.fail(RusticAudioError::DeviceUnavailable("unplugged".to_string()));
+        player.shared.dispatch_events();
+
+        assert_eq!(player.state(), PlayerState::Stopped);
+        assert!(matches!(events.try_recv(), Ok(PlayerEvent::Failed(RusticAudioError::DeviceUnavailable(_)))));
+    }
+
+    #[test]
+    fn opus_files_seek_to_the_same_audio_as_playing_through() {
+        let dir = std::env::temp_dir().join(format!("rustic_player_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let wav_path = dir.join("tone.wav");
+        let opus_path = dir.join("tone.opus");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48_000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&wav_path, spec).unwrap();
+        for i in 0..48_000 {
+            let t = i as f32 / 48_000.0;
+            let sample = (t * 440.0 * std::f32::consts::TAU).sin() * (1.0 + t * 3.0) * 4000.0;
+            writer.write_sample(sample as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        OpusEncoder::new().encode_wav_to_opus(wav_path.to_str().unwrap(), opus_path.to_str().unwrap()).unwrap();
+
+        let through = Player::open(opus_path.to_str().unwrap()).unwrap();
+        assert_eq!(through.duration(), Duration::from_secs(1));
+        through.shared.lock().state = PlayerState::Playing;
+        let mut all = vec![0.0; 48_000];
+        through.shared.render(&mut all, 1);
+
+        let seeking = Player::open(opus_path.to_str().unwrap()).unwrap();
+        seeking.seek(Duration::from_millis(700)).unwrap();
+        seeking.shared.lock().state = PlayerState::Playing;
+        let mut tail = vec![0.0; 4800];
+        seeking.shared.render(&mut tail, 1);
+
+        let expected = &all[33_600..38_400];
+        let error: f32 = tail.iter().zip(expected).map(|(a, b)| (a - b).powi(2)).sum();
+        let energy: f32 = expected.iter().map(|s| s * s).sum();
+        assert!(error < energy * 0.01, "error {} vs energy {}", error, energy);
+        assert_eq!(seeking.position(), Duration::from_millis(800));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
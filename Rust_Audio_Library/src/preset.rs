@@ -0,0 +1,354 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use crate::dsp::AudioProcessor;
+use crate::error::{Result, RusticAudioError};
+use crate::opus_encoder::{OpusApplication, OpusEncoder, OpusEncodingMode, OpusSignal};
+use crate::opus_tags::OpusTags;
+
+/// Schema version written by this release. Newer presets are rejected on load.
+pub const PRESET_VERSION: u32 = 1;
+
+const BUILTIN_PRESETS: &[&str] = &["voice-message", "high-quality", "low-bandwidth", "unprocessed"];
+
+/// On-disk format of a preset file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PresetFormat {
+    Toml,
+    Json,
+}
+
+impl PresetFormat {
+    /// Picks JSON for `.json` files and TOML for everything else.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => PresetFormat::Json,
+            _ => PresetFormat::Toml,
+        }
+    }
+}
+
+/// Every `AudioProcessor` field plus the `OpusEncoder` settings, saved under a name.
+///
+/// Fields missing from a file take their library defaults, unknown fields are
+/// an error, and every value is range-checked before the preset is returned.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    pub version: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub processor: AudioProcessor,
+    #[serde(default)]
+    pub opus: OpusEncoder,
+}
+
+// Read first so a newer schema is reported as such rather than as unknown fields
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: Option<u32>,
+}
+
+impl Preset {
+    /// Captures the current settings of `processor` and `opus`.
+    pub fn from_settings(name: &str, processor: &AudioProcessor, opus: &OpusEncoder) -> Self {
+        // OpusTags are per-message metadata, not a setting
+        let mut opus = opus.clone();
+        opus.set_tags(OpusTags::new());
+
+        Self {
+            version: PRESET_VERSION,
+            name: name.to_string(),
+            description: String::new(),
+            processor: processor.clone(),
+            opus,
+        }
+    }
+
+    /// Copies the preset into `processor` and `opus`.
+    ///
+    /// The encoder's OpusTags are per-message metadata and are kept.
+    pub fn apply(&self, processor: &mut AudioProcessor, opus: &mut OpusEncoder) {
+        *processor = self.processor.clone();
+        let tags = opus.get_tags().clone();
+        *opus = self.opus.clone();
+        opus.set_tags(tags);
+    }
+
+    /// Names accepted by `builtin`.
+    pub fn builtin_names() -> &'static [&'static str] {
+        BUILTIN_PRESETS
+    }
+
+    /// Returns one of the presets shipped with the library.
+    ///
+    /// * `voice-message` - the default voice chain at 10 kbps constrained VBR in
+    ///   VoIP mode with a voice hint; a 30 s recording stays under 45 kB
+    /// * `high-quality` - the same chain at 24 kbps VBR
+    /// * `low-bandwidth` - narrowband voice at 6 kbps CBR, 8 kHz
+    /// * `unprocessed` - every DSP stage off, 12 kbps VBR in audio mode
+    pub fn builtin(name: &str) -> Option<Self> {
+        let mut processor = AudioProcessor::new(48000.0);
+        processor.threshold_db = 5.0;
+        processor.limiter_threshold_db = -1.0;
+        processor.rms_enabled = true;
+        processor.filters_enabled = true;
+        processor.spectral_gate_enabled = true;
+        processor.amplitude_gate_enabled = true;
+
+        let mut opus = OpusEncoder::new();
+        opus.set_mode(OpusEncodingMode::Vbr);
+        opus.set_complexity(10);
+        opus.set_application(OpusApplication::Voip);
+        opus.set_signal(OpusSignal::Voice);
+
+        let description = match name {
+            "voice-message" => {
+                // Constrained, so a busy recording cannot overshoot the budget
+                opus.set_mode(OpusEncodingMode::ConstrainedVbr);
+                opus.set_bitrate(10000);
+                "30 s voice message under 45 kB: clean voice chain, 10 kbps constrained VBR"
+            }
+            "high-quality" => {
+                opus.set_bitrate(24000);
+                "Clean voice chain at 24 kbps VBR"
+            }
+            "low-bandwidth" => {
+                processor.lowpass_freq = 4000.0;
+                opus.set_mode(OpusEncodingMode::Cbr);
+                opus.set_bitrate(6000);
+                opus.set_sample_rate(8000);
+                "Narrowband voice at 6 kbps CBR for the smallest files"
+            }
+            "unprocessed" => {
+                processor = AudioProcessor::new(48000.0);
+                processor.limiter_enabled = false;
+                opus.set_application(OpusApplication::Audio);
+                opus.set_signal(OpusSignal::Auto);
+                "No DSP, 12 kbps VBR"
+            }
+            _ => return None,
+        };
+
+        let mut preset = Self::from_settings(name, &processor, &opus);
+        preset.description = description.to_string();
+        Some(preset)
+    }
+
+    /// Resolves a built-in preset name, or loads the file at `name_or_path`.
+    pub fn resolve(name_or_path: &str) -> Result<Self> {
+        match Self::builtin(name_or_path) {
+            Some(preset) => Ok(preset),
+            None => Self::load(name_or_path),
+        }
+    }
+
+    /// Loads and validates a preset, choosing the format from the file extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text, PresetFormat::from_path(path))
+    }
+
+    /// Validates and writes the preset, choosing the format from the file extension.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let text = self.serialize(PresetFormat::from_path(path))?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Parses and validates a preset held in memory.
+    pub fn parse(text: &str, format: PresetFormat) -> Result<Self> {
+        let probe: VersionProbe = deserialize(text, format)?;
+        match probe.version {
+            None => return Err(RusticAudioError::InvalidPreset("missing `version`".to_string())),
+            Some(version) if version == 0 || version > PRESET_VERSION => {
+                return Err(RusticAudioError::InvalidPreset(format!(
+                    "version {} is not supported (this library reads versions 1 to {})",
+                    version, PRESET_VERSION
+                )));
+            }
+            Some(_) => {}
+        }
+
+        let preset: Preset = deserialize(text, format)?;
+        preset.validate()?;
+        Ok(preset)
+    }
+
+    /// Validates the preset and renders it as TOML or JSON.
+    pub fn serialize(&self, format: PresetFormat) -> Result<String> {
+        self.validate()?;
+        match format {
+            PresetFormat::Toml => toml::to_string_pretty(self)
+                .map_err(|e| RusticAudioError::InvalidPreset(e.to_string())),
+            PresetFormat::Json => serde_json::to_string_pretty(self)
+                .map_err(|e| RusticAudioError::InvalidPreset(e.to_string())),
+        }
+    }
+
+    /// Checks every setting against the range the library can handle.
+    pub fn validate(&self) -> Result<()> {
+        let p = &self.processor;
+
+        let ranges = [
+            ("processor.sample_rate", p.sample_rate, 8000.0, 384000.0),
+            ("processor.threshold_db", p.threshold_db, -120.0, 60.0),
+            ("processor.amplitude_threshold_db", p.amplitude_threshold_db, -120.0, 0.0),
+            ("processor.amplitude_attack_ms", p.amplitude_attack_ms, 0.01, 1000.0),
+            ("processor.amplitude_release_ms", p.amplitude_release_ms, 0.01, 5000.0),
+            ("processor.amplitude_lookahead_ms", p.amplitude_lookahead_ms, 0.0, 100.0),
+            ("processor.gain_db", p.gain_db, -60.0, 40.0),
+            ("processor.limiter_threshold_db", p.limiter_threshold_db, -60.0, 0.0),
+            ("processor.limiter_ceiling_db", p.limiter_ceiling_db, -60.0, 0.0),
+            ("processor.limiter_attack_ms", p.limiter_attack_ms, 0.0, 1000.0),
+            ("processor.limiter_release_ms", p.limiter_release_ms, 0.01, 5000.0),
+            ("processor.limiter_lookahead_ms", p.limiter_lookahead_ms, 0.0, 100.0),
+            ("processor.lowpass_freq", p.lowpass_freq, 0.0, 96000.0),
+            ("processor.highpass_freq", p.highpass_freq, 0.0, 96000.0),
+            ("processor.rms_target_db", p.rms_target_db, -60.0, 0.0),
+        ];
+        for (field, value, min, max) in ranges {
+            if !(min..=max).contains(&value) {
+                return Err(RusticAudioError::InvalidPreset(format!(
+                    "{} = {} is outside {}..={}", field, value, min, max
+                )));
+            }
+        }
+
+        if p.highpass_freq >= p.lowpass_freq {
+            return Err(RusticAudioError::InvalidPreset(format!(
+                "processor.highpass_freq ({} Hz) must be below processor.lowpass_freq ({} Hz)",
+                p.highpass_freq, p.lowpass_freq
+            )));
+        }
+
+        self.opus.validate_settings().map_err(|e| match e {
+            RusticAudioError::InvalidArgument(msg) => RusticAudioError::InvalidPreset(msg),
+            other => other,
+        })
+    }
+}
+
+fn deserialize<T: for<'de> Deserialize<'de>>(text: &str, format: PresetFormat) -> Result<T> {
+    match format {
+        PresetFormat::Toml => toml::from_str(text)
+            .map_err(|e| RusticAudioError::InvalidPreset(e.to_string())),
+        PresetFormat::Json => serde_json::from_str(text)
+            .map_err(|e| RusticAudioError::InvalidPreset(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Preset, PresetFormat, PRESET_VERSION};
+    use crate::{
+        AudioProcessor, Downmix, OpusApplication, OpusEncoder, OpusEncodingMode, OpusSignal, OpusTags, RusticAudioError,
+    };
+
+    #[test]
+    fn builtins_round_trip_through_toml_and_json() {
+        for name in Preset::builtin_names() {
+            let preset = Preset::builtin(name).unwrap();
+            for format in [PresetFormat::Toml, PresetFormat::Json] {
+                let text = preset.serialize(format).unwrap();
+                assert_eq!(Preset::parse(&text, format).unwrap(), preset);
+            }
+        }
+    }
+
+    #[test]
+    fn voice_message_keeps_thirty_seconds_under_45_kb() {
+        // Noise over a tone, which VBR spends the most bits on
+        let mut seed = 0x2545_f491u32;
+        let samples: Vec<f32> = (0..48_000 * 30)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = (seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5;
+                let tone = (i as f32 * 180.0 * 2.0 * std::f32::consts::PI / 48_000.0).sin();
+                tone * 0.3 + noise * 0.4
+            })
+            .collect();
+
+        let preset = Preset::builtin("voice-message").unwrap();
+        let encoded = preset.opus.encode_samples(&samples, 48_000).unwrap();
+        assert!(encoded.len() <= 45_000, "{} bytes", encoded.len());
+    }
+
+    #[test]
+    fn channel_downmix_round_trips() {
+        let mut preset = Preset::builtin("voice-message").unwrap();
+        preset.processor.downmix = Downmix::Channel(1);
+        preset.opus.set_downmix(Downmix::PhaseAware);
+        for format in [PresetFormat::Toml, PresetFormat::Json] {
+            let text = preset.serialize(format).unwrap();
+            assert_eq!(Preset::parse(&text, format).unwrap(), preset);
+        }
+    }
+
+    #[test]
+    fn presets_leave_opus_tags_alone() {
+        let mut tags = OpusTags::new();
+        tags.set("LANGUAGE", "fr").unwrap();
+        let mut encoder = OpusEncoder::new();
+        encoder.set_tags(tags.clone());
+
+        let preset = Preset::from_settings("tagged", &AudioProcessor::default(), &encoder);
+        assert_eq!(preset.opus.get_tags(), &OpusTags::new());
+
+        let mut processor = AudioProcessor::default();
+        Preset::builtin("high-quality").unwrap().apply(&mut processor, &mut encoder);
+        assert_eq!(encoder.get_tags(), &tags);
+    }
+
+    #[test]
+    fn missing_fields_take_library_defaults() {
+        let preset = Preset::parse("version = 1\n[opus]\nbitrate = 16000\n", PresetFormat::Toml).unwrap();
+        assert_eq!(preset.processor, AudioProcessor::default());
+        assert_eq!(preset.opus.get_bitrate(), 16000);
+        assert_eq!(preset.opus.get_sample_rate(), OpusEncoder::new().get_sample_rate());
+    }
+
+    #[test]
+    fn opus_tuning_round_trips_and_old_names_still_load() {
+        let voice = Preset::builtin("voice-message").unwrap();
+        assert_eq!(voice.opus.get_application(), OpusApplication::Voip);
+        assert_eq!(voice.opus.get_signal(), OpusSignal::Voice);
+
+        let text = "version = 1\n[opus]\nmode = \"constrained_vbr\"\napplication = \"restricted_low_delay\"\nsignal = \"music\"\ncomplexity = 3\n";
+        let preset = Preset::parse(text, PresetFormat::Toml).unwrap();
+        assert_eq!(preset.opus.get_mode(), OpusEncodingMode::ConstrainedVbr);
+        assert_eq!(preset.opus.get_application(), OpusApplication::RestrictedLowDelay);
+        assert_eq!(preset.opus.get_signal(), OpusSignal::Music);
+        assert_eq!(preset.opus.get_complexity(), 3);
+
+        let old = Preset::parse("version = 1\n[opus]\nvbr_quality = 7\n", PresetFormat::Toml).unwrap();
+        assert_eq!(old.opus.get_complexity(), 7);
+        let too_complex = "version = 1\n[opus]\ncomplexity = 11\n";
+        assert!(matches!(Preset::parse(too_complex, PresetFormat::Toml), Err(RusticAudioError::InvalidPreset(_))));
+    }
+
+    #[test]
+    fn rejects_newer_or_missing_versions() {
+        let newer = format!("{{\"version\": {}, \"future_field\": true}}", PRESET_VERSION + 1);
+        let err = Preset::parse(&newer, PresetFormat::Json).unwrap_err();
+        assert!(matches!(err, RusticAudioError::InvalidPreset(msg) if msg.contains("not supported")));
+
+        assert!(Preset::parse("name = \"x\"", PresetFormat::Toml).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_and_unknown_fields() {
+        let loud = "version = 1\n[processor]\nlimiter_ceiling_db = 3.0\n";
+        assert!(matches!(Preset::parse(loud, PresetFormat::Toml), Err(RusticAudioError::InvalidPreset(_))));
+
+        let rate = "version = 1\n[opus]\nsample_rate = 44100\n";
+        assert!(matches!(Preset::parse(rate, PresetFormat::Toml), Err(RusticAudioError::InvalidPreset(_))));
+
+        let typo = "version = 1\n[processor]\nlimter_enabled = true\n";
+        assert!(matches!(Preset::parse(typo, PresetFormat::Toml), Err(RusticAudioError::InvalidPreset(_))));
+    }
+}
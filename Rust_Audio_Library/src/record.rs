@@ -1,181 +1,196 @@
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use hound;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::sync::Mutex;
-use std::error::Error;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crate::device::{open_device, DeviceDirection, DeviceSettings};
 use crate::dsp::AudioProcessor;
+use crate::error::{Result, RusticAudioError};
+use crate::resample::{resample, ResamplerQuality};
+use crate::ring_buffer::{sample_ring, SampleConsumer, SampleProducer};
 
-pub fn record_audio(file_path: &str, is_recording_flag: Arc<AtomicBool>, processor: AudioProcessor) -> Result<(), Box<dyn Error>> {
-    let host = cpal::default_host();
-    let device = host.default_input_device().expect("Failed to get default input device");
-    let config = device.default_input_config()?;
+// Audio the ring buffer holds between the device callback and the writer thread
+const RING_SECONDS: usize = 2;
+// How long the writer thread sleeps when the ring buffer is empty
+const DRAIN_INTERVAL: Duration = Duration::from_millis(5);
 
-    let sample_format = config.sample_format();
-    let channels = config.channels();
-    let input_sample_rate = config.sample_rate();
-    let config = config.config();
+/// What happened to the audio between the input device and the recording.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecordingStats {
+    /// Samples, counting every channel, that reached the recording.
+    pub captured_samples: u64,
+    /// Samples the device delivered while the ring buffer was full; they are
+    /// missing from the recording.
+    pub dropped_samples: u64,
+    /// Device callbacks that lost samples. Each one leaves a gap in the recording.
+    pub overruns: u64,
+}
+
+impl RecordingStats {
+    /// Whether any audio was lost, leaving the recording shorter than the time it ran.
+    pub fn has_gaps(&self) -> bool {
+        self.dropped_samples > 0
+    }
+}
+
+/// Progress of a recording with a maximum duration, passed to the handler
+/// set with `RusticAudio::on_recording_event`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingEvent {
+    /// Time left before the recording stops itself, sent about every 100 ms.
+    Remaining(Duration),
+    /// The time left has reached the warning set with `set_recording_warning`. Sent once.
+    Warning(Duration),
+    /// Capture stopped at the limit; post-processing follows.
+    LimitReached,
+}
+
+type RecordingEventHandler = Box<dyn FnMut(RecordingEvent) + Send>;
+
+// Handler for `RecordingEvent`s, shared with the recording thread
+#[derive(Clone, Default)]
+pub(crate) struct RecordingEvents(Arc<Mutex<Option<RecordingEventHandler>>>);
+
+impl RecordingEvents {
+    pub(crate) fn set(&self, handler: RecordingEventHandler) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(handler);
+    }
+
+    fn emit(&self, event: RecordingEvent) {
+        if let Some(handler) = self.0.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            handler(event);
+        }
+    }
+}
+
+// How long a recording may run and when to warn before it stops
+#[derive(Clone, Default)]
+pub(crate) struct RecordingLimit {
+    pub(crate) max_duration: Option<Duration>,
+    pub(crate) warn_before: Option<Duration>,
+    pub(crate) events: RecordingEvents,
+}
+
+// State the input callback shares with `record_audio`
+struct CaptureState {
+    is_recording: Arc<AtomicBool>,
+    // Samples the maximum duration allows, counting every channel
+    limit_samples: Option<u64>,
+    // Samples the device delivered while recording, kept or dropped; only the callback stores it
+    delivered_samples: AtomicU64,
+    limit_reached: AtomicBool,
+    dropped_samples: AtomicU64,
+    overruns: AtomicU64,
+}
+
+pub fn record_audio(
+    file_path: &Path,
+    is_recording_flag: Arc<AtomicBool>,
+    processor: AudioProcessor,
+    resampler_quality: ResamplerQuality,
+    device_settings: &DeviceSettings,
+    limit: &RecordingLimit,
+) -> Result<RecordingStats> {
+    let (device, config, sample_format) = open_device(device_settings, DeviceDirection::Input)?;
+    let channels = config.channels;
+    let input_sample_rate = config.sample_rate;
 
     println!("Recording with: format={:?}, rate={}, channels={}", 
              sample_format, input_sample_rate, channels);
 
-    // Create a temporary file for initial recording
-    let temp_file = "temp_recording.wav";
-    let spec = hound::WavSpec {
-        channels,
-        sample_rate: input_sample_rate,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
+    // The callback only pushes into the ring buffer; a writer thread drains it
+    // into memory, so nothing the callback does can block
+    let (producer, consumer) = sample_ring(input_sample_rate as usize * channels as usize * RING_SECONDS);
+    // The limit is counted in whole frames at the device rate, so capture stops on the exact sample
+    let state = Arc::new(CaptureState {
+        is_recording: Arc::clone(&is_recording_flag),
+        limit_samples: limit.max_duration
+            .map(|max| (max.as_secs_f64() * input_sample_rate as f64).round() as u64 * channels as u64),
+        delivered_samples: AtomicU64::new(0),
+        limit_reached: AtomicBool::new(false),
+        dropped_samples: AtomicU64::new(0),
+        overruns: AtomicU64::new(0),
+    });
+    let capturing = Arc::new(AtomicBool::new(true));
+    let captured = Arc::new(AtomicU64::new(0));
+    let writer = {
+        let capturing = Arc::clone(&capturing);
+        let captured = Arc::clone(&captured);
+        thread::spawn(move || drain_capture(consumer, &capturing, &captured))
     };
-    
-    let writer = Arc::new(Mutex::new(Some(hound::WavWriter::create(temp_file, spec)?)));
-    let samples_written = Arc::new(Mutex::new(0u32));
 
+    use cpal::SampleFormat::*;
     let stream = match sample_format {
-        cpal::SampleFormat::F32 => {
-            let writer_clone = Arc::clone(&writer);
-            let is_recording = Arc::clone(&is_recording_flag);
-            let samples_count = Arc::clone(&samples_written);
-            
-            device.build_input_stream(
-                &config,
-                move |data: &[f32], _| {
-                    if is_recording.load(Ordering::Relaxed) {
-                        if let Ok(mut guard) = writer_clone.try_lock() {
-                            if let Some(writer) = guard.as_mut() {
-                                for &sample in data {
-                                    let sample = (sample * i16::MAX as f32) as i16;
-                                    let _ = writer.write_sample(sample);
-                                    if let Ok(mut count) = samples_count.try_lock() {
-                                        *count += 1;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                },
-                |err| eprintln!("Stream error: {:?}", err),
-                None,
-            )?
-        },
-        cpal::SampleFormat::I16 => {
-            let writer_clone = Arc::clone(&writer);
-            let is_recording = Arc::clone(&is_recording_flag);
-            let samples_count = Arc::clone(&samples_written);
-            
-            device.build_input_stream(
-                &config,
-                move |data: &[i16], _| {
-                    if is_recording.load(Ordering::Relaxed) {
-                        if let Ok(mut guard) = writer_clone.try_lock() {
-                            if let Some(writer) = guard.as_mut() {
-                                for &sample in data {
-                                    let _ = writer.write_sample(sample);
-                                    if let Ok(mut count) = samples_count.try_lock() {
-                                        *count += 1;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                },
-                |err| eprintln!("Stream error: {:?}", err),
-                None,
-            )?
-        },
-        cpal::SampleFormat::U16 => {
-            let writer_clone = Arc::clone(&writer);
-            let is_recording = Arc::clone(&is_recording_flag);
-            let samples_count = Arc::clone(&samples_written);
-            
-            device.build_input_stream(
-                &config,
-                move |data: &[u16], _| {
-                    if is_recording.load(Ordering::Relaxed) {
-                        if let Ok(mut guard) = writer_clone.try_lock() {
-                            if let Some(writer) = guard.as_mut() {
-                                for &sample in data {
-                                    let sample = sample as i16 - i16::MAX;
-                                    let _ = writer.write_sample(sample);
-                                    if let Ok(mut count) = samples_count.try_lock() {
-                                        *count += 1;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                },
-                |err| eprintln!("Stream error: {:?}", err),
-                None,
-            )?
-        },
-        _ => return Err("Unsupported sample format".into()),
+        F32 => build_capture::<f32>(&device, &config, producer, &state),
+        F64 => build_capture::<f64>(&device, &config, producer, &state),
+        I8 => build_capture::<i8>(&device, &config, producer, &state),
+        I16 => build_capture::<i16>(&device, &config, producer, &state),
+        I24 => build_capture::<cpal::I24>(&device, &config, producer, &state),
+        I32 => build_capture::<i32>(&device, &config, producer, &state),
+        I64 => build_capture::<i64>(&device, &config, producer, &state),
+        U8 => build_capture::<u8>(&device, &config, producer, &state),
+        U16 => build_capture::<u16>(&device, &config, producer, &state),
+        U24 => build_capture::<cpal::U24>(&device, &config, producer, &state),
+        U32 => build_capture::<u32>(&device, &config, producer, &state),
+        U64 => build_capture::<u64>(&device, &config, producer, &state),
+        other => Err(RusticAudioError::UnsupportedSampleFormat(format!("{:?} input", other))),
+    };
+    let stream = stream.and_then(|stream| {
+        stream.play()?;
+        Ok(stream)
+    });
+    let stream = match stream {
+        Ok(stream) => stream,
+        Err(e) => {
+            capturing.store(false, Ordering::Release);
+            let _ = writer.join();
+            return Err(e);
+        }
     };
-
-    println!("Stream created, starting playback");
-    stream.play()?;
     println!("Stream started");
 
+    let mut tracker = limit.max_duration.map(|max_duration| LimitTracker {
+        max_duration,
+        warn_before: limit.warn_before,
+        warned: false,
+    });
     while is_recording_flag.load(Ordering::Relaxed) {
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        if let Ok(count) = samples_written.try_lock() {
-            println!("Samples written: {}", *count);
-        }
-    }
-
-    // Give a small delay for the stream to finish
-    std::thread::sleep(std::time::Duration::from_millis(500));
+        thread::sleep(Duration::from_millis(100));
+        println!("Samples written: {}", captured.load(Ordering::Relaxed));
 
-    // Drop the stream first
-    drop(stream);
-    println!("Stream dropped");
-
-    // Then finalize the writer
-    if let Ok(mut guard) = writer.try_lock() {
-        if let Some(writer) = guard.take() {
-            match writer.finalize() {
-                Ok(_) => println!("Writer finalized successfully"),
-                Err(e) => eprintln!("Error finalizing writer: {:?}", e),
+        if let Some(tracker) = tracker.as_mut() {
+            let frames = state.delivered_samples.load(Ordering::Relaxed) / channels as u64;
+            let elapsed = Duration::from_secs_f64(frames as f64 / input_sample_rate as f64);
+            let reached = state.limit_reached.load(Ordering::Acquire);
+            tracker.update(elapsed, reached, |event| limit.events.emit(event));
+            if reached {
+                is_recording_flag.store(false, Ordering::Relaxed);
             }
         }
     }
 
-    if let Ok(count) = samples_written.try_lock() {
-        println!("Total samples recorded: {}", *count);
-    }
-    
-    if let Ok(metadata) = std::fs::metadata(temp_file) {
-        println!("Output file size: {} bytes", metadata.len());
-    }
+    // Once the stream is gone nothing more is pushed; the writer drains what is left
+    drop(stream);
+    capturing.store(false, Ordering::Release);
+    let samples = writer.join().map_err(|_| RusticAudioError::ThreadPanicked)?;
 
-    // Save the original recording first
-    std::fs::copy(temp_file, "original.wav")?;
-    
-    // Read the temporary file for processing
-    let mut reader = hound::WavReader::open(temp_file)?;
-    let input_spec = reader.spec();
-    
-    // Read all samples into memory
-    let samples: Vec<i16> = reader.samples::<i16>()
-        .filter_map(Result::ok)
-        .collect();
-    
-    // Convert to mono if stereo (take left channel)
-    let mono_samples: Vec<i16> = if input_spec.channels == 2 {
-        samples.chunks(2)
-            .map(|chunk| chunk[0]) // Take left channel
-            .collect()
-    } else {
-        samples
+    let stats = RecordingStats {
+        captured_samples: samples.len() as u64,
+        dropped_samples: state.dropped_samples.load(Ordering::Relaxed),
+        overruns: state.overruns.load(Ordering::Relaxed),
     };
-    
-    // Convert to float for processing
-    let mut mono_float: Vec<f32> = mono_samples.iter()
-        .map(|&s| s as f32 / 32768.0)
-        .collect();
+    println!("Total samples recorded: {}", stats.captured_samples);
+    if stats.has_gaps() {
+        eprintln!("Warning: {} samples dropped in {} overruns; the recording has gaps",
+                  stats.dropped_samples, stats.overruns);
+    }
+
+    // Reduce every channel to mono for processing
+    let mut mono_float = processor.downmix.apply(&samples, channels as usize)?;
 
     // Apply highpass filter at 20Hz
-    apply_highpass_filter(&mut mono_float, 20.0, input_spec.sample_rate as f32);
+    apply_highpass_filter(&mut mono_float, 20.0, input_sample_rate as f32);
 
     // Apply RMS normalization with peak limiting if enabled in processor
     if processor.rms_enabled {
@@ -192,43 +207,111 @@ pub fn record_audio(file_path: &str, is_recording_flag: Arc<AtomicBool>, process
 
     let mut output_writer = hound::WavWriter::create(file_path, output_spec)?;
 
-    if input_spec.sample_rate != 48000 {
-        let input_duration = mono_float.len() as f32 / input_spec.sample_rate as f32;
-        let output_len = (input_duration * 48000.0) as usize;
-        let scale = (mono_float.len() - 1) as f32 / (output_len - 1) as f32;
-        
-        for i in 0..output_len {
-            let pos = i as f32 * scale;
-            let index = pos as usize;
-            let frac = pos - index as f32;
-            
-            let sample = if index + 1 < mono_float.len() {
-                mono_float[index] * (1.0 - frac) + mono_float[index + 1] * frac
-            } else {
-                mono_float[index]
-            };
-            
-            let sample_i16 = (sample * 32767.0).min(32767.0).max(-32768.0) as i16;
-            output_writer.write_sample(sample_i16)?;
-        }
-    } else {
-        // No resampling needed, just write normalized float samples as i16
-        for &sample in &mono_float {
-            let sample_i16 = (sample * 32767.0).min(32767.0).max(-32768.0) as i16;
-            output_writer.write_sample(sample_i16)?;
-        }
+    for sample in resample(&mono_float, input_sample_rate, 48000, resampler_quality) {
+        let sample_i16 = (sample * 32767.0).clamp(-32768.0, 32767.0) as i16;
+        output_writer.write_sample(sample_i16)?;
     }
 
     output_writer.finalize()?;
-    
-    // Clean up temporary file
-    std::fs::remove_file(temp_file)?;
 
-    Ok(())
+    Ok(stats)
+}
+
+// Builds an input stream in the device's sample format that converts to f32
+// and pushes whole callbacks' worth of frames into the ring buffer, up to the
+// limit. What does not fit is counted, never waited for.
+fn build_capture<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut producer: SampleProducer,
+    state: &Arc<CaptureState>,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let state = Arc::clone(state);
+
+    Ok(device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            if !state.is_recording.load(Ordering::Relaxed) || state.limit_reached.load(Ordering::Relaxed) {
+                return;
+            }
+            let delivered = state.delivered_samples.load(Ordering::Relaxed);
+            let data = within_limit(data, delivered, state.limit_samples);
+            let delivered = delivered + data.len() as u64;
+            state.delivered_samples.store(delivered, Ordering::Relaxed);
+            if state.limit_samples == Some(delivered) {
+                state.limit_reached.store(true, Ordering::Release);
+            }
+
+            // Whole frames only, so a drop never shifts the channels of later audio
+            let room = producer.free() / channels * channels;
+            let pushed = producer.push(data.iter().take(room).map(|&sample| sample.to_sample::<f32>()));
+            if pushed < data.len() {
+                state.overruns.fetch_add(1, Ordering::Relaxed);
+                state.dropped_samples.fetch_add((data.len() - pushed) as u64, Ordering::Relaxed);
+            }
+        },
+        |err| eprintln!("Stream error: {:?}", err),
+        None,
+    )?)
+}
+
+// The part of a callback's samples the limit still allows, after `delivered`
+// samples have already been taken
+fn within_limit<T>(data: &[T], delivered: u64, limit_samples: Option<u64>) -> &[T] {
+    match limit_samples {
+        Some(limit) => &data[..data.len().min(limit.saturating_sub(delivered) as usize)],
+        None => data,
+    }
+}
+
+// Turns capture progress into `RecordingEvent`s for a recording with a maximum duration
+struct LimitTracker {
+    max_duration: Duration,
+    warn_before: Option<Duration>,
+    warned: bool,
+}
+
+impl LimitTracker {
+    fn update(&mut self, elapsed: Duration, reached: bool, mut emit: impl FnMut(RecordingEvent)) {
+        let remaining = if reached { Duration::ZERO } else { self.max_duration.saturating_sub(elapsed) };
+        if !self.warned && self.warn_before.is_some_and(|warn_before| remaining <= warn_before) {
+            self.warned = true;
+            emit(RecordingEvent::Warning(remaining));
+        }
+        emit(RecordingEvent::Remaining(remaining));
+        if reached {
+            emit(RecordingEvent::LimitReached);
+        }
+    }
+}
+
+// Moves samples from the ring buffer into memory until capture has stopped
+// and the buffer is empty
+fn drain_capture(mut consumer: SampleConsumer, capturing: &AtomicBool, captured: &AtomicU64) -> Vec<f32> {
+    let mut samples = Vec::new();
+    let mut chunk = vec![0.0; 4096];
+    loop {
+        // Checked before popping, so samples pushed just before the stop are still drained
+        let stopped = !capturing.load(Ordering::Acquire);
+        let count = consumer.pop(&mut chunk);
+        samples.extend_from_slice(&chunk[..count]);
+        captured.fetch_add(count as u64, Ordering::Relaxed);
+        if count == 0 {
+            if stopped {
+                return samples;
+            }
+            thread::sleep(DRAIN_INTERVAL);
+        }
+    }
 }
 
 // Add this new function for RMS normalization with peak limiting
-fn normalize_audio_rms(samples: &mut Vec<f32>, target_rms_db: f32) {
+fn normalize_audio_rms(samples: &mut [f32], target_rms_db: f32) {
     // Calculate current RMS
     let rms_current = (samples.iter().map(|&x| x * x).sum::<f32>() / samples.len() as f32).sqrt();
     let rms_current_db = 20.0 * rms_current.log10();
@@ -257,7 +340,7 @@ fn normalize_audio_rms(samples: &mut Vec<f32>, target_rms_db: f32) {
         }
         
         // Hard limit as a safety measure
-        *sample = sample.max(-1.0).min(1.0);
+        *sample = sample.clamp(-1.0, 1.0);
     }
     
     // Calculate new RMS after normalization
@@ -268,7 +351,7 @@ fn normalize_audio_rms(samples: &mut Vec<f32>, target_rms_db: f32) {
 }
 
 // Add this new function for the highpass filter
-fn apply_highpass_filter(samples: &mut Vec<f32>, cutoff_hz: f32, sample_rate: f32) {
+fn apply_highpass_filter(samples: &mut [f32], cutoff_hz: f32, sample_rate: f32) {
     println!("Applying highpass filter at {} Hz", cutoff_hz);
     
     // Calculate filter coefficients (first-order highpass)
@@ -304,3 +387,62 @@ fn apply_highpass_filter(samples: &mut Vec<f32>, cutoff_hz: f32, sample_rate: f3
     println!("  DC offset after: {:.6}", dc_after);
     println!("  Final DC offset: {:.6}", samples.iter().sum::<f32>() / samples.len() as f32);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_limit_cuts_capture_on_the_exact_sample() {
+        let data = [0i16; 512];
+        assert_eq!(within_limit(&data, 0, None).len(), 512);
+        assert_eq!(within_limit(&data, 1000, Some(1200)).len(), 200);
+        assert_eq!(within_limit(&data, 1200, Some(1200)).len(), 0);
+        assert_eq!(within_limit(&data, 0, Some(1200)).len(), 512);
+    }
+
+    #[test]
+    fn remaining_time_counts_down_with_one_warning() {
+        let mut tracker = LimitTracker {
+            max_duration: Duration::from_secs(30),
+            warn_before: Some(Duration::from_secs(5)),
+            warned: false,
+        };
+        let mut events = Vec::new();
+        for elapsed in [10, 25, 27] {
+            tracker.update(Duration::from_secs(elapsed), false, |event| events.push(event));
+        }
+        tracker.update(Duration::from_millis(29_900), true, |event| events.push(event));
+
+        assert_eq!(events, [
+            RecordingEvent::Remaining(Duration::from_secs(20)),
+            RecordingEvent::Warning(Duration::from_secs(5)),
+            RecordingEvent::Remaining(Duration::from_secs(5)),
+            RecordingEvent::Remaining(Duration::from_secs(3)),
+            RecordingEvent::Remaining(Duration::ZERO),
+            RecordingEvent::LimitReached,
+        ]);
+    }
+
+    #[test]
+    fn the_writer_drains_everything_pushed_before_the_stop() {
+        let (mut producer, consumer) = sample_ring(1024);
+        let capturing = Arc::new(AtomicBool::new(true));
+        let captured = Arc::new(AtomicU64::new(0));
+        let writer = {
+            let (capturing, captured) = (Arc::clone(&capturing), Arc::clone(&captured));
+            thread::spawn(move || drain_capture(consumer, &capturing, &captured))
+        };
+
+        let mut pushed = 0;
+        while pushed < 10_000 {
+            pushed += producer.push((pushed..10_000).map(|i| i as f32));
+        }
+        capturing.store(false, Ordering::Release);
+
+        let samples = writer.join().unwrap();
+        assert_eq!(samples.len(), 10_000);
+        assert!(samples.iter().enumerate().all(|(i, &sample)| sample == i as f32));
+        assert_eq!(captured.load(Ordering::Relaxed), 10_000);
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Creates a single-producer, single-consumer queue of f32 samples holding at
+/// least `capacity` samples.
+///
+/// Neither side ever locks, allocates or waits, so the producer can live in a
+/// realtime audio callback. Samples are stored as their bits in atomics, which
+/// keeps the queue free of `unsafe`.
+pub(crate) fn sample_ring(capacity: usize) -> (SampleProducer, SampleConsumer) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let ring = Arc::new(Ring {
+        slots: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+        mask: capacity - 1,
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+    });
+    (SampleProducer { ring: Arc::clone(&ring) }, SampleConsumer { ring })
+}
+
+struct Ring {
+    slots: Box<[AtomicU32]>,
+    // Capacity minus one; the capacity is a power of two so counts can wrap
+    mask: usize,
+    // Running totals of samples pushed and popped; only their owner stores them
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+impl Ring {
+    fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+}
+
+/// The writing end of a `sample_ring`.
+pub(crate) struct SampleProducer {
+    ring: Arc<Ring>,
+}
+
+impl SampleProducer {
+    /// Samples that can be pushed right now. Only grows until the next push.
+    pub(crate) fn free(&self) -> usize {
+        let written = self.ring.written.load(Ordering::Relaxed);
+        let read = self.ring.read.load(Ordering::Acquire);
+        self.ring.capacity() - written.wrapping_sub(read)
+    }
+
+    /// Pushes samples until the queue is full and returns how many it took.
+    pub(crate) fn push(&mut self, samples: impl IntoIterator<Item = f32>) -> usize {
+        let written = self.ring.written.load(Ordering::Relaxed);
+        let mut pushed = 0;
+        for sample in samples.into_iter().take(self.free()) {
+            self.ring.slots[written.wrapping_add(pushed) & self.ring.mask].store(sample.to_bits(), Ordering::Relaxed);
+            pushed += 1;
+        }
+        // Publishes the slots stored above to the consumer
+        self.ring.written.store(written.wrapping_add(pushed), Ordering::Release);
+        pushed
+    }
+}
+
+/// The reading end of a `sample_ring`.
+pub(crate) struct SampleConsumer {
+    ring: Arc<Ring>,
+}
+
+impl SampleConsumer {
+    /// Moves queued samples into `out` and returns how many it moved.
+    pub(crate) fn pop(&mut self, out: &mut [f32]) -> usize {
+        let read = self.ring.read.load(Ordering::Relaxed);
+        let written = self.ring.written.load(Ordering::Acquire);
+        let count = written.wrapping_sub(read).min(out.len());
+        for (offset, sample) in out[..count].iter_mut().enumerate() {
+            *sample = f32::from_bits(self.ring.slots[read.wrapping_add(offset) & self.ring.mask].load(Ordering::Relaxed));
+        }
+        // Hands the slots back to the producer once they have been read
+        self.ring.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sample_ring;
+
+    #[test]
+    fn samples_come_out_in_order_across_the_wrap() {
+        let (mut producer, mut consumer) = sample_ring(6);
+        assert_eq!(producer.free(), 8);
+        let mut out = [0.0; 8];
+
+        assert_eq!(producer.push([1.0, 2.0, 3.0, 4.0, 5.0, 6.0]), 6);
+        assert_eq!(consumer.pop(&mut out[..4]), 4);
+        assert_eq!(out[..4], [1.0, 2.0, 3.0, 4.0]);
+
+        // Only 6 of these fit; the rest are refused
+        assert_eq!(producer.push((7..15).map(|i| i as f32)), 6);
+        assert_eq!(producer.free(), 0);
+        assert_eq!(consumer.pop(&mut out), 8);
+        assert_eq!(out, [5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0]);
+        assert_eq!(consumer.pop(&mut out), 0);
+    }
+
+    #[test]
+    fn a_reader_thread_sees_every_pushed_sample_once() {
+        let (mut producer, mut consumer) = sample_ring(64);
+        let total = 200_000;
+        let writer = std::thread::spawn(move || {
+            let mut next = 0;
+            while next < total {
+                let end = (next + 37).min(total);
+                next += producer.push((next..end).map(|i| i as f32));
+            }
+        });
+
+        let mut expected = 0;
+        let mut out = [0.0; 50];
+        while expected < total {
+            let count = consumer.pop(&mut out);
+            for &sample in &out[..count] {
+                assert_eq!(sample, expected as f32);
+                expected += 1;
+            }
+        }
+        writer.join().unwrap();
+    }
+}
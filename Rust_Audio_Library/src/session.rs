@@ -0,0 +1,445 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::device::DeviceSettings;
+use crate::dsp::AudioProcessor;
+use crate::error::{Result, RusticAudioError};
+use crate::opus_encoder::OpusEncoder;
+use crate::padding::pad_opus_file;
+use crate::record::{record_audio, RecordingLimit, RecordingStats};
+
+// Tells apart temporary directories and recordings staged in the same process
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Every file a finished recording produced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordingArtifacts {
+    /// Directory all of them are in.
+    pub dir: PathBuf,
+    /// The capture as a mono 48 kHz WAV, high-pass filtered and, when enabled, RMS normalized.
+    pub original_wav: PathBuf,
+    /// The capture after the processor's effect chain.
+    pub processed_wav: PathBuf,
+    /// The processed WAV encoded to Opus, within the size budget and padded to a size class when set.
+    pub processed_opus: PathBuf,
+    /// The original WAV encoded to Opus, for comparison.
+    pub unprocessed_opus: PathBuf,
+    /// A copy of the original WAV at the path given to `RusticAudio::start_recording`;
+    /// `None` for other sessions.
+    pub output_wav: Option<PathBuf>,
+}
+
+impl RecordingArtifacts {
+    /// The paths of every file, in the order they are written.
+    pub fn paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = vec![&self.original_wav, &self.processed_wav, &self.processed_opus, &self.unprocessed_opus];
+        paths.extend(self.output_wav.as_deref());
+        paths
+    }
+}
+
+/// Where one recording writes its files.
+///
+/// A session either writes into a directory the caller names or into a
+/// private directory it creates under the system temp dir, so recorders
+/// never share file names by accident. Each step writes to a hidden `.part`
+/// file named for that one recording, so two recordings with the same name
+/// never write into each other's files, and the files only take their final
+/// names once every step has succeeded. If capture fails, the `.part` files are removed, along with
+/// the directory when the session created it, so it leaves nothing behind.
+/// Once audio has been captured it is never thrown away: if processing or
+/// encoding it fails, the other `.part` files are removed and the capture
+/// takes its final `<name>_original.wav` name, reported in
+/// `RusticAudioError::ProcessingFailed`.
+///
+/// ```no_run
+/// use rustic_audio_tool::{RecordingSession, RusticAudio};
+///
+/// let mut audio = RusticAudio::new();
+/// audio.start_recording_session(RecordingSession::in_dir("recordings")?.with_name("interview")?)?;
+/// // ...
+/// audio.stop_recording()?;
+/// if let Some(artifacts) = audio.last_recording() {
+///     println!("{}", artifacts.processed_opus.display());
+/// }
+/// # Ok::<(), rustic_audio_tool::RusticAudioError>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct RecordingSession {
+    dir: PathBuf,
+    name: String,
+    // Set when the session created `dir` and removes it on failure
+    owns_dir: bool,
+    overwrite: bool,
+    // File in `dir` that also receives a copy of the original WAV
+    output: Option<String>,
+}
+
+impl RecordingSession {
+    /// Writes into `dir`, creating it when missing. Files are named after
+    /// `recording` unless `with_name` says otherwise.
+    pub fn in_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let owns_dir = !dir.exists();
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            name: "recording".to_string(),
+            owns_dir,
+            overwrite: false,
+            output: None,
+        })
+    }
+
+    /// Writes into a new directory under the system temp dir that no other
+    /// session uses. The directory stays after a successful recording; it
+    /// belongs to the caller from then on.
+    pub fn temporary() -> Result<Self> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        loop {
+            let dir = std::env::temp_dir().join(format!(
+                "rustic_audio_{}_{}_{}",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                nanos
+            ));
+            // `create_dir` fails if the directory exists, so it is never shared
+            match std::fs::create_dir(&dir) {
+                Ok(()) => {
+                    return Ok(Self { dir, name: "recording".to_string(), owns_dir: true, overwrite: false, output: None });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Names the files `<name>_original.wav`, `<name>_processed.wav`,
+    /// `<name>_processed.opus` and `<name>_unprocessed.opus`.
+    ///
+    /// # Returns
+    /// * `Err(RusticAudioError::InvalidArgument)` if `name` is empty or holds a path separator
+    pub fn with_name(mut self, name: &str) -> Result<Self> {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(RusticAudioError::InvalidArgument(format!("{:?} is not a file name", name)));
+        }
+        self.name = name.to_string();
+        Ok(self)
+    }
+
+    /// Whether existing files with the same names are replaced. Off by
+    /// default: starting a session whose files already exist fails instead.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    // Also writes the original WAV to `file_name` in the session's directory,
+    // as `start_recording` promises its callers
+    pub(crate) fn with_output_copy(mut self, file_name: &str) -> Self {
+        self.output = Some(file_name.to_string());
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The paths this session writes to.
+    pub fn artifacts(&self) -> RecordingArtifacts {
+        self.paths("", "")
+    }
+
+    // Where each step of one recording writes before it is complete. The names
+    // are unique to that recording, so recordings with the same name in one
+    // directory never write to each other's files
+    fn staged(&self) -> RecordingArtifacts {
+        let stage = format!(".{}_{}.part", std::process::id(), NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        self.paths(".", &stage)
+    }
+
+    fn paths(&self, prefix: &str, extension: &str) -> RecordingArtifacts {
+        let path = |suffix: &str| self.dir.join(format!("{}{}_{}{}", prefix, self.name, suffix, extension));
+        RecordingArtifacts {
+            dir: self.dir.clone(),
+            original_wav: path("original.wav"),
+            processed_wav: path("processed.wav"),
+            processed_opus: path("processed.opus"),
+            unprocessed_opus: path("unprocessed.opus"),
+            output_wav: self.output.as_ref().map(|name| self.dir.join(format!("{}{}{}", prefix, name, extension))),
+        }
+    }
+
+    // Fails if another recording's files are in the way
+    pub(crate) fn check_free(&self) -> Result<()> {
+        if self.overwrite {
+            return Ok(());
+        }
+        match self.artifacts().paths().into_iter().find(|path| path.exists()) {
+            Some(path) => Err(RusticAudioError::InvalidArgument(format!("{} already exists", path.display()))),
+            None => Ok(()),
+        }
+    }
+
+    // Records until `is_recording` clears or the pipeline's limit is reached, then processes and encodes the
+    // capture. If capture fails, every file written so far is removed.
+    pub(crate) fn record(
+        &self,
+        is_recording: Arc<AtomicBool>,
+        pipeline: &RecordingPipeline,
+    ) -> Result<(RecordingArtifacts, RecordingStats)> {
+        let staged = self.staged();
+        let mut cleanup = Cleanup {
+            paths: staged.paths().into_iter().map(Path::to_path_buf).collect(),
+            dir: self.owns_dir.then(|| self.dir.clone()),
+        };
+
+        let stats = record_audio(
+            &staged.original_wav,
+            is_recording,
+            pipeline.processor.clone(),
+            pipeline.opus_encoder.get_resampler_quality(),
+            &pipeline.input_device,
+            &pipeline.limit,
+        )?;
+        cleanup.disarm();
+        Ok((self.finish(staged, pipeline)?, stats))
+    }
+
+    // Processes and encodes the staged capture and moves every file into place. If a step fails, the files
+    // it wrote are removed but the capture is kept under its final name.
+    fn finish(&self, staged: RecordingArtifacts, pipeline: &RecordingPipeline) -> Result<RecordingArtifacts> {
+        let artifacts = self.artifacts();
+        let mut cleanup = Cleanup {
+            paths: staged.paths()[1..].iter().map(|path| path.to_path_buf()).collect(),
+            dir: None,
+        };
+
+        if let Err(e) = pipeline.process(&staged) {
+            drop(cleanup);
+            // Left as the `.part` file if it cannot be renamed, rather than lost
+            let original_wav = match std::fs::rename(&staged.original_wav, &artifacts.original_wav) {
+                Ok(()) => {
+                    // Best effort, as the error already names the kept file
+                    if let Some(output_wav) = &artifacts.output_wav {
+                        let _ = std::fs::copy(&artifacts.original_wav, output_wav);
+                    }
+                    artifacts.original_wav
+                }
+                Err(_) => staged.original_wav,
+            };
+            return Err(RusticAudioError::ProcessingFailed { original_wav, source: Box::new(e) });
+        }
+        if let Some(output_wav) = &staged.output_wav {
+            std::fs::copy(&staged.original_wav, output_wav)?;
+        }
+
+        for (from, to) in staged.paths().into_iter().zip(artifacts.paths()) {
+            std::fs::rename(from, to)?;
+        }
+        cleanup.disarm();
+        Ok(artifacts)
+    }
+}
+
+/// Settings a recording is processed and encoded with, captured when it starts.
+pub(crate) struct RecordingPipeline {
+    pub(crate) processor: AudioProcessor,
+    pub(crate) opus_encoder: OpusEncoder,
+    // The encoder for the processed file, which may carry the DSP chain tag
+    pub(crate) processed_encoder: OpusEncoder,
+    pub(crate) size_budget: Option<usize>,
+    pub(crate) size_classes: Option<Vec<usize>>,
+    pub(crate) input_device: DeviceSettings,
+    pub(crate) limit: RecordingLimit,
+}
+
+impl RecordingPipeline {
+    fn process(&self, artifacts: &RecordingArtifacts) -> Result<()> {
+        let original_wav = path_str(&artifacts.original_wav)?;
+        let processed_wav = path_str(&artifacts.processed_wav)?;
+        let processed_opus = path_str(&artifacts.processed_opus)?;
+
+        self.processor.clone().process_file(original_wav, processed_wav)?;
+        match self.size_budget {
+            Some(max_bytes) => self.processed_encoder.encode_wav_to_opus_with_size_budget(processed_wav, processed_opus, max_bytes)?,
+            None => self.processed_encoder.encode_wav_to_opus(processed_wav, processed_opus)?,
+        }
+        if let Some(classes) = &self.size_classes {
+            pad_opus_file(processed_opus, classes)?;
+        }
+        self.opus_encoder.encode_wav_to_opus(original_wav, path_str(&artifacts.unprocessed_opus)?)
+    }
+}
+
+// Removes a session's staged files when dropped, unless the session finished
+struct Cleanup {
+    paths: Vec<PathBuf>,
+    // The session's own directory, removed once empty
+    dir: Option<PathBuf>,
+}
+
+impl Cleanup {
+    fn disarm(&mut self) {
+        self.paths.clear();
+        self.dir = None;
+    }
+}
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = std::fs::remove_file(path);
+        }
+        if let Some(dir) = &self.dir {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| RusticAudioError::InvalidArgument(format!("{} is not valid UTF-8", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_tone(path: &Path) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48_000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for i in 0..24_000 {
+            writer.write_sample(((i as f32 * 0.05).sin() * 8000.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn pipeline() -> RecordingPipeline {
+        RecordingPipeline {
+            processor: AudioProcessor::new(48_000.0),
+            opus_encoder: OpusEncoder::new(),
+            processed_encoder: OpusEncoder::new(),
+            size_budget: None,
+            size_classes: None,
+            input_device: DeviceSettings::default(),
+            limit: RecordingLimit::default(),
+        }
+    }
+
+    #[test]
+    fn temporary_sessions_never_share_a_directory() {
+        let first = RecordingSession::temporary().unwrap();
+        let second = RecordingSession::temporary().unwrap();
+        assert_ne!(first.dir(), second.dir());
+        assert!(first.dir().is_dir() && second.dir().is_dir());
+
+        let artifacts = first.artifacts();
+        assert_eq!(artifacts.original_wav, first.dir().join("recording_original.wav"));
+        assert!(artifacts.paths().iter().all(|path| path.starts_with(first.dir())));
+
+        std::fs::remove_dir(first.dir()).unwrap();
+        std::fs::remove_dir(second.dir()).unwrap();
+    }
+
+    #[test]
+    fn recordings_with_the_same_name_stage_to_their_own_files() {
+        let session = RecordingSession::temporary().unwrap().with_name("take").unwrap().overwrite(true);
+        let first = session.staged();
+        let second = session.clone().staged();
+        assert!(first.original_wav.file_name().unwrap().to_str().unwrap().starts_with(".take_original.wav."));
+        assert!(first.paths().iter().all(|path| path.starts_with(session.dir()) && !second.paths().contains(path)));
+
+        std::fs::remove_dir(session.dir()).unwrap();
+    }
+
+    #[test]
+    fn names_and_existing_files_are_checked() {
+        let session = RecordingSession::temporary().unwrap();
+        for name in ["", "a/b", "..", "a\\b"] {
+            assert!(matches!(session.clone().with_name(name), Err(RusticAudioError::InvalidArgument(_))));
+        }
+
+        let session = session.with_name("take").unwrap();
+        assert!(session.check_free().is_ok());
+        std::fs::write(session.artifacts().processed_opus, b"").unwrap();
+        assert!(matches!(session.check_free(), Err(RusticAudioError::InvalidArgument(_))));
+        assert!(session.clone().overwrite(true).check_free().is_ok());
+
+        std::fs::remove_dir_all(session.dir()).unwrap();
+    }
+
+    #[test]
+    fn the_pipeline_writes_every_artifact() {
+        let session = RecordingSession::temporary().unwrap();
+        let artifacts = session.artifacts();
+        write_tone(&artifacts.original_wav);
+
+        pipeline().process(&artifacts).unwrap();
+        assert!(artifacts.paths().iter().all(|path| path.is_file()));
+
+        std::fs::remove_dir_all(session.dir()).unwrap();
+    }
+
+    #[test]
+    fn an_output_copy_holds_the_original_wav() {
+        let session = RecordingSession::temporary().unwrap().with_name("take").unwrap().with_output_copy("take.wav");
+        let staged = session.staged();
+        write_tone(&staged.original_wav);
+
+        let artifacts = session.finish(staged, &pipeline()).unwrap();
+        let output_wav = artifacts.output_wav.clone().unwrap();
+        assert_eq!(output_wav, session.dir().join("take.wav"));
+        assert_eq!(std::fs::read(&output_wav).unwrap(), std::fs::read(&artifacts.original_wav).unwrap());
+        assert_eq!(artifacts.paths().len(), 5);
+        assert!(artifacts.paths().iter().all(|path| path.is_file()));
+        assert!(session.check_free().is_err());
+
+        std::fs::remove_dir_all(session.dir()).unwrap();
+    }
+
+    #[test]
+    fn a_failed_capture_removes_what_the_session_wrote() {
+        let session = RecordingSession::temporary().unwrap();
+        let artifacts = session.staged();
+        write_tone(&artifacts.original_wav);
+
+        // Dropped armed, as `record` drops it when capture returns early
+        drop(Cleanup {
+            paths: artifacts.paths().into_iter().map(Path::to_path_buf).collect(),
+            dir: Some(session.dir().to_path_buf()),
+        });
+        assert!(!session.dir().exists());
+    }
+
+    #[test]
+    fn a_failed_step_after_capture_keeps_the_original_wav() {
+        let session = RecordingSession::temporary().unwrap();
+        let staged = session.staged();
+        write_tone(&staged.original_wav);
+
+        // No budget can fit a second of audio into 10 bytes
+        let mut pipeline = pipeline();
+        pipeline.size_budget = Some(10);
+        let kept = match session.finish(staged.clone(), &pipeline) {
+            Err(RusticAudioError::ProcessingFailed { original_wav, source }) => {
+                assert!(matches!(*source, RusticAudioError::SizeBudgetExceeded { .. }));
+                original_wav
+            }
+            other => panic!("expected ProcessingFailed, got {:?}", other),
+        };
+
+        let artifacts = session.artifacts();
+        assert_eq!(kept, artifacts.original_wav);
+        assert!(kept.is_file());
+        assert!(staged.paths().iter().chain(&artifacts.paths()[1..]).all(|path| !path.exists()));
+
+        std::fs::remove_dir_all(session.dir()).unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+use crate::dsp::{AudioProcessor, FADE_IN_MS};
+use crate::effect::{Chain, Effect};
+use crate::error::{Result, RusticAudioError};
+
+/// Block-based version of the `AudioProcessor` chain for real-time use.
+///
+/// Blocks of any size go in and the same number of samples come out, delayed
+/// by `latency_samples()`. Every channel runs its own `Chain`, so STFT overlap,
+/// gate envelope, limiter gain and lookahead buffers are carried between calls
+/// and the result is the same as running `process_samples` on the whole signal.
+/// RMS normalization needs the level of the complete recording and is therefore
+/// not applied here.
+pub struct StreamingProcessor {
+    sample_rate: u32,
+    channels: usize,
+    chains: Vec<Chain>,
+    channel_block: Vec<f32>,
+}
+
+impl StreamingProcessor {
+    /// Builds a streaming chain from the stage settings of `processor`.
+    pub fn new(processor: &AudioProcessor, sample_rate: u32, channels: u16) -> Result<Self> {
+        let mut settings = processor.clone();
+        settings.sample_rate = sample_rate as f32;
+        let fade_samples = (FADE_IN_MS / 1000.0 * settings.sample_rate) as usize;
+
+        Self::with_chain(sample_rate, channels, || settings.build_chain(false, fade_samples))
+    }
+
+    /// Builds a streaming processor around a custom chain.
+    ///
+    /// `build` is called once per channel and must return chains with the same
+    /// latency, e.g. the same effects in the same order.
+    pub fn with_chain(sample_rate: u32, channels: u16, mut build: impl FnMut() -> Chain) -> Result<Self> {
+        if sample_rate == 0 || channels == 0 {
+            return Err(RusticAudioError::InvalidArgument(
+                "sample rate and channel count must be greater than zero".to_string(),
+            ));
+        }
+
+        let chains: Vec<Chain> = (0..channels).map(|_| build()).collect();
+        if chains.iter().any(|chain| chain.latency_samples() != chains[0].latency_samples()) {
+            return Err(RusticAudioError::InvalidArgument(
+                "every channel's chain must have the same latency".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            sample_rate,
+            channels: channels as usize,
+            chains,
+            channel_block: Vec::new(),
+        })
+    }
+
+    /// Total delay between a sample entering `process` and leaving it, in frames.
+    pub fn latency_samples(&self) -> usize {
+        self.chains[0].latency_samples()
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Processes an interleaved block in place.
+    ///
+    /// `block.len()` must be a multiple of the channel count.
+    pub fn process(&mut self, block: &mut [f32]) -> Result<()> {
+        if !block.len().is_multiple_of(self.channels) {
+            return Err(RusticAudioError::InvalidArgument(format!(
+                "{} samples is not a whole number of {}-channel frames", block.len(), self.channels
+            )));
+        }
+
+        let channels = self.channels;
+        for (channel, chain) in self.chains.iter_mut().enumerate() {
+            self.channel_block.clear();
+            self.channel_block.extend(block.iter().skip(channel).step_by(channels));
+
+            chain.process(&mut self.channel_block);
+
+            for (sample, &processed) in block.iter_mut().skip(channel).step_by(channels).zip(&self.channel_block) {
+                *sample = processed;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flushes everything still held in the chain and resets it.
+    ///
+    /// The returned interleaved samples are the `latency_samples()` frames that
+    /// `process` has not handed out yet.
+    pub fn finish(&mut self) -> Vec<f32> {
+        let tails: Vec<Vec<f32>> = self.chains.iter_mut().map(|chain| chain.flush()).collect();
+
+        let frames = tails.iter().map(Vec::len).min().unwrap_or(0);
+        let mut output = Vec::with_capacity(frames * self.channels);
+        for frame in 0..frames {
+            for tail in &tails {
+                output.push(tail[frame]);
+            }
+        }
+
+        output
+    }
+
+    /// Discards all carried state, as if the processor had just been created.
+    pub fn reset(&mut self) {
+        for chain in self.chains.iter_mut() {
+            chain.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamingProcessor;
+    use crate::AudioProcessor;
+
+    fn test_signal(len: usize) -> Vec<f32> {
+        let mut seed = 0x1234_5678u32;
+        (0..len)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = (seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5;
+                let tone = (i as f32 * 220.0 * 2.0 * std::f32::consts::PI / 48_000.0).sin();
+                let envelope = if (i / 6000) % 2 == 0 { 0.6 } else { 0.02 };
+                tone * envelope + noise * 0.01
+            })
+            .collect()
+    }
+
+    fn full_chain() -> AudioProcessor {
+        let mut processor = AudioProcessor::new(48_000.0);
+        processor.filters_enabled = true;
+        processor.spectral_gate_enabled = true;
+        processor.amplitude_gate_enabled = true;
+        processor.gain_boost_enabled = true;
+        processor.limiter_enabled = true;
+        processor.threshold_db = -10.0;
+        processor
+    }
+
+    #[test]
+    fn streamed_output_matches_batch_processing() {
+        let input = test_signal(48_000);
+        let mut processor = full_chain();
+
+        let mut expected = input.clone();
+        processor.process_samples(&mut expected, 48_000, 1).unwrap();
+
+        let mut streaming = StreamingProcessor::new(&processor, 48_000, 1).unwrap();
+        let offset = streaming.latency_samples();
+        let mut streamed = Vec::new();
+        for block in input.chunks(37) {
+            let mut block = block.to_vec();
+            streaming.process(&mut block).unwrap();
+            streamed.extend(block);
+        }
+        streamed.extend(streaming.finish());
+
+        assert_eq!(streamed.len(), offset + expected.len());
+        assert!(streamed[..offset].iter().all(|&s| s == 0.0));
+        for (a, b) in streamed[offset..].iter().zip(&expected) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn block_size_does_not_change_the_result() {
+        let input = test_signal(20_000);
+        let processor = full_chain();
+
+        let run = |block_size: usize| {
+            let mut streaming = StreamingProcessor::new(&processor, 48_000, 1).unwrap();
+            let mut output = Vec::new();
+            for block in input.chunks(block_size) {
+                let mut block = block.to_vec();
+                streaming.process(&mut block).unwrap();
+                output.extend(block);
+            }
+            output
+        };
+
+        assert_eq!(run(64), run(1000));
+    }
+
+    #[test]
+    fn latency_covers_stft_and_lookahead() {
+        let processor = full_chain();
+        let streaming = StreamingProcessor::new(&processor, 48_000, 2).unwrap();
+
+        // Two STFT stages plus 5 ms gate and 5 ms limiter lookahead at 48 kHz
+        assert_eq!(streaming.latency_samples(), 2 * 4095 + 240 + 240);
+    }
+
+    #[test]
+    fn rejects_partial_frames() {
+        let mut streaming = StreamingProcessor::new(&AudioProcessor::default(), 48_000, 2).unwrap();
+        assert!(streaming.process(&mut [0.0; 3]).is_err());
+    }
+}
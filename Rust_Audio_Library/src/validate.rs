@@ -0,0 +1,575 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use crate::error::{Result, RusticAudioError};
+use crate::opus_decoder::DecodedAudio;
+use crate::opus_packet::packet_frames;
+use crate::opus_tags::OpusTags;
+
+// Ogg page header size before the lacing table
+const PAGE_HEADER_BYTES: usize = 27;
+// Longest packet Opus allows, 120 ms at 48 kHz
+const MAX_PACKET_SAMPLES: usize = 5760;
+// OpusHead with the largest channel mapping table
+const MAX_HEAD_BYTES: usize = 21 + 255;
+
+/// Resource limits enforced when reading Ogg Opus or WAV input from untrusted sources.
+///
+/// Readers of input that may come from someone else (Opus decoding,
+/// inspection, tags, `strip_padding` and the padding of streams in memory)
+/// apply `ParseLimits::default()`; call `validate_opus` or `read_wav_bytes`
+/// with tighter limits to screen input before handing it on. Local files the
+/// caller processes, encodes, pads or plays are read with
+/// `ParseLimits::trusted()`, so long recordings keep working.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseLimits {
+    /// Largest file or stream accepted, in bytes.
+    pub max_file_bytes: u64,
+    /// Longest playable duration accepted, in seconds.
+    pub max_duration_secs: f64,
+    /// Largest Opus audio packet accepted, in bytes.
+    pub max_packet_bytes: usize,
+    /// Most Ogg pages accepted in one stream.
+    pub max_pages: usize,
+    /// Largest OpusTags packet accepted, in bytes, padding included.
+    pub max_tags_bytes: usize,
+    /// Most channels accepted in a WAV file. Opus streams are limited to two regardless.
+    pub max_channels: u16,
+    /// Lowest WAV sample rate, or OpusHead input rate other than zero, accepted in Hz.
+    pub min_sample_rate: u32,
+    /// Highest WAV sample rate, or OpusHead input rate, accepted in Hz.
+    pub max_sample_rate: u32,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_file_bytes: 64 * 1024 * 1024,
+            max_duration_secs: 600.0,
+            // 48 frames of the largest Opus frame, 1275 bytes, plus framing
+            max_packet_bytes: 61_440,
+            max_pages: 65_536,
+            max_tags_bytes: 1024 * 1024,
+            max_channels: 8,
+            min_sample_rate: 8_000,
+            max_sample_rate: 192_000,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// No bound on size, duration, page count or packet and tags size.
+    ///
+    /// The channel and sample rate checks of `default()` still apply, as
+    /// nothing outside them can be processed anyway.
+    pub fn trusted() -> Self {
+        Self {
+            max_file_bytes: u64::MAX,
+            max_duration_secs: f64::INFINITY,
+            max_packet_bytes: usize::MAX,
+            max_pages: usize::MAX,
+            max_tags_bytes: usize::MAX,
+            ..Self::default()
+        }
+    }
+}
+
+/// Fields of an OpusHead packet (RFC 7845, section 5.1).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct OpusHead {
+    pub channels: u8,
+    pub pre_skip: u16,
+    pub input_sample_rate: u32,
+    /// Q7.8 dB.
+    pub output_gain: i16,
+}
+
+impl OpusHead {
+    pub fn output_gain_db(&self) -> f32 {
+        self.output_gain as f32 / 256.0
+    }
+}
+
+/// What validation learns about a stream, for callers that go on to use it.
+pub(crate) struct StreamSummary {
+    pub head: OpusHead,
+    /// Granule position of the last page that completes a packet.
+    pub end_granule: u64,
+}
+
+/// Parses an OpusHead packet, rejecting anything this crate cannot decode.
+pub(crate) fn parse_opus_head(packet: &[u8]) -> Result<OpusHead> {
+    let invalid = |msg: &str| RusticAudioError::OggParse(msg.to_string());
+    if packet.len() < 19 || !packet.starts_with(b"OpusHead") {
+        return Err(invalid("first packet is not an OpusHead header"));
+    }
+    if packet.len() > MAX_HEAD_BYTES {
+        return Err(invalid("OpusHead packet is too long"));
+    }
+    // Minor versions are compatible; a new major version is not
+    if packet[8] >> 4 != 0 {
+        return Err(RusticAudioError::OggParse(format!("unsupported OpusHead version {}", packet[8])));
+    }
+
+    let channels = packet[9];
+    match (packet[18], channels) {
+        (_, 0) => return Err(invalid("OpusHead declares zero channels")),
+        (0, 1 | 2) => {}
+        (0, _) => return Err(RusticAudioError::OggParse(format!(
+            "channel mapping family 0 cannot carry {} channels", channels
+        ))),
+        (family, _) => return Err(RusticAudioError::OggParse(format!(
+            "channel mapping family {} is not supported", family
+        ))),
+    }
+
+    Ok(OpusHead {
+        channels,
+        pre_skip: u16::from_le_bytes([packet[10], packet[11]]),
+        input_sample_rate: u32::from_le_bytes([packet[12], packet[13], packet[14], packet[15]]),
+        output_gain: i16::from_le_bytes([packet[16], packet[17]]),
+    })
+}
+
+/// Checks that `stream` is a well-formed Ogg Opus stream within `limits`.
+///
+/// Walks every page and packet without decoding: the Ogg framing, a single
+/// logical stream with consecutive page numbers, the OpusHead and OpusTags
+/// headers, the TOC byte of every audio packet, granule positions that never
+/// go backwards, and the size, page count and duration limits. Malformed input
+/// is `RusticAudioError::OggParse`; input over a limit is
+/// `RusticAudioError::LimitExceeded`.
+pub fn validate_opus(stream: &[u8], limits: &ParseLimits) -> Result<()> {
+    check_opus(stream, limits).map(|_| ())
+}
+
+pub(crate) fn check_opus(stream: &[u8], limits: &ParseLimits) -> Result<StreamSummary> {
+    let malformed = |msg: String| RusticAudioError::OggParse(msg);
+    let exceeded = |msg: String| RusticAudioError::LimitExceeded(msg);
+
+    if stream.len() as u64 > limits.max_file_bytes {
+        return Err(exceeded(format!("stream is {} bytes, the limit is {}", stream.len(), limits.max_file_bytes)));
+    }
+
+    let mut offset = 0;
+    let mut pages = 0usize;
+    let mut serial = 0u32;
+    let mut ended = false;
+    // Packet being assembled, and how many packets came before it
+    let mut packet: Vec<u8> = Vec::new();
+    let mut in_packet = false;
+    let mut packet_index = 0usize;
+
+    let mut head = None;
+    let mut granule = 0u64;
+    let mut decoded_samples = 0u64;
+
+    while offset < stream.len() {
+        let header = stream.get(offset..offset + PAGE_HEADER_BYTES)
+            .filter(|header| header.starts_with(b"OggS"))
+            .ok_or_else(|| malformed(format!("no Ogg page at byte {}", offset)))?;
+        if ended {
+            return Err(malformed("data after the end-of-stream page".to_string()));
+        }
+        pages += 1;
+        if pages > limits.max_pages {
+            return Err(exceeded(format!("more than {} Ogg pages", limits.max_pages)));
+        }
+
+        let version = header[4];
+        let flags = header[5];
+        let page_granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        let page_serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let sequence = u32::from_le_bytes(header[18..22].try_into().unwrap());
+        let segments = header[26] as usize;
+
+        if version != 0 || flags & !0x07 != 0 {
+            return Err(malformed(format!("unsupported Ogg page header at byte {}", offset)));
+        }
+        let (continued, first, last) = (flags & 0x01 != 0, flags & 0x02 != 0, flags & 0x04 != 0);
+        if first != (pages == 1) {
+            return Err(malformed("beginning-of-stream flag on the wrong page".to_string()));
+        }
+        if pages == 1 {
+            serial = page_serial;
+        } else if page_serial != serial {
+            return Err(malformed("multiplexed Ogg streams are not supported".to_string()));
+        }
+        if sequence as usize != pages - 1 {
+            return Err(malformed(format!("Ogg page {} is out of sequence", sequence)));
+        }
+        if continued != in_packet {
+            return Err(malformed(format!("broken packet continuation at page {}", sequence)));
+        }
+        ended = last;
+
+        let lacing = stream.get(offset + PAGE_HEADER_BYTES..offset + PAGE_HEADER_BYTES + segments)
+            .ok_or_else(|| malformed("truncated Ogg page header".to_string()))?;
+        let mut body_offset = offset + PAGE_HEADER_BYTES + segments;
+        let mut completed = 0usize;
+
+        for &lace in lacing {
+            let data = stream.get(body_offset..body_offset + lace as usize)
+                .ok_or_else(|| malformed("truncated Ogg page body".to_string()))?;
+            body_offset += lace as usize;
+            packet.extend_from_slice(data);
+            in_packet = true;
+
+            let limit = match packet_index {
+                0 => MAX_HEAD_BYTES,
+                1 => limits.max_tags_bytes,
+                _ => limits.max_packet_bytes,
+            };
+            if packet.len() > limit {
+                return Err(match packet_index {
+                    0 => malformed("OpusHead packet is too long".to_string()),
+                    1 => exceeded(format!("OpusTags packet is over {} bytes", limit)),
+                    _ => exceeded(format!("audio packet is over {} bytes", limit)),
+                });
+            }
+            if lace == 255 {
+                continue;
+            }
+
+            // A lacing value under 255 ends the packet
+            match packet_index {
+                0 => head = Some(parse_opus_head(&packet)?),
+                1 => {
+                    OpusTags::from_packet(&packet)?;
+                }
+                _ => decoded_samples += audio_packet_samples(&packet)? as u64,
+            }
+            packet.clear();
+            in_packet = false;
+            packet_index += 1;
+            completed += 1;
+        }
+
+        // OpusHead sits alone on the first page, and audio starts on a fresh page
+        let headers_done = packet_index >= 2 && !in_packet;
+        if pages == 1 && (packet_index != 1 || in_packet) {
+            return Err(malformed("the first page must hold exactly the OpusHead packet".to_string()));
+        }
+        if packet_index == 2 && completed > 0 && !headers_done {
+            return Err(malformed("audio shares a page with OpusTags".to_string()));
+        }
+        if packet_index <= 2 && completed > 0 && page_granule != 0 {
+            return Err(malformed("header pages must have a zero granule position".to_string()));
+        }
+
+        // Pages on which no packet ends carry no granule position (-1)
+        if completed > 0 {
+            if page_granule < granule {
+                return Err(malformed(format!("granule position goes backwards at page {}", sequence)));
+            }
+            granule = page_granule;
+        }
+
+        offset = body_offset;
+    }
+
+    let head = head.ok_or_else(|| malformed("missing OpusHead packet".to_string()))?;
+    if packet_index < 2 {
+        return Err(malformed("missing OpusTags packet".to_string()));
+    }
+    if in_packet {
+        return Err(malformed("stream ends inside a packet".to_string()));
+    }
+    if head.input_sample_rate != 0
+        && !(limits.min_sample_rate..=limits.max_sample_rate).contains(&head.input_sample_rate)
+    {
+        return Err(malformed(format!("implausible input sample rate {} Hz", head.input_sample_rate)));
+    }
+
+    let playable = granule.max(decoded_samples).saturating_sub(head.pre_skip as u64);
+    let max_samples = (limits.max_duration_secs * 48_000.0) as u64;
+    if playable > max_samples {
+        return Err(exceeded(format!(
+            "duration of {:.1} s is over the {} s limit", playable as f64 / 48_000.0, limits.max_duration_secs
+        )));
+    }
+
+    Ok(StreamSummary { head, end_granule: granule })
+}
+
+// Checks the TOC byte of an audio packet and returns its length in 48 kHz samples
+fn audio_packet_samples(packet: &[u8]) -> Result<usize> {
+    // Empty packets are dropped frames, and older streams end with one
+    if packet.is_empty() {
+        return Ok(0);
+    }
+    match packet_frames(packet) {
+        Some((frame_samples, frames)) if frames > 0 && frame_samples * frames <= MAX_PACKET_SAMPLES => {
+            Ok(frame_samples * frames)
+        }
+        _ => Err(RusticAudioError::OggParse("invalid Opus packet TOC".to_string())),
+    }
+}
+
+/// Reads an Ogg Opus file, checking its size before reading and its contents after.
+pub(crate) fn read_opus_file(path: &str, limits: &ParseLimits) -> Result<(Vec<u8>, StreamSummary)> {
+    let size = std::fs::metadata(path)?.len();
+    if size > limits.max_file_bytes {
+        return Err(RusticAudioError::LimitExceeded(format!(
+            "{} is {} bytes, the limit is {}", path, size, limits.max_file_bytes
+        )));
+    }
+
+    let stream = std::fs::read(path)?;
+    let summary = check_opus(&stream, limits)?;
+    Ok((stream, summary))
+}
+
+/// Reads a WAV file held in memory as interleaved samples in -1.0..1.0.
+///
+/// 8, 16, 24 and 32-bit integer and 32-bit float files are accepted. The
+/// header is checked against `limits` before any samples are read, and a
+/// truncated data chunk is `RusticAudioError::WavParse`.
+pub fn read_wav_bytes(data: &[u8], limits: &ParseLimits) -> Result<DecodedAudio> {
+    if data.len() as u64 > limits.max_file_bytes {
+        return Err(RusticAudioError::LimitExceeded(format!(
+            "WAV data is {} bytes, the limit is {}", data.len(), limits.max_file_bytes
+        )));
+    }
+    let (samples, spec) = read_wav(hound::WavReader::new(Cursor::new(data))?, limits)?;
+    Ok(DecodedAudio {
+        samples,
+        channels: spec.channels,
+        sample_rate: spec.sample_rate,
+    })
+}
+
+/// Reads a WAV file as interleaved samples in -1.0..1.0; see `read_wav_bytes`.
+pub(crate) fn read_wav_file(path: &str, limits: &ParseLimits) -> Result<(Vec<f32>, hound::WavSpec)> {
+    let size = std::fs::metadata(path)?.len();
+    if size > limits.max_file_bytes {
+        return Err(RusticAudioError::LimitExceeded(format!(
+            "{} is {} bytes, the limit is {}", path, size, limits.max_file_bytes
+        )));
+    }
+    read_wav(hound::WavReader::new(BufReader::new(File::open(path)?))?, limits)
+}
+
+fn read_wav<R: Read>(mut reader: hound::WavReader<R>, limits: &ParseLimits) -> Result<(Vec<f32>, hound::WavSpec)> {
+    let spec = reader.spec();
+
+    if spec.channels == 0 || spec.channels > limits.max_channels {
+        return Err(RusticAudioError::WavParse(format!(
+            "{} channels is outside 1..={}", spec.channels, limits.max_channels
+        )));
+    }
+    if !(limits.min_sample_rate..=limits.max_sample_rate).contains(&spec.sample_rate) {
+        return Err(RusticAudioError::WavParse(format!(
+            "sample rate {} Hz is outside {}..={} Hz", spec.sample_rate, limits.min_sample_rate, limits.max_sample_rate
+        )));
+    }
+    let declared = reader.duration() as f64 / spec.sample_rate as f64;
+    if declared > limits.max_duration_secs {
+        return Err(RusticAudioError::LimitExceeded(format!(
+            "duration of {:.1} s is over the {} s limit", declared, limits.max_duration_secs
+        )));
+    }
+
+    // hound reports a short read as an `Other` I/O error
+    let truncated = |err: hound::Error| match err {
+        hound::Error::IoError(err)
+            if matches!(err.kind(), std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::Other) =>
+        {
+            RusticAudioError::WavParse("sample data is truncated".to_string())
+        }
+        other => other.into(),
+    };
+
+    let samples: Vec<f32> = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Float, 32) => reader.samples::<f32>()
+            .collect::<std::result::Result<_, _>>()
+            .map_err(truncated)?,
+        (hound::SampleFormat::Int, bits @ (8 | 16 | 24 | 32)) => {
+            let scale = 1.0 / (1u64 << (bits - 1)) as f32;
+            reader.samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<std::result::Result<_, _>>()
+                .map_err(truncated)?
+        }
+        (format, bits) => {
+            return Err(RusticAudioError::UnsupportedSampleFormat(format!("{}-bit {:?} WAV", bits, format)));
+        }
+    };
+
+    Ok((samples, spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_wav_bytes, validate_opus, ParseLimits};
+    use crate::{decode_opus_bytes, strip_padding, OpusEncoder, OpusFileInfo, OpusTags, RusticAudioError};
+    use std::io::Cursor;
+
+    fn stream(samples: usize) -> Vec<u8> {
+        let input: Vec<f32> = (0..samples).map(|i| (i as f32 * 0.03).sin() * 0.3).collect();
+        OpusEncoder::new().encode_samples(&input, 48_000).unwrap()
+    }
+
+    fn wav(spec: hound::WavSpec, frames: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut writer = hound::WavWriter::new(Cursor::new(&mut data), spec).unwrap();
+        for i in 0..frames * spec.channels as usize {
+            match spec.sample_format {
+                hound::SampleFormat::Float => writer.write_sample((i as f32 * 0.01).sin() * 0.5).unwrap(),
+                hound::SampleFormat::Int => writer.write_sample(((i % 100) as i32 - 50) << (spec.bits_per_sample - 8)).unwrap(),
+            }
+        }
+        writer.finalize().unwrap();
+        data
+    }
+
+    fn spec(channels: u16, bits: u16, format: hound::SampleFormat) -> hound::WavSpec {
+        hound::WavSpec { channels, sample_rate: 16_000, bits_per_sample: bits, sample_format: format }
+    }
+
+    #[test]
+    fn limits_are_typed_errors() {
+        let valid = stream(48_000);
+        assert!(validate_opus(&valid, &ParseLimits::default()).is_ok());
+
+        let short = ParseLimits { max_duration_secs: 0.5, ..ParseLimits::default() };
+        assert!(matches!(validate_opus(&valid, &short), Err(RusticAudioError::LimitExceeded(_))));
+        let tiny_packets = ParseLimits { max_packet_bytes: 4, ..ParseLimits::default() };
+        assert!(matches!(validate_opus(&valid, &tiny_packets), Err(RusticAudioError::LimitExceeded(_))));
+        let few_pages = ParseLimits { max_pages: 2, ..ParseLimits::default() };
+        assert!(matches!(validate_opus(&valid, &few_pages), Err(RusticAudioError::LimitExceeded(_))));
+
+        let wav_data = wav(spec(1, 16, hound::SampleFormat::Int), 16_000);
+        assert!(matches!(read_wav_bytes(&wav_data, &short), Err(RusticAudioError::LimitExceeded(_))));
+        let mono_only = ParseLimits { max_channels: 1, ..ParseLimits::default() };
+        let stereo = wav(spec(2, 16, hound::SampleFormat::Int), 100);
+        assert!(matches!(read_wav_bytes(&stereo, &mono_only), Err(RusticAudioError::WavParse(_))));
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let valid = stream(9_600);
+        let limits = ParseLimits::default();
+        // OpusHead starts after the 27-byte page header and one lacing byte
+        let head = 28;
+
+        let mut bad_version = valid.clone();
+        bad_version[head + 8] = 0x10;
+        let mut no_channels = valid.clone();
+        no_channels[head + 9] = 0;
+        let mut multistream = valid.clone();
+        multistream[head + 18] = 1;
+        for corrupt in [bad_version, no_channels, multistream] {
+            assert!(matches!(validate_opus(&corrupt, &limits), Err(RusticAudioError::OggParse(_))));
+        }
+
+        // OpusTags follows on the second page; give its vendor string an impossible length
+        let tags = head + 19 + 28;
+        let mut bad_tags = valid.clone();
+        assert_eq!(&bad_tags[tags..tags + 8], b"OpusTags");
+        bad_tags[tags + 8..tags + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(validate_opus(&bad_tags, &limits), Err(RusticAudioError::OggParse(_))));
+
+        let truncated = &valid[..valid.len() - 10];
+        assert!(matches!(validate_opus(truncated, &limits), Err(RusticAudioError::OggParse(_))));
+        assert!(OpusFileInfo::from_stream(truncated).is_err());
+    }
+
+    #[test]
+    fn wav_bit_depths_and_truncation() {
+        for (bits, format) in [(8, hound::SampleFormat::Int), (16, hound::SampleFormat::Int),
+                               (24, hound::SampleFormat::Int), (32, hound::SampleFormat::Int),
+                               (32, hound::SampleFormat::Float)] {
+            let data = wav(spec(2, bits, format), 500);
+            let audio = read_wav_bytes(&data, &ParseLimits::default()).unwrap();
+            assert_eq!((audio.channels, audio.sample_rate, audio.samples.len()), (2, 16_000, 1_000));
+            assert!(audio.samples.iter().all(|s| (-1.0..=1.0).contains(s)));
+            assert!(audio.samples.iter().any(|&s| s != 0.0));
+
+            let cut = &data[..data.len() - 7];
+            assert!(matches!(read_wav_bytes(cut, &ParseLimits::default()), Err(RusticAudioError::WavParse(_))));
+        }
+    }
+
+    // Rewrites the CRC of every Ogg page that still parses, so mutated packets
+    // get past the page checks and reach the Opus decoder
+    fn fix_page_crcs(data: &mut [u8]) {
+        let mut page = 0;
+        while page + 27 <= data.len() && &data[page..page + 4] == b"OggS" {
+            let segments = data[page + 26] as usize;
+            let header = page + 27 + segments;
+            if header > data.len() {
+                return;
+            }
+            let end = header + data[page + 27..header].iter().map(|&len| len as usize).sum::<usize>();
+            if end > data.len() {
+                return;
+            }
+
+            data[page + 22..page + 26].fill(0);
+            // CRC-32 with polynomial 0x04c11db7, unreflected and starting from zero
+            let crc = data[page..end].iter().fold(0u32, |mut crc, &byte| {
+                crc ^= (byte as u32) << 24;
+                for _ in 0..8 {
+                    crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+                }
+                crc
+            });
+            data[page + 22..page + 26].copy_from_slice(&crc.to_le_bytes());
+            page = end;
+        }
+    }
+
+    // Deterministic mutation of valid input; every reader must return, never panic
+    #[test]
+    fn mutated_input_never_panics() {
+        let limits = ParseLimits::default();
+        let opus_seed = stream(4_800);
+        let wav_seed = wav(spec(1, 16, hound::SampleFormat::Int), 400);
+
+        let mut state = 0x9e37_79b9u32;
+        let mut next = move |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as usize % bound.max(1)
+        };
+
+        let mut decoded = 0;
+        for round in 0..3_000 {
+            let seed = if round % 2 == 0 { &opus_seed } else { &wav_seed };
+            let mut data = seed.clone();
+            match next(4) {
+                0 => data.truncate(next(data.len())),
+                1 => {
+                    for _ in 0..1 + next(8) {
+                        let at = next(data.len());
+                        data[at] = next(256) as u8;
+                    }
+                }
+                2 => {
+                    let at = next(data.len());
+                    data.splice(at..at, (0..next(300)).map(|_| next(256) as u8));
+                }
+                _ => {
+                    let at = next(data.len());
+                    data[at] ^= 1 << next(8);
+                }
+            }
+
+            if round % 2 == 0 {
+                fix_page_crcs(&mut data);
+            }
+
+            if validate_opus(&data, &limits).is_ok() {
+                decoded += 1;
+                let _ = decode_opus_bytes(&data);
+            }
+            let _ = OpusFileInfo::from_stream(&data);
+            let _ = OpusTags::from_stream(&data);
+            let _ = strip_padding(&data);
+            let _ = read_wav_bytes(&data, &limits);
+        }
+        // Corrupted TOC bytes and frame lengths, not just bad CRCs, reach the decoder
+        assert!(decoded >= 300, "{} mutated streams decoded", decoded);
+    }
+}